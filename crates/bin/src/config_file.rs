@@ -0,0 +1,156 @@
+use std::{collections::BTreeMap, ffi::OsString, path::PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{Arg, ArgAction, ArgMatches, Command, Parser, parser::ValueSource};
+
+/// Parse `P` from `argv`, filling in options that are neither passed as flags nor set through the environment
+/// from the YAML file given with `--config`.
+///
+/// Keys of the file are the long flag names without the leading dashes, such as `interval` or `dry-run`.
+/// Lists are passed as repeated flags, and boolean flags are set by `true`.
+pub fn parse_with_config_file<P: Parser>(argv: Vec<OsString>) -> Result<P> {
+    // Errors such as missing required flags may only be resolved by the file, so they are reported by the final parse
+    let Ok(matches) = P::command().ignore_errors(true).try_get_matches_from(&argv) else {
+        return Ok(P::parse_from(argv));
+    };
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return Ok(P::parse_from(argv));
+    };
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file {}", path.display()))?;
+    let file_args = config_file_args(&P::command(), &matches, &content)
+        .with_context(|| format!("invalid config file {}", path.display()))?;
+
+    let mut argv = argv;
+    argv.splice(1..1, file_args);
+    Ok(P::parse_from(argv))
+}
+
+/// Translate the keys of a config file into flags, skipping those given on the command line or in the environment
+fn config_file_args(cmd: &Command, matches: &ArgMatches, content: &str) -> Result<Vec<OsString>> {
+    let values: BTreeMap<String, serde_yaml::Value> = match serde_yaml::from_str(content)? {
+        // An empty file
+        None => return Ok(vec![]),
+        Some(values) => values,
+    };
+    let mut args = vec![];
+    for (key, value) in values {
+        let arg = cmd
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()) && arg.get_id() != "config")
+            .ok_or_else(|| anyhow!("unknown key `{key}`"))?;
+        if matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        args.extend(
+            flag_values(cmd, arg, &key, value)
+                .with_context(|| format!("failed to parse key `{key}`"))?,
+        );
+    }
+    Ok(args)
+}
+
+fn flag_values(
+    cmd: &Command,
+    arg: &Arg,
+    key: &str,
+    value: serde_yaml::Value,
+) -> Result<Vec<OsString>> {
+    if matches!(arg.get_action(), ArgAction::SetTrue) {
+        return match value {
+            serde_yaml::Value::Bool(true) => Ok(vec![format!("--{key}").into()]),
+            serde_yaml::Value::Bool(false) => Ok(vec![]),
+            _ => bail!("expected true or false"),
+        };
+    }
+    let values = match value {
+        serde_yaml::Value::Sequence(values) => values,
+        value => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| {
+            let value = match value {
+                serde_yaml::Value::String(value) => value,
+                serde_yaml::Value::Number(value) => value.to_string(),
+                serde_yaml::Value::Bool(value) => value.to_string(),
+                _ => bail!("expected a string, number, boolean or a list of them"),
+            };
+            // Only the value parser of the flag is checked here, relations between flags are validated by the final parse
+            Command::new(cmd.get_name().to_string())
+                .no_binary_name(true)
+                .arg(
+                    Arg::new(arg.get_id().clone())
+                        .long(key.to_string())
+                        .value_parser(arg.get_value_parser().clone()),
+                )
+                .try_get_matches_from([format!("--{key}={value}")])
+                .map_err(|e| {
+                    // Only keep the first line, without the leading "error: " and the hint to --help
+                    let rendered = e.render().to_string();
+                    let message = rendered.lines().next().unwrap_or_default();
+                    anyhow!("{}", message.trim_start_matches("error: "))
+                })?;
+            Ok(format!("--{key}={value}").into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Parser, Debug)]
+    struct TestArgs {
+        #[arg(long)]
+        config: Option<PathBuf>,
+        #[arg(long, default_value_t = 60)]
+        interval: u32,
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+        #[arg(long = "namespace", value_delimiter = ',')]
+        namespaces: Vec<String>,
+    }
+
+    fn args_with_file(name: &str, argv: &[&str], content: &str) -> Result<TestArgs> {
+        let path = std::env::temp_dir().join(format!("externalip-manager-config-{name}.yaml"));
+        std::fs::write(&path, content).unwrap();
+        let mut argv = argv.iter().map(OsString::from).collect::<Vec<_>>();
+        argv.extend(["--config".into(), path.into()]);
+        parse_with_config_file(argv)
+    }
+
+    #[test]
+    fn flags_override_file_values() {
+        let content = "interval: 30\ndry-run: true\nnamespace: [a, b]\n";
+        let args = args_with_file("valid", &["test"], content).unwrap();
+        assert_eq!(args.interval, 30);
+        assert!(args.dry_run);
+        assert_eq!(args.namespaces, vec!["a", "b"]);
+
+        let args = args_with_file(
+            "override",
+            &["test", "--interval", "10", "--namespace", "c"],
+            content,
+        )
+        .unwrap();
+        assert_eq!(args.interval, 10);
+        assert_eq!(args.namespaces, vec!["c"]);
+    }
+
+    #[test]
+    fn reports_invalid_keys() {
+        for (content, key) in [
+            ("interval: soon\n", "interval"),
+            ("dry-run: yes please\n", "dry-run"),
+            ("intervall: 30\n", "intervall"),
+            ("config: other.yaml\n", "config"),
+        ] {
+            let err = format!("{:#}", args_with_file(key, &["test"], content).unwrap_err());
+            assert!(err.contains(&format!("`{key}`")), "{err}");
+        }
+    }
+}
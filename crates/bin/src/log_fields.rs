@@ -0,0 +1,115 @@
+use serde_json::{Map, Value};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::{
+    fmt::{FmtContext, FormatEvent, FormatFields, format::Writer},
+    registry::LookupSpan,
+};
+
+/// Build information added to every log line, so that logs can be correlated with releases
+pub fn build_info_fields() -> Vec<(&'static str, &'static str)> {
+    let mut fields = vec![("version", env!("CARGO_PKG_VERSION"))];
+    // Set at build time, such as by CI
+    if let Some(git_sha) = option_env!("EXTERNALIP_MANAGER_GIT_SHA") {
+        fields.push(("git_sha", git_sha));
+    }
+    fields
+}
+
+/// Adds constant fields to every event formatted by `inner`, which has to format events as JSON objects.
+///
+/// Each formatted event is read back as a JSON object and the fields are added as members of it, never replacing
+/// members of the event itself. Events that aren't JSON objects are written out unchanged.
+pub struct WithStaticFields<F> {
+    inner: F,
+    fields: Map<String, Value>,
+}
+
+impl<F> WithStaticFields<F> {
+    pub fn new(inner: F, fields: &[(&str, &str)]) -> WithStaticFields<F> {
+        let fields = fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), Value::from(*value)))
+            .collect();
+        WithStaticFields { inner, fields }
+    }
+}
+
+impl<S, N, F> FormatEvent<S, N> for WithStaticFields<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let mut line = String::new();
+        self.inner
+            .format_event(ctx, Writer::new(&mut line), event)?;
+        let Ok(mut object) = serde_json::from_str::<Map<String, Value>>(&line) else {
+            return writer.write_str(&line);
+        };
+        for (key, value) in &self.fields {
+            object.entry(key.as_str()).or_insert_with(|| value.clone());
+        }
+        writeln!(writer, "{}", Value::Object(object))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::info;
+    use tracing_subscriber::{fmt, prelude::*};
+
+    use super::*;
+
+    #[test]
+    fn adds_fields_to_json_lines() {
+        let out = Arc::new(Mutex::new(vec![]));
+        let layer = fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_target(false)
+            .without_time()
+            .with_writer({
+                let out = out.clone();
+                move || SharedBuf(out.clone())
+            })
+            // Members of the event itself take precedence
+            .map_event_format(|format| {
+                WithStaticFields::new(format, &[("version", "1.2.3"), ("level", "ignored")])
+            });
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::info_span!("reconcile", svc = "web").in_scope(|| info!(msg = "reconciled"));
+        });
+
+        let line: serde_json::Value = serde_json::from_slice(&out.lock().unwrap()).unwrap();
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "version": "1.2.3",
+                "level": "INFO",
+                "fields": {"msg": "reconciled"},
+                "span": {"name": "reconcile", "svc": "web"}
+            })
+        );
+    }
+
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -1,35 +1,376 @@
-use std::time::Duration;
+use std::{
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Result, anyhow};
+use clap::{Parser, Subcommand, ValueEnum};
+use kube::{
+    Client, Config,
+    config::{KubeConfigOptions, Kubeconfig},
+};
+use tokio::{sync::Notify, time::Instant};
 
 use tracing::{error, info, warn};
-use tracing_subscriber::{EnvFilter, fmt, prelude::*};
+use tracing_subscriber::{EnvFilter, fmt, fmt::writer::BoxMakeWriter, prelude::*};
 
-use externalip_manager_manager::{Manager, ManagerConfig};
+use externalip_manager_manager::{
+    DEFAULT_ANNOTATION_PREFIX, Manager, ManagerConfig, PublishSelfConfig, ResolverProtocol,
+    SelfTarget, SharedDebugState, TcpProbe, crd, parse_annotation_prefix, parse_self_target,
+    serve_debug_state, serve_metrics, watch_ip_sources,
+};
+use log_fields::{WithStaticFields, build_info_fields};
+use schedule::IntervalSchedule;
+
+mod config_file;
+mod log_fields;
+mod schedule;
+
+/// Shortest time to wait between retries of pending services
+const MIN_PENDING_RETRY_DELAY: Duration = Duration::from_secs(1);
 
 #[derive(Parser, Debug)]
 #[command(version, about)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// YAML file with further options, keyed by their flag names such as `interval` or `dry-run`.
+    /// Flags and environment variables take precedence over values in the file
+    #[arg(long, env = "EXTERNALIP_MANAGER_CONFIG")]
+    config: Option<PathBuf>,
     /// Seconds between reconciliation runs, in seconds
     #[arg(short, long, env = "EXTERNALIP_MANAGER_INTERVAL", default_value_t = 60)]
     interval: u32,
+    /// Intervals depending on the time of day in UTC, such as "09:00-17:00=30,17:00-09:00=600". Falls back to --interval outside of all windows
+    #[arg(long, env = "EXTERNALIP_MANAGER_INTERVAL_SCHEDULE")]
+    interval_schedule: Option<IntervalSchedule>,
+    /// Seconds after which services referencing a not-yet-existing ClusterExternalIPSource are retried
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_UNKNOWN_SOURCE_RETRY",
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    unknown_source_retry: u32,
+    /// Seconds after which services whose solvers timed out are retried, instead of waiting for the next run
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_SOLVER_TIMEOUT_RETRY",
+        default_value_t = 15,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    solver_timeout_retry: u32,
+    /// Seconds for which a service keeps its addresses after its ClusterExternalIPSource disappeared, after which they are removed. Addresses are kept indefinitely if unset
+    #[arg(long, env = "EXTERNALIP_MANAGER_SOURCE_MISSING_GRACE")]
+    source_missing_grace: Option<u32>,
+    /// Maximum seconds to spend on a single service before moving on to the next one. Should be larger than the solver timeout of 5s
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_SERVICE_RECONCILE_TIMEOUT",
+        default_value_t = 30
+    )]
+    service_reconcile_timeout: u32,
+    /// Maximum seconds for an entire reconciliation run. Longer runs stop at the deadline, and the next run starts with the remaining services. Unbounded if unset
+    #[arg(long, env = "EXTERNALIP_MANAGER_RECONCILE_RUN_TIMEOUT")]
+    reconcile_run_timeout: Option<u32>,
+    /// Seconds to wait for a connection to an IP API to be established. Requests to IP APIs time out after 10s in total regardless
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_HTTP_CONNECT_TIMEOUT",
+        default_value_t = 3
+    )]
+    http_connect_timeout: u32,
+    /// Seconds after which idle connections to IP APIs are closed
+    #[arg(long, env = "EXTERNALIP_MANAGER_HTTP_POOL_IDLE_TIMEOUT")]
+    http_pool_idle_timeout: Option<u32>,
+    /// Maximum number of redirects IP APIs may issue. Set to 0 to refuse all redirects
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_HTTP_MAX_REDIRECTS",
+        default_value_t = 3
+    )]
+    http_max_redirects: usize,
+    /// Refuse redirects of IP APIs to private, loopback or link-local addresses
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_HTTP_DENY_PRIVATE_REDIRECTS",
+        default_value_t = false
+    )]
+    http_deny_private_redirects: bool,
+    /// Maximum number of queries to IP APIs and name servers in flight at once, across all services
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_MAX_OUTBOUND_QUERIES",
+        default_value_t = NonZeroUsize::new(16).unwrap()
+    )]
+    max_outbound_queries: NonZeroUsize,
+    /// Send all requests to IP APIs through this SOCKS5 proxy, such as socks5h://proxy:1080. DNS queries bypass the proxy
+    #[arg(long, env = "EXTERNALIP_MANAGER_SOCKS_PROXY")]
+    socks_proxy: Option<String>,
+    /// Only manage services in these comma-separated namespaces, for namespaced RBAC. All namespaces if unset
+    #[arg(
+        long = "namespace",
+        env = "EXTERNALIP_MANAGER_NAMESPACES",
+        value_delimiter = ','
+    )]
+    namespaces: Vec<String>,
+    /// Skip services of type LoadBalancer unless they have the manage-load-balancer annotation set to "true"
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_SKIP_LOADBALANCER_SERVICES",
+        default_value_t = false
+    )]
+    skip_loadbalancer_services: bool,
+    /// Don't write resolved addresses that are also ClusterIPs of the service. Collisions are always reported
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_REJECT_SELF_COLLISION",
+        default_value_t = false
+    )]
+    reject_self_collision: bool,
+    /// Only write resolved addresses that accept TCP connections on --verify-reachable-port, skipping unreachable ones with a warning event
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_VERIFY_REACHABLE",
+        default_value_t = false
+    )]
+    verify_reachable: bool,
+    /// Port to connect to when checking whether resolved addresses are reachable
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_VERIFY_REACHABLE_PORT",
+        default_value_t = 443
+    )]
+    verify_reachable_port: u16,
+    /// Seconds to wait for the connection when checking whether resolved addresses are reachable
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_VERIFY_REACHABLE_TIMEOUT",
+        default_value_t = 2
+    )]
+    verify_reachable_timeout: u32,
+    /// Publish an AddressCountDropped warning event if the resolved addresses of a service shrink by at least this many since its last update. Disabled if unset
+    #[arg(long, env = "EXTERNALIP_MANAGER_ADDRESS_DROP_THRESHOLD")]
+    address_drop_threshold: Option<NonZeroUsize>,
+    /// Protocol for DNS lookups of dnsHostname solvers. `https` requires --doh-server and --doh-server-name
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_RESOLVER_PROTOCOL",
+        value_enum,
+        default_value_t = ResolverProtocolArg::Udp
+    )]
+    resolver_protocol: ResolverProtocolArg,
+    /// Address of the DNS-over-HTTPS server to use with --resolver-protocol https
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_DOH_SERVER",
+        required_if_eq("resolver_protocol", "https")
+    )]
+    doh_server: Option<IpAddr>,
+    /// Name in the certificate of the DNS-over-HTTPS server, such as cloudflare-dns.com
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_DOH_SERVER_NAME",
+        required_if_eq("resolver_protocol", "https")
+    )]
+    doh_server_name: Option<String>,
     /// Show what actions would be performed without actually modifying any services
     #[arg(long, env = "EXTERNALIP_MANAGER_DRY_RUN", default_value_t = false)]
     dry_run: bool,
+    /// Never modify services, but compare the resolved addresses with the live ones and count differences in the externalip_canary_diff_total metric. Useful for a canary instance next to the primary one
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_COMPARE_WITH_LIVE",
+        default_value_t = false
+    )]
+    compare_with_live: bool,
+    /// Allow clearing the externalIPs of a service if its source resolves no addresses
+    #[arg(long, env = "EXTERNALIP_MANAGER_ALLOW_EMPTY", default_value_t = false)]
+    allow_empty: bool,
+    /// Keep externalIPs that were added manually or by other controllers, only replacing those managed by externalip-manager
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_MERGE_WITH_EXISTING",
+        default_value_t = false
+    )]
+    merge_with_existing: bool,
+    /// Publish addresses as a comma-separated list in this service annotation instead of spec.externalIPs
+    #[arg(long, env = "EXTERNALIP_MANAGER_TARGET_ANNOTATION")]
+    target_annotation: Option<String>,
+    /// Also publish the addresses of --publish-self-source on this object, either node/<name> or lease/<namespace>/<name>
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_PUBLISH_SELF",
+        value_parser = parse_self_target,
+        requires = "publish_self_source"
+    )]
+    publish_self: Option<SelfTarget>,
+    /// Name of the ClusterExternalIPSource whose addresses are published with --publish-self
+    #[arg(long, env = "EXTERNALIP_MANAGER_PUBLISH_SELF_SOURCE")]
+    publish_self_source: Option<String>,
+    /// Only publish addresses with --publish-self and don't manage any services
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_PUBLISH_SELF_ONLY",
+        default_value_t = false,
+        requires = "publish_self"
+    )]
+    publish_self_only: bool,
+    /// Prefix of all annotations read and written by the manager. Allows running multiple managers side by side
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_ANNOTATION_PREFIX",
+        default_value = DEFAULT_ANNOTATION_PREFIX,
+        value_parser = parse_annotation_prefix
+    )]
+    annotation_prefix: String,
+    /// Log every service with an externalip-manager annotation on each run, instead of only their total count
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_VERBOSE_SVC_LOGGING",
+        default_value_t = false
+    )]
+    verbose_svc_logging: bool,
+    /// Annotate each ClusterExternalIPSource with the services referencing it. Requires permissions to patch clusterexternalipsources
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_ANNOTATE_SOURCE_REFERENCES",
+        default_value_t = false
+    )]
+    annotate_source_references: bool,
+    /// Exit with an error at startup if no ClusterExternalIPSources exist or they can't be listed
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_REQUIRE_SOURCES",
+        default_value_t = false
+    )]
+    require_sources: bool,
+    /// Don't watch ClusterExternalIPSources for changes. Changes are then only picked up on the next regular run
+    #[arg(
+        long,
+        env = "EXTERNALIP_MANAGER_NO_SOURCE_WATCH",
+        default_value_t = false
+    )]
+    no_source_watch: bool,
+    /// Address to serve Prometheus metrics on under /metrics, such as 0.0.0.0:9090. Metrics are not served if unset
+    #[arg(long, env = "EXTERNALIP_MANAGER_METRICS_ADDRESS")]
+    metrics_address: Option<SocketAddr>,
+    /// Address to serve the in-memory state of the manager on under /debug/state as JSON, such as 127.0.0.1:9091.
+    /// The state includes the configuration of all solvers, so only bind it to addresses reachable by operators. Not served if unset
+    #[arg(long, env = "EXTERNALIP_MANAGER_DEBUG_ADDRESS")]
+    debug_address: Option<SocketAddr>,
+    /// Run a single reconciliation and exit
+    #[arg(long, env = "EXTERNALIP_MANAGER_ONCE", default_value_t = false)]
+    once: bool,
+    /// Only reconcile the given service, formatted as namespace/name, with detailed solver output. Implies --once
+    #[arg(long, value_parser = parse_svc_ref)]
+    only_service: Option<(String, String)>,
+    /// Print the ClusterExternalIPSource CRD as YAML and exit, such as for piping into `kubectl apply -f -`
+    #[arg(long, default_value_t = false)]
+    dump_crd: bool,
+    /// Create or update the ClusterExternalIPSource CRD on startup. Requires permissions to get, create and patch customresourcedefinitions
+    #[arg(long, env = "EXTERNALIP_MANAGER_INSTALL_CRD", default_value_t = false)]
+    install_crd: bool,
+    /// Directory to persist solver results in, such as responses of IP APIs, so that they are reused after a restart
+    #[arg(long, env = "EXTERNALIP_MANAGER_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+    /// Minimum seconds solvers cache their results for, such as responses of IP APIs, name servers and sockets.
+    /// Only raises shorter cache durations, it never lowers longer ones
+    #[arg(long, env = "EXTERNALIP_MANAGER_MIN_CACHE_TTL")]
+    min_cache_ttl: Option<u32>,
+    /// Path to a kubeconfig file to use instead of the in-cluster or default configuration
+    #[arg(long)]
+    kubeconfig: Option<PathBuf>,
+    /// Kubeconfig context to use instead of the current one
+    #[arg(long)]
+    context: Option<String>,
+    /// Name of this instance in the events it publishes, to tell replicas apart. Defaults to the pod name or $HOSTNAME
+    #[arg(long, env = "EXTERNALIP_MANAGER_REPORTER_INSTANCE")]
+    reporter_instance: Option<String>,
+    /// Name of the Pod the manager runs in, used to publish reconcile summary events together with --pod-namespace. Usually set through the downward API
+    #[arg(long, env = "POD_NAME")]
+    pod_name: Option<String>,
+    /// Namespace of the Pod the manager runs in. Usually set through the downward API
+    #[arg(long, env = "POD_NAMESPACE")]
+    pod_namespace: Option<String>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum ResolverProtocolArg {
+    Udp,
+    Tcp,
+    Https,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Resolve the addresses of all annotated services and print them as JSON, without modifying any services
+    Report,
+}
+
+fn parse_svc_ref(svc_ref: &str) -> Result<(String, String), String> {
+    svc_ref
+        .split_once('/')
+        .filter(|(namespace, name)| !namespace.is_empty() && !name.is_empty())
+        .map(|(namespace, name)| (namespace.to_string(), name.to_string()))
+        .ok_or_else(|| format!("expected a service in the format namespace/name, got {svc_ref}"))
+}
+
+/// Build a kube client from the given kubeconfig and context, falling back to the default configuration if neither is set
+async fn build_client(kubeconfig: Option<PathBuf>, context: Option<String>) -> Result<Client> {
+    if kubeconfig.is_none() && context.is_none() {
+        return Ok(Client::try_default().await?);
+    }
+    let kubeconfig = match kubeconfig {
+        Some(path) => Kubeconfig::read_from(path)?,
+        None => Kubeconfig::read()?,
+    };
+    let config = Config::from_custom_kubeconfig(
+        kubeconfig,
+        &KubeConfigOptions {
+            context,
+            ..Default::default()
+        },
+    )
+    .await?;
+    Ok(Client::try_from(config)?)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let args: Args = config_file::parse_with_config_file(std::env::args_os().collect())?;
+    if args.dump_crd {
+        let yaml = crd::cluster_external_ip_source_crd_yaml(crd::STORAGE_VERSION);
+        std::io::stdout().write_all(yaml.as_bytes())?;
+        return Ok(());
+    }
 
-    let filter_layer = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    // Show per-solver output when debugging a single service
+    let default_level = if args.only_service.is_some() {
+        "debug"
+    } else {
+        "info"
+    };
+    let filter_layer =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level));
+    // Keep stdout free for the report
+    let log_writer = match args.command {
+        Some(Command::Report) => BoxMakeWriter::new(std::io::stderr),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
     let fmt_layer = fmt::layer()
+        .with_writer(log_writer)
         .json()
         .with_level(true)
-        .with_current_span(false)
+        // Attach the fields of the current span, such as the service being reconciled, to every log line
+        .with_current_span(true)
         .with_span_list(false)
-        .with_target(false);
+        .with_target(false)
+        .map_event_format(|format| WithStaticFields::new(format, &build_info_fields()));
     tracing_subscriber::registry()
         .with(filter_layer)
         .with(fmt_layer)
@@ -38,11 +379,135 @@ async fn main() -> Result<()> {
     if args.dry_run {
         warn!(msg = "Running in dry-run mode, no changes will be made");
     }
+    if args.compare_with_live {
+        warn!(msg = "Comparing resolved addresses with the live ones, no changes will be made");
+    }
 
+    let resolver_protocol = match args.resolver_protocol {
+        ResolverProtocolArg::Udp => ResolverProtocol::Udp,
+        ResolverProtocolArg::Tcp => ResolverProtocol::Tcp,
+        ResolverProtocolArg::Https => ResolverProtocol::Https {
+            server: args.doh_server.expect("enforced by clap"),
+            server_name: args.doh_server_name.clone().expect("enforced by clap"),
+        },
+    };
+    // The pod name alone still names this instance in events, only the summary events need the namespace as well
+    let pod = match (args.pod_name.clone(), args.pod_namespace.clone()) {
+        (Some(name), Some(namespace)) => Some((name, namespace)),
+        (Some(name), None) => {
+            error!(
+                msg = "pod namespace is not set, reconcile summaries will not be published. Set --pod-namespace or POD_NAMESPACE through the downward API",
+                pod = name
+            );
+            None
+        }
+        (None, _) => None,
+    };
     let cfg = ManagerConfig {
         dry_run: args.dry_run,
+        compare_with_live: args.compare_with_live,
+        allow_empty: args.allow_empty,
+        merge_with_existing: args.merge_with_existing,
+        unknown_source_retry: Duration::from_secs(args.unknown_source_retry.into()),
+        solver_timeout_retry: Duration::from_secs(args.solver_timeout_retry.into()),
+        source_missing_grace: args
+            .source_missing_grace
+            .map(|secs| Duration::from_secs(secs.into())),
+        service_reconcile_timeout: Some(Duration::from_secs(args.service_reconcile_timeout.into())),
+        reconcile_run_timeout: args
+            .reconcile_run_timeout
+            .map(|secs| Duration::from_secs(secs.into())),
+        namespaces: args.namespaces.clone(),
+        skip_loadbalancer_services: args.skip_loadbalancer_services,
+        reject_self_collision: args.reject_self_collision,
+        verify_reachable: args.verify_reachable.then(|| TcpProbe {
+            port: args.verify_reachable_port,
+            timeout: Duration::from_secs(args.verify_reachable_timeout.into()),
+        }),
+        address_drop_threshold: args.address_drop_threshold,
+        verbose_svc_logging: args.verbose_svc_logging,
+        annotate_source_references: args.annotate_source_references,
+        require_sources: args.require_sources,
+        target_annotation: args.target_annotation.clone(),
+        annotation_prefix: Some(args.annotation_prefix.clone()),
+        http_connect_timeout: Some(Duration::from_secs(args.http_connect_timeout.into())),
+        http_pool_idle_timeout: args
+            .http_pool_idle_timeout
+            .map(|secs| Duration::from_secs(secs.into())),
+        http_max_redirects: args.http_max_redirects,
+        http_deny_private_redirects: args.http_deny_private_redirects,
+        socks_proxy: args.socks_proxy.clone(),
+        max_outbound_queries: Some(args.max_outbound_queries),
+        resolver_protocol,
+        cache_dir: args.cache_dir.clone(),
+        min_cache_ttl: args
+            .min_cache_ttl
+            .map(|secs| Duration::from_secs(secs.into())),
+        publish_self: args
+            .publish_self
+            .clone()
+            .zip(args.publish_self_source.clone())
+            .map(|(target, source)| PublishSelfConfig {
+                target,
+                source,
+                only: args.publish_self_only,
+            }),
+        reporter_instance: args
+            .reporter_instance
+            .clone()
+            .or_else(|| args.pod_name.clone())
+            .or_else(|| std::env::var("HOSTNAME").ok()),
+        pod,
     };
-    let mut manager = Manager::new(cfg).await?;
+    let client = build_client(args.kubeconfig.clone(), args.context.clone()).await?;
+    if args.install_crd {
+        crd::install_crds(client.clone()).await.map_err(|e| match e {
+            kube::Error::Api(status) if status.code == 403 => anyhow!(
+                "not allowed to install CRDs, grant the manager get, create and patch permissions on customresourcedefinitions or run without --install-crd: {status}"
+            ),
+            e => anyhow!("failed to install CRDs: {e}"),
+        })?;
+    }
+    let mut manager = Manager::new(cfg, client.clone()).await?;
+
+    if let Some(Command::Report) = args.command {
+        let report = manager.report().await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    if let Some((namespace, name)) = &args.only_service {
+        manager.reconcile_named_svc(namespace, name).await?;
+        info!(msg = "Completed reconciliation of service", namespace, name);
+        return Ok(());
+    }
+
+    // Reconcile early when a source changes instead of waiting for the next interval.
+    // Every run is a full resync of all services, so events missed by the watch are corrected on the next regular run
+    let source_changes = Arc::new(Notify::new());
+    if !args.once && !args.no_source_watch {
+        tokio::spawn(watch_ip_sources(client, source_changes.clone()));
+    }
+    if let Some(addr) = args.metrics_address
+        && !args.once
+    {
+        let metrics = manager.metrics();
+        tokio::spawn(async move {
+            if let Err(e) = serve_metrics(addr, metrics).await {
+                error!(msg = "failed to serve metrics", err = ?e);
+            }
+        });
+    }
+    let debug_state = args.debug_address.filter(|_| !args.once).map(|addr| {
+        let state = Arc::new(SharedDebugState::default());
+        let served = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_debug_state(addr, served).await {
+                error!(msg = "failed to serve debug state", err = ?e);
+            }
+        });
+        state
+    });
 
     loop {
         match manager.reconcile_svcs().await {
@@ -59,6 +524,55 @@ async fn main() -> Result<()> {
                 info!(msg = "Completed reconciliation");
             }
         };
-        tokio::time::sleep(Duration::from_secs(args.interval.into())).await;
+        if let Some(debug_state) = &debug_state {
+            debug_state.set(manager.debug_state().await);
+        }
+        if args.once {
+            return Ok(());
+        }
+
+        let interval = args
+            .interval_schedule
+            .as_ref()
+            .and_then(IntervalSchedule::current_interval)
+            .unwrap_or(Duration::from_secs(args.interval.into()));
+        let next_run = Instant::now() + interval;
+        let mut source_changed = false;
+        // Retry services waiting for their source or a timed out solver more often than the regular interval
+        while let Some(retry_delay) = manager
+            .pending_retry_delay()
+            // never spin on retries, whatever delay the services were scheduled with
+            .map(|delay| delay.max(MIN_PENDING_RETRY_DELAY))
+            .filter(|delay| Instant::now() + *delay < next_run)
+        {
+            tokio::select! {
+                _ = tokio::time::sleep(retry_delay) => (),
+                _ = source_changes.notified() => {
+                    source_changed = true;
+                    break;
+                }
+            }
+            match manager.reconcile_pending_svcs().await {
+                Ok(errs) if !errs.is_empty() => {
+                    warn!(msg = "Errors encountered when retrying pending services", errs = ?errs);
+                }
+                Err(e) => {
+                    error!(msg = "Failed to retry pending services", err = ?e);
+                }
+                Ok(_) => (),
+            }
+            if let Some(debug_state) = &debug_state {
+                debug_state.set(manager.debug_state().await);
+            }
+        }
+        if !source_changed {
+            tokio::select! {
+                _ = tokio::time::sleep_until(next_run) => (),
+                _ = source_changes.notified() => source_changed = true,
+            }
+        }
+        if source_changed {
+            info!(msg = "ClusterExternalIPSource changed, reconciling early");
+        }
     }
 }
@@ -0,0 +1,142 @@
+use std::{
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Reconcile intervals that depend on the time of day, such as `09:00-17:00=30,17:00-09:00=600`.
+///
+/// Times are in UTC. Windows may wrap around midnight. If windows overlap, the first matching one is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntervalSchedule {
+    windows: Vec<Window>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Window {
+    /// Minutes since midnight, inclusive
+    start: u32,
+    /// Minutes since midnight, exclusive
+    end: u32,
+    interval: Duration,
+}
+
+impl Window {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start < self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl IntervalSchedule {
+    /// The interval applicable at the current wall-clock time, if any window matches
+    pub fn current_interval(&self) -> Option<Duration> {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is before the unix epoch")
+            .as_secs();
+        self.interval_at(((secs / 60) % u64::from(MINUTES_PER_DAY)) as u32)
+    }
+
+    fn interval_at(&self, minute: u32) -> Option<Duration> {
+        self.windows
+            .iter()
+            .find(|w| w.contains(minute))
+            .map(|w| w.interval)
+    }
+}
+
+/// Parse a time of day formatted as HH:MM into minutes since midnight
+fn parse_time(time: &str) -> Result<u32, String> {
+    let (hours, minutes) = time
+        .split_once(':')
+        .ok_or_else(|| format!("expected a time formatted as HH:MM, got {time}"))?;
+    let hours: u32 = hours
+        .parse()
+        .map_err(|_| format!("invalid hours in {time}"))?;
+    let minutes: u32 = minutes
+        .parse()
+        .map_err(|_| format!("invalid minutes in {time}"))?;
+    if hours >= 24 || minutes >= 60 {
+        return Err(format!("{time} is not a valid time of day"));
+    }
+    Ok(hours * 60 + minutes)
+}
+
+impl FromStr for IntervalSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let windows = s
+            .split(',')
+            .map(|window| {
+                let (range, interval) = window.trim().split_once('=').ok_or_else(|| {
+                    format!("expected a window formatted as HH:MM-HH:MM=SECONDS, got {window}")
+                })?;
+                let (start, end) = range.split_once('-').ok_or_else(|| {
+                    format!("expected a range formatted as HH:MM-HH:MM, got {range}")
+                })?;
+                let (start, end) = (parse_time(start)?, parse_time(end)?);
+                if start == end {
+                    return Err(format!("window {range} is empty"));
+                }
+                let interval = interval
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|secs| *secs > 0)
+                    .ok_or_else(|| format!("invalid number of seconds for {range}: {interval}"))?;
+                Ok(Window {
+                    start,
+                    end,
+                    interval: Duration::from_secs(interval),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(IntervalSchedule { windows })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_interval_by_time_of_day() {
+        let schedule: IntervalSchedule = "09:00-17:00=30,17:00-09:00=600".parse().unwrap();
+        assert_eq!(schedule.interval_at(9 * 60), Some(Duration::from_secs(30)));
+        assert_eq!(
+            schedule.interval_at(17 * 60),
+            Some(Duration::from_secs(600))
+        );
+        // Windows wrap around midnight
+        assert_eq!(schedule.interval_at(3 * 60), Some(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn uncovered_times_have_no_interval() {
+        let schedule: IntervalSchedule = "09:00-17:00=30".parse().unwrap();
+        assert_eq!(schedule.interval_at(20 * 60), None);
+    }
+
+    #[test]
+    fn rejects_malformed_schedules() {
+        for schedule in [
+            "",
+            "09:00-17:00",
+            "09:00=30",
+            "9-17=30",
+            "09:00-24:00=30",
+            "09:00-17:00=0",
+            "09:00-09:00=30",
+        ] {
+            assert!(
+                schedule.parse::<IntervalSchedule>().is_err(),
+                "{schedule} should be rejected"
+            );
+        }
+    }
+}
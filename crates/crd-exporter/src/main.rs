@@ -5,13 +5,21 @@ use std::{
 
 use anyhow::Result;
 use clap::{Parser, ValueEnum};
-use externalip_manager_manager::crd::v1alpha1;
-use kube::CustomResourceExt;
+use externalip_manager_manager::crd;
 
 #[derive(Debug, Clone, PartialEq, Eq, ValueEnum, Default)]
 enum ApiVersion {
     #[default]
     V1Alpha1,
+    V1Beta1,
+}
+impl ApiVersion {
+    fn name(&self) -> &'static str {
+        match self {
+            ApiVersion::V1Alpha1 => "v1alpha1",
+            ApiVersion::V1Beta1 => "v1beta1",
+        }
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -20,25 +28,22 @@ struct Arg {
     /// Directory to save the CRDs into
     #[arg()]
     output_dir: PathBuf,
-    /// Which version of CRDs to print
+    /// Which version resources are stored as. All versions are included in the CRD
     #[arg(long, value_enum, default_value_t = ApiVersion::V1Alpha1)]
     api_version: ApiVersion,
 }
 
 fn write(crd: String, dest: PathBuf) -> Result<()> {
-    // dirty, dirty hack to fix https://github.com/kube-rs/kube/issues/1680 for now
-    let crd = crd.replace("additionalPrinterColumns: []\n    name:", "name:");
     Ok(fs::write(dest, crd)?)
 }
 
 fn main() -> Result<()> {
     let args = Arg::parse();
 
-    match args.api_version {
-        ApiVersion::V1Alpha1 => write(
-            serde_yaml::to_string(&v1alpha1::ClusterExternalIPSource::crd()).unwrap(),
-            args.output_dir
-                .join("v1alpha1-ClusterExternalIPSource.yaml"),
-        ),
-    }
+    let version = args.api_version.name();
+    write(
+        crd::cluster_external_ip_source_crd_yaml(version),
+        args.output_dir
+            .join(format!("{version}-ClusterExternalIPSource.yaml")),
+    )
 }
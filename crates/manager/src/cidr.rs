@@ -0,0 +1,85 @@
+use std::{fmt::Display, net::IpAddr, str::FromStr};
+
+/// An IP network in CIDR notation, such as `192.0.2.0/24` or `2001:db8::/32`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Whether `addr` is part of this network. Addresses of the other family are never contained
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                net.to_bits() & mask == addr.to_bits() & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                net.to_bits() & mask == addr.to_bits() & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = s
+            .split_once('/')
+            .ok_or_else(|| format!("`{s}` is not in CIDR notation (address/prefix length)"))?;
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|e| format!("invalid address in CIDR `{s}`: {e}"))?;
+        let prefix_len: u8 = prefix_len
+            .parse()
+            .map_err(|e| format!("invalid prefix length in CIDR `{s}`: {e}"))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max_len {
+            return Err(format!(
+                "prefix length of CIDR `{s}` must not be larger than {max_len}"
+            ));
+        }
+        Ok(Cidr { addr, prefix_len })
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_addresses_in_network() {
+        let v4: Cidr = "192.0.2.0/24".parse().unwrap();
+        assert!(v4.contains(&"192.0.2.77".parse().unwrap()));
+        assert!(!v4.contains(&"192.0.3.1".parse().unwrap()));
+        assert!(!v4.contains(&"2001:db8::1".parse().unwrap()));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(&"2001:db8:ffff::1".parse().unwrap()));
+        assert!(!v6.contains(&"2001:db9::1".parse().unwrap()));
+
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(&"203.0.113.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn rejects_invalid_cidrs() {
+        assert!("192.0.2.0".parse::<Cidr>().is_err());
+        assert!("192.0.2.0/33".parse::<Cidr>().is_err());
+        assert!("not-an-ip/8".parse::<Cidr>().is_err());
+    }
+}
@@ -1 +1,63 @@
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::{
+    Api, Client, CustomResourceExt,
+    api::{Patch, PatchParams},
+    core::crd::merge_crds,
+};
+use tracing::info;
+
+use crate::MANAGER_ID;
+
 pub mod v1alpha1;
+pub mod v1beta1;
+
+/// The version that resources are stored as by default
+pub const STORAGE_VERSION: &str = "v1alpha1";
+
+/// The CRD of ClusterExternalIPSource with all API versions, as exported to YAML and installed by the manager.
+///
+/// The versions have identical schemas, so the API server can convert between them without a conversion webhook.
+pub fn cluster_external_ip_source_crd(storage_version: &str) -> CustomResourceDefinition {
+    let mut crd = merge_crds(
+        vec![
+            v1alpha1::ClusterExternalIPSource::crd(),
+            v1beta1::ClusterExternalIPSource::crd(),
+        ],
+        storage_version,
+    )
+    .expect("CRD versions should be mergeable");
+    // Work around https://github.com/kube-rs/kube/issues/1680, where missing printer columns end up as an empty list
+    for version in &mut crd.spec.versions {
+        if version
+            .additional_printer_columns
+            .as_ref()
+            .is_some_and(Vec::is_empty)
+        {
+            version.additional_printer_columns = None;
+        }
+    }
+    crd
+}
+
+/// The CRD of ClusterExternalIPSource rendered as YAML, to be written to a file or piped into `kubectl apply -f -`
+pub fn cluster_external_ip_source_crd_yaml(storage_version: &str) -> String {
+    serde_yaml::to_string(&cluster_external_ip_source_crd(storage_version))
+        .expect("CRD should be serializable to YAML")
+}
+
+/// Create or update the CRDs of the manager through server-side apply.
+///
+/// Requires permissions to `get`, `create` and `patch` customresourcedefinitions.
+pub async fn install_crds(client: Client) -> Result<(), kube::Error> {
+    let api: Api<CustomResourceDefinition> = Api::all(client);
+    let crd = cluster_external_ip_source_crd(STORAGE_VERSION);
+    let name = crd.metadata.name.clone().unwrap_or_default();
+    api.patch(
+        &name,
+        &PatchParams::apply(MANAGER_ID).force(),
+        &Patch::Apply(&crd),
+    )
+    .await?;
+    info!(msg = "installed CRD", name);
+    Ok(())
+}
@@ -1,4 +1,8 @@
-use std::net::IpAddr;
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    net::{IpAddr, Ipv6Addr},
+};
 
 use kube::CustomResource;
 use schemars::JsonSchema;
@@ -14,7 +18,11 @@ pub const CLUSTER_EXTERNAL_IP_SOURCE_KIND: &str = "ClusterExternalIPSource";
     plural = "clusterexternalipsources",
     doc = "Cluster-Wide source of external IP addresses for a given service",
     category = "externalip-manager",
-    shortname = "ceips"
+    shortname = "ceips",
+    printcolumn = r#"{"name":"IPv4 Query Mode", "type":"string", "jsonPath":".spec.ipv4.queryMode"}"#,
+    printcolumn = r#"{"name":"IPv6 Query Mode", "type":"string", "jsonPath":".spec.ipv6.queryMode"}"#,
+    printcolumn = r#"{"name":"Dual Stack Policy", "type":"string", "jsonPath":".spec.dualStackPolicy"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#
 )]
 #[serde(rename_all = "camelCase")]
 pub struct ClusterExternalIpSourceSpec {
@@ -24,6 +32,36 @@ pub struct ClusterExternalIpSourceSpec {
     /// Configure solvers for Ipv6 addresses
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ipv6: Option<IpSolversConfig>,
+    /// Fixed addresses of either family, added to the results of the matching family.
+    /// Saves splitting a mixed list into static solvers in both blocks, and may be used without any blocks.
+    /// IPv4-mapped IPv6 addresses such as ::ffff:192.0.2.1 are treated as IPv4 addresses.
+    /// Can be given either as a list or as a single string of comma- or whitespace-separated addresses
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_address_list"
+    )]
+    #[schemars(schema_with = "address_list_schema")]
+    pub static_addresses: Vec<IpAddr>,
+    /// How to combine IPv4 and IPv6 addresses. Can be "both" (default), "preferV4" or "preferV6".
+    /// "both" returns the addresses of both families.
+    /// "preferV4" and "preferV6" only return addresses of the other family if the preferred one yields no addresses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dual_stack_policy: Option<DualStackPolicy>,
+    /// Minimum seconds between queries of this source for the same service, such as for DNS records with long TTLs.
+    /// Until the interval has passed, services keep the addresses last resolved for them. Changing the source resets it.
+    /// Queried on every run if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_interval_seconds: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum DualStackPolicy {
+    #[default]
+    Both,
+    PreferV4,
+    PreferV6,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default)]
@@ -35,7 +73,86 @@ pub struct IpSolversConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub query_mode: Option<QueryMode>,
     #[serde(default)]
-    pub solvers: Vec<SolverKind>,
+    pub solvers: Vec<SolverEntry>,
+    /// Minimum number of addresses that need to be resolved for the service to be updated.
+    /// If fewer addresses are found, the current externalIPs of the service are kept. Defaults to 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_addresses: Option<u32>,
+    /// Maximum number of addresses to use. If more addresses are resolved, `selection` decides which ones are kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_addresses: Option<u32>,
+    /// Which addresses to keep if more than `maxAddresses` are resolved. Can be "deterministic" (default), "preferSolverOrder" or "random".
+    /// "deterministic" keeps the lowest addresses.
+    /// "preferSolverOrder" keeps the addresses of the solvers listed first.
+    /// "random" keeps a random subset, which may change on every run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selection: Option<Selection>,
+    /// What to do if a solver returns an error. Can be "skip" (default) or "fail".
+    /// "skip" logs the error and continues with the next solver.
+    /// "fail" stops querying and fails the whole block, keeping the current externalIPs of the service.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<SolverErrorPolicy>,
+    /// Only valid in the "ipv6" block. If set, the solvers are queried for IPv4 addresses instead,
+    /// which are then embedded into this /96 prefix (NAT64, RFC 6052), e.g. "64:ff9b::"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embed_v4_in_v6_prefix: Option<Ipv6Addr>,
+    /// Addresses that are removed from the results of every solver
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_addresses: Vec<IpAddr>,
+    /// Networks in CIDR notation, such as "192.0.2.0/24", whose addresses are removed from the results of every solver
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_cidrs: Vec<String>,
+}
+
+impl IpSolversConfig {
+    /// Solvers of the block that are not disabled, in order
+    pub fn enabled_solvers(&self) -> impl Iterator<Item = &SolverKind> {
+        self.solvers
+            .iter()
+            .filter(|entry| entry.is_enabled())
+            .map(|entry| &entry.kind)
+    }
+}
+
+/// A solver of a block, which can be disabled without removing its configuration
+#[derive(Deserialize, Serialize, Clone, Debug, Hash, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SolverEntry {
+    #[serde(flatten)]
+    pub kind: SolverKind,
+    /// Set to false to skip this solver, such as to find out which solver causes issues. Defaults to true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+}
+impl SolverEntry {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+impl From<SolverKind> for SolverEntry {
+    fn from(kind: SolverKind) -> Self {
+        SolverEntry {
+            kind,
+            enabled: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum SolverErrorPolicy {
+    #[default]
+    Skip,
+    Fail,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Selection {
+    #[default]
+    Deterministic,
+    PreferSolverOrder,
+    Random,
 }
 
 #[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default)]
@@ -54,14 +171,50 @@ pub enum SolverKind {
     IpAPI(IpAPIConfig),
     /// Use local interface addresses as the public IPs, such as with hostNetworking enabled
     Interface(InterfaceConfig),
+    /// Use the addresses of a single named interface on the host, including private addresses. Requires hostNetworking
+    LocalInterface(LocalInterfaceConfig),
     /// Resolve a hostname through DNS and return the resulting A/AAAA records as IP addresses
     DnsHostname(DnsHostnameConfig),
     /// Use the ingress addresses assigned to the service in .status.loadBalancer.ingress as external IP addresses
     LoadBalancerIngress(LoadBalancerIngressConfig),
+    /// Use the addresses currently in .spec.externalIPs of the service, such as to take ownership of them without changes
+    KeepCurrent(KeepCurrentConfig),
+    /// Use the addresses of the node the manager runs on, preferring ExternalIPs over InternalIPs. Requires the NODE_NAME environment variable to be set through the downward API
+    SelfNode(SelfNodeConfig),
+    /// Use the addresses in an annotation on the namespace of the service, such as one set by a provisioning controller
+    NamespaceAnnotation(NamespaceAnnotationConfig),
+    /// Read the addresses from a sidecar over a unix domain socket, avoiding requests to external services
+    UnixSocket(UnixSocketConfig),
     /// Return one or more static IP addresses. Useful as a fallback or as a partial address for the "merge" solver
     Static(StaticConfig),
     /// Merge the results from multiple solvers into a single address through masks. Useful for overriding a prefix or subnet from an acquired IP, or for merging a public prefix with a private address
     Merge(MergeConfig),
+    /// Build an address by substituting the results of named solvers into a template, such as "{prefix/64}::1"
+    Template(TemplateConfig),
+    /// Return fixed addresses after an optional delay, or fail on purpose. Only available in builds for testing
+    #[cfg(feature = "testing")]
+    Mock(MockConfig),
+}
+impl SolverKind {
+    /// Name of the solver kind as used in the resource, such as "dnsHostname"
+    pub fn name(&self) -> &'static str {
+        match self {
+            SolverKind::IpAPI(_) => "ipAPI",
+            SolverKind::Interface(_) => "interface",
+            SolverKind::LocalInterface(_) => "localInterface",
+            SolverKind::DnsHostname(_) => "dnsHostname",
+            SolverKind::LoadBalancerIngress(_) => "loadBalancerIngress",
+            SolverKind::KeepCurrent(_) => "keepCurrent",
+            SolverKind::SelfNode(_) => "selfNode",
+            SolverKind::NamespaceAnnotation(_) => "namespaceAnnotation",
+            SolverKind::UnixSocket(_) => "unixSocket",
+            SolverKind::Static(_) => "static",
+            SolverKind::Merge(_) => "merge",
+            SolverKind::Template(_) => "template",
+            #[cfg(feature = "testing")]
+            SolverKind::Mock(_) => "mock",
+        }
+    }
 }
 impl From<PartialSolverKind> for SolverKind {
     fn from(value: PartialSolverKind) -> Self {
@@ -69,8 +222,12 @@ impl From<PartialSolverKind> for SolverKind {
             PartialSolverKind::IpAPI(c) => SolverKind::IpAPI(c),
             PartialSolverKind::DnsHostname(c) => SolverKind::DnsHostname(c),
             PartialSolverKind::LoadBalancerIngress(c) => SolverKind::LoadBalancerIngress(c),
+            PartialSolverKind::KeepCurrent(c) => SolverKind::KeepCurrent(c),
             PartialSolverKind::Static(c) => SolverKind::Static(c),
             PartialSolverKind::Interface(c) => SolverKind::Interface(c),
+            PartialSolverKind::LocalInterface(c) => SolverKind::LocalInterface(c),
+            PartialSolverKind::SelfNode(c) => SolverKind::SelfNode(c),
+            PartialSolverKind::UnixSocket(c) => SolverKind::UnixSocket(c),
         }
     }
 }
@@ -85,14 +242,113 @@ impl From<&PartialSolverKind> for SolverKind {
 pub struct DnsHostnameConfig {
     /// The host to resolve.
     pub host: String,
+    /// The record type to query. Can be "A", "AAAA", "TXT" or "SRV".
+    /// If unset, A or AAAA records are queried depending on the address family.
+    /// For SRV records, the addresses of all targets are returned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_type: Option<DnsRecordType>,
+    /// Only used for TXT records. Pattern of the words in the record containing an address, such as "ip={address}".
+    /// Defaults to "{address}", which treats every word of the record as an address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txt_pattern: Option<String>,
+    /// Which of the resolved addresses of the requested family to return. Can be "all" (default), "first", "min" or "max".
+    /// "first" returns the first address in the order of the response, "min" and "max" the lowest or highest address,
+    /// which stays stable if the name server rotates the records of a round-robin record set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selection: Option<DnsSelection>,
 }
 
-#[derive(Deserialize, Serialize, Copy, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DnsSelection {
+    #[default]
+    All,
+    First,
+    Min,
+    Max,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DnsRecordType {
+    A,
+    Aaaa,
+    Txt,
+    Srv,
+}
+
+#[derive(Deserialize, Serialize, Clone, JsonSchema, Hash, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct IpAPIConfig {
     /// The service to use for retrieving public IP information
     #[serde(default)]
     pub provider: IpSolverProvider,
+    /// Providers to try in order if `provider` fails or rate limits us. They share the headers, url and other settings
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_providers: Vec<IpSolverProvider>,
+    /// Additional HTTP headers to send to the provider, such as an API key
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// URL to query. Only used by the "jsonArray" provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// HTTP status codes with which the provider signals rate limiting, such as 503. Defaults to [429]
+    #[serde(default = "default_ratelimit_status_codes")]
+    pub ratelimit_status_codes: Vec<u16>,
+    /// Client certificate to present to providers requiring mutual TLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_certificate: Option<ClientCertificateConfig>,
+    /// Only connect to the provider if the public key of its certificate matches one of these pins, in addition to the
+    /// usual certificate validation. Pins are the base64-encoded SHA-256 hash of the DER-encoded public key,
+    /// such as "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=". List the pin of the next key as well before rotating it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pinned_public_keys: Vec<String>,
+}
+fn default_ratelimit_status_codes() -> Vec<u16> {
+    vec![429]
+}
+impl Debug for IpAPIConfig {
+    // Header values may contain credentials, so we don't want them to show up in logs
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IpAPIConfig")
+            .field("provider", &self.provider)
+            .field("fallback_providers", &self.fallback_providers)
+            .field("url", &self.url)
+            .field("ratelimit_status_codes", &self.ratelimit_status_codes)
+            .field("client_certificate", &self.client_certificate)
+            .field("pinned_public_keys", &self.pinned_public_keys)
+            .field(
+                "headers",
+                &self
+                    .headers
+                    .iter()
+                    .map(|(name, value)| {
+                        if is_sensitive_header(name) {
+                            (name.as_str(), "<redacted>")
+                        } else {
+                            (name.as_str(), value.as_str())
+                        }
+                    })
+                    .collect::<BTreeMap<_, _>>(),
+            )
+            .finish()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCertificateConfig {
+    /// Path to the PEM-encoded client certificate (chain) on the manager pod, such as a mounted Secret
+    pub cert_file: String,
+    /// Path to the PEM-encoded private key of the client certificate on the manager pod
+    pub key_file: String,
+}
+
+/// Returns whether the given HTTP header name likely refers to a secret value
+pub(crate) fn is_sensitive_header(name: &str) -> bool {
+    const SENSITIVE_MARKERS: [&str; 6] = ["auth", "key", "token", "secret", "password", "cookie"];
+    let name = name.to_ascii_lowercase();
+    SENSITIVE_MARKERS.iter().any(|m| name.contains(m))
 }
 
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, JsonSchema, Default, Hash, PartialEq, Eq)]
@@ -103,6 +359,8 @@ pub enum IpSolverProvider {
     MyIp,
     // https://www.ipify.org/
     Ipify,
+    /// Any endpoint returning a JSON array of addresses, such as `["192.0.2.1", "2001:db8::1"]`. Requires `url` to be set
+    JsonArray,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
@@ -112,33 +370,216 @@ pub struct InterfaceConfig {
     pub name: Option<String>,
 }
 
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalInterfaceConfig {
+    /// Name of the network interface
+    pub interface: String,
+    /// Which addresses of the interface to use. Can be "global" (default) or "all".
+    /// "global" excludes loopback and link-local addresses, "all" returns every address of the requested family
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<InterfaceAddressScope>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum InterfaceAddressScope {
+    #[default]
+    Global,
+    All,
+}
+
 #[derive(Deserialize, Serialize, Copy, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct LoadBalancerIngressConfig {}
 
+#[derive(Deserialize, Serialize, Copy, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct KeepCurrentConfig {}
+
+#[derive(Deserialize, Serialize, Copy, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfNodeConfig {
+    /// For services with externalTrafficPolicy "Local", only return addresses if the node hosts a ready endpoint of the service,
+    /// as traffic to other nodes would be dropped. Requires permissions to list endpointslices. Defaults to false
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub respect_local_traffic_policy: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NamespaceAnnotationConfig {
+    /// Key of the namespace annotation containing the addresses, as a comma- or whitespace-separated list
+    pub annotation_key: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UnixSocketConfig {
+    /// Path of the socket, which must be mounted into the manager pod, such as through a shared emptyDir volume.
+    /// The sidecar is expected to write its response and close the connection for every client
+    pub path: String,
+    /// Format of the response of the sidecar. Can be "text" (default) or "json".
+    /// "text" expects a comma- or whitespace-separated list of addresses, "json" expects a JSON array of addresses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<UnixSocketFormat>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum UnixSocketFormat {
+    #[default]
+    Text,
+    Json,
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct StaticConfig {
-    /// Addresses to return. Addresses with mismatched types (v4 vs v6) will be ignored
+    /// Addresses to return. Addresses with mismatched types (v4 vs v6) will be ignored.
+    /// IPv4-mapped IPv6 addresses such as ::ffff:192.0.2.1 are treated as IPv4 addresses.
+    /// Can be given either as a list or as a single string of comma- or whitespace-separated addresses
+    #[serde(default, deserialize_with = "deserialize_address_list")]
+    #[schemars(schema_with = "address_list_schema")]
     pub addresses: Vec<IpAddr>,
+    /// Inclusive ranges of addresses such as "192.0.2.1-192.0.2.4", which are expanded into the individual addresses.
+    /// Both ends must belong to the same address family, and a range may contain at most 256 addresses
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ranges: Vec<String>,
+}
+
+/// Accepts addresses either as a list or as a single comma/whitespace-separated string
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AddressList {
+    List(Vec<String>),
+    Joined(String),
+}
+
+pub(crate) fn deserialize_address_list<'de, D>(deserializer: D) -> Result<Vec<IpAddr>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let tokens = match AddressList::deserialize(deserializer)? {
+        AddressList::List(list) => list,
+        AddressList::Joined(joined) => joined
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|token| !token.is_empty())
+            .map(str::to_string)
+            .collect(),
+    };
+    tokens
+        .iter()
+        .map(|token| {
+            token
+                .trim()
+                .parse::<IpAddr>()
+                .map_err(|e| serde::de::Error::custom(format!("invalid IP address `{token}`: {e}")))
+        })
+        .collect()
+}
+
+// Structural schemas can't express "string or list", so the value is validated by the deserializer instead
+pub(crate) fn address_list_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({
+        "x-kubernetes-preserve-unknown-fields": true,
+    })
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct MergeConfig {
     /// Each partial solver returns a section of the final IP address.
-    /// Should a solver return multiple IP addresses, the last address is used
+    /// Should a solver return multiple IP addresses, the partSelector of its partial solver decides which one is used (default "min")
     pub partial_solvers: Vec<PartialSolver>,
+    /// How to combine partial solvers returning multiple addresses. "single" (default) uses one address per part,
+    /// selected by its partSelector, and returns one merged address.
+    /// "product" returns one merged address per combination of the addresses of all parts, e.g. one per prefix returned by DNS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<MergeResultMode>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MergeResultMode {
+    #[default]
+    Single,
+    Product,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct PartialSolver {
     /// Type of solver to retrieve the address part through.
-    /// Should a solver return multiple IP addresses, the last address is used as the part
-    pub solver: PartialSolverKind,
-    /// This netmask defines the section of the solvers response that will be used in the final address. Examples: 0:0:0:ffff::, 0.0.255.0
-    pub mask: IpAddr,
+    /// Should a solver return multiple IP addresses, partSelector decides which one is used as the part.
+    /// Mutually exclusive with value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub solver: Option<PartialSolverKind>,
+    /// Fixed address to use instead of a solver, such as "::1" to set the host part of a dynamic prefix.
+    /// Must belong to the address family of the merge. Mutually exclusive with solver
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<IpAddr>,
+    /// This netmask defines the section of the solvers response that will be used in the final address. Examples: 0:0:0:ffff::, 0.0.255.0.
+    /// Mutually exclusive with maskPrefixLen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<IpAddr>,
+    /// Alternative to mask: use the first N bits of the solvers response in the final address. Example: 64 is equivalent to ffff:ffff:ffff:ffff::.
+    /// Mutually exclusive with mask
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask_prefix_len: Option<u8>,
+    /// Which address to use if the solver returns multiple addresses. Can be "min" (default), "max", "first" or "last".
+    /// "first" and "last" depend on the order returned by the solver, which may vary between queries, such as with DNS round-robin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub part_selector: Option<PartSelector>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PartSelector {
+    #[default]
+    Min,
+    Max,
+    First,
+    Last,
+}
+
+#[cfg(feature = "testing")]
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MockConfig {
+    /// Addresses to return
+    #[serde(default)]
+    pub addresses: Vec<IpAddr>,
+    /// Milliseconds to wait before responding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay_ms: Option<u64>,
+    /// Fail instead of returning addresses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<MockFailure>,
+}
+
+#[cfg(feature = "testing")]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MockFailure {
+    /// Fail with an error
+    Error,
+    /// Be rate limited like an IP API, backing off before querying again
+    RateLimited,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Hash, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateConfig {
+    /// Template for the final address. Placeholders in the form {name} are replaced with the address returned by the solver of the same name.
+    /// Use {name/N} to only insert the first N bits of the address, which must be a multiple of 8 for IPv4 and 16 for IPv6. Example: {prefix/64}::1
+    pub template: String,
+    /// Named solvers to substitute into the template.
+    /// Should a solver return multiple IP addresses, partSelector decides which one is used
+    pub solvers: BTreeMap<String, PartialSolverKind>,
+    /// Which address to use if a solver returns multiple addresses. Can be "min" (default), "max", "first" or "last"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub part_selector: Option<PartSelector>,
 }
 
 // TODO: Generate this and SolverKind through a macro as to avoid duplication
@@ -150,10 +591,80 @@ pub enum PartialSolverKind {
     IpAPI(IpAPIConfig),
     /// Use local interface addresses as the public IPs, such as with hostNetworking enabled
     Interface(InterfaceConfig),
+    /// Use the addresses of a single named interface on the host, including private addresses. Requires hostNetworking
+    LocalInterface(LocalInterfaceConfig),
     /// Resolve a hostname through DNS and return the resulting A/AAAA records as IP addresses
     DnsHostname(DnsHostnameConfig),
     /// Use the ingress addresses assigned to the service in .status.loadBalancer.ingress as external IP addresses
     LoadBalancerIngress(LoadBalancerIngressConfig),
+    /// Use the addresses currently in .spec.externalIPs of the service, such as to take ownership of them without changes
+    KeepCurrent(KeepCurrentConfig),
+    /// Use the addresses of the node the manager runs on, preferring ExternalIPs over InternalIPs. Requires the NODE_NAME environment variable to be set through the downward API
+    SelfNode(SelfNodeConfig),
+    /// Read the addresses from a sidecar over a unix domain socket, avoiding requests to external services
+    UnixSocket(UnixSocketConfig),
     /// Return one or more static IP addresses. Useful as a fallback or as a partial address for the "merge" solver
     Static(StaticConfig),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_addresses_from_list() {
+        let config: StaticConfig =
+            serde_json::from_str(r#"{"addresses": ["192.0.2.1", "2001:db8::1"]}"#).unwrap();
+        assert_eq!(
+            config.addresses,
+            vec![
+                "192.0.2.1".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn static_addresses_from_string_with_mixed_separators() {
+        let config: StaticConfig = serde_json::from_str(
+            r#"{"addresses": "192.0.2.1, 192.0.2.2 2001:db8::1,2001:db8::2\n\t192.0.2.3"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.addresses,
+            [
+                "192.0.2.1",
+                "192.0.2.2",
+                "2001:db8::1",
+                "2001:db8::2",
+                "192.0.2.3"
+            ]
+            .iter()
+            .map(|a| a.parse::<IpAddr>().unwrap())
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn static_addresses_reports_bad_token() {
+        let err =
+            serde_json::from_str::<StaticConfig>(r#"{"addresses": "192.0.2.1, 192.0.2.300"}"#)
+                .unwrap_err();
+        assert!(err.to_string().contains("`192.0.2.300`"), "{err}");
+    }
+
+    #[test]
+    fn solvers_can_be_disabled() {
+        let config: IpSolversConfig = serde_json::from_str(
+            r#"{"solvers": [{"static": {"addresses": ["192.0.2.1"]}, "enabled": false}, {"loadBalancerIngress": {}}]}"#,
+        )
+        .unwrap();
+        assert!(!config.solvers[0].is_enabled());
+        assert_eq!(
+            config.enabled_solvers().collect::<Vec<_>>(),
+            vec![&SolverKind::LoadBalancerIngress(
+                LoadBalancerIngressConfig {}
+            )]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! The v1beta1 API. Currently identical to [v1alpha1], so the nested types are shared until they diverge.
+
+use std::net::IpAddr;
+
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::v1alpha1;
+pub use super::v1alpha1::{DualStackPolicy, IpSolversConfig};
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "externalip.spacebird.dev",
+    version = "v1beta1",
+    kind = "ClusterExternalIPSource",
+    plural = "clusterexternalipsources",
+    doc = "Cluster-Wide source of external IP addresses for a given service",
+    category = "externalip-manager",
+    shortname = "ceips",
+    printcolumn = r#"{"name":"IPv4 Query Mode", "type":"string", "jsonPath":".spec.ipv4.queryMode"}"#,
+    printcolumn = r#"{"name":"IPv6 Query Mode", "type":"string", "jsonPath":".spec.ipv6.queryMode"}"#,
+    printcolumn = r#"{"name":"Dual Stack Policy", "type":"string", "jsonPath":".spec.dualStackPolicy"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterExternalIpSourceSpec {
+    /// Configure solvers for Ipv4 addresses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv4: Option<IpSolversConfig>,
+    /// Configure solvers for Ipv6 addresses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<IpSolversConfig>,
+    /// Fixed addresses of either family, added to the results of the matching family.
+    /// Saves splitting a mixed list into static solvers in both blocks, and may be used without any blocks.
+    /// IPv4-mapped IPv6 addresses such as ::ffff:192.0.2.1 are treated as IPv4 addresses.
+    /// Can be given either as a list or as a single string of comma- or whitespace-separated addresses
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "v1alpha1::deserialize_address_list"
+    )]
+    #[schemars(schema_with = "v1alpha1::address_list_schema")]
+    pub static_addresses: Vec<IpAddr>,
+    /// How to combine IPv4 and IPv6 addresses. Can be "both" (default), "preferV4" or "preferV6".
+    /// "both" returns the addresses of both families.
+    /// "preferV4" and "preferV6" only return addresses of the other family if the preferred one yields no addresses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dual_stack_policy: Option<DualStackPolicy>,
+    /// Minimum seconds between queries of this source for the same service, such as for DNS records with long TTLs.
+    /// Until the interval has passed, services keep the addresses last resolved for them. Changing the source resets it.
+    /// Queried on every run if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_interval_seconds: Option<u32>,
+}
+
+impl From<v1alpha1::ClusterExternalIpSourceSpec> for ClusterExternalIpSourceSpec {
+    fn from(value: v1alpha1::ClusterExternalIpSourceSpec) -> Self {
+        ClusterExternalIpSourceSpec {
+            ipv4: value.ipv4,
+            ipv6: value.ipv6,
+            static_addresses: value.static_addresses,
+            dual_stack_policy: value.dual_stack_policy,
+            refresh_interval_seconds: value.refresh_interval_seconds,
+        }
+    }
+}
+impl From<ClusterExternalIpSourceSpec> for v1alpha1::ClusterExternalIpSourceSpec {
+    fn from(value: ClusterExternalIpSourceSpec) -> Self {
+        v1alpha1::ClusterExternalIpSourceSpec {
+            ipv4: value.ipv4,
+            ipv6: value.ipv6,
+            static_addresses: value.static_addresses,
+            dual_stack_policy: value.dual_stack_policy,
+            refresh_interval_seconds: value.refresh_interval_seconds,
+        }
+    }
+}
+
+impl From<v1alpha1::ClusterExternalIPSource> for ClusterExternalIPSource {
+    fn from(value: v1alpha1::ClusterExternalIPSource) -> Self {
+        ClusterExternalIPSource {
+            metadata: value.metadata,
+            spec: value.spec.into(),
+        }
+    }
+}
+impl From<ClusterExternalIPSource> for v1alpha1::ClusterExternalIPSource {
+    fn from(value: ClusterExternalIPSource) -> Self {
+        v1alpha1::ClusterExternalIPSource {
+            metadata: value.metadata,
+            spec: value.spec.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crd::v1alpha1::{QueryMode, SolverKind, StaticConfig};
+
+    use super::*;
+
+    #[test]
+    fn converts_from_and_to_v1alpha1() {
+        let alpha = v1alpha1::ClusterExternalIPSource::new(
+            "public",
+            v1alpha1::ClusterExternalIpSourceSpec {
+                ipv4: Some(IpSolversConfig {
+                    query_mode: Some(QueryMode::All),
+                    solvers: vec![
+                        SolverKind::Static(StaticConfig {
+                            addresses: vec!["192.0.2.1".parse().unwrap()],
+                            ranges: vec![],
+                        })
+                        .into(),
+                    ],
+                    ..Default::default()
+                }),
+                ipv6: None,
+                static_addresses: vec!["2001:db8::1".parse().unwrap()],
+                dual_stack_policy: Some(DualStackPolicy::PreferV4),
+                refresh_interval_seconds: Some(300),
+            },
+        );
+        let beta = ClusterExternalIPSource::from(alpha.clone());
+        assert_eq!(beta.metadata.name.as_deref(), Some("public"));
+
+        let roundtrip = v1alpha1::ClusterExternalIPSource::from(beta);
+        assert_eq!(
+            serde_json::to_value(&roundtrip.spec).unwrap(),
+            serde_json::to_value(&alpha.spec).unwrap()
+        );
+    }
+}
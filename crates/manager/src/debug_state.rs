@@ -0,0 +1,139 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    Manager,
+    external_ip_source::{AddressKind, SolverCacheState},
+    metrics::serve_get,
+};
+
+/// In-memory state of a manager for troubleshooting, see [serve_debug_state]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DebugState {
+    /// When this state was collected, in seconds since the unix epoch
+    pub collected_at: u64,
+    /// When the sources were last refreshed successfully, in seconds since the unix epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry_last_refresh: Option<u64>,
+    /// Known sources keyed by name, including ones that failed to parse
+    pub sources: BTreeMap<String, SourceDebugState>,
+    /// All solvers shared by the sources
+    pub solvers: Vec<SolverDebugState>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SourceDebugState {
+    /// Kinds of the solvers of the ipv4 block, such as "ipAPI"
+    pub ipv4: Vec<&'static str>,
+    /// Kinds of the solvers of the ipv6 block
+    pub ipv6: Vec<&'static str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub static_addresses: Vec<IpAddr>,
+    /// Most recent error of the source since the manager started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SolverDebugState {
+    pub kind: &'static str,
+    pub family: String,
+    /// Configuration of the solver, with secrets such as API keys redacted
+    pub config: String,
+    /// [None] if the solver doesn't cache its results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<SolverCacheState>,
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl Manager {
+    /// Collect the current state of the source registry and its solvers
+    pub async fn debug_state(&self) -> DebugState {
+        let mut sources: BTreeMap<String, SourceDebugState> = self
+            .ip_sources
+            .sources()
+            .map(|source| {
+                let kinds = |kind| {
+                    source
+                        .solvers(kind)
+                        .iter()
+                        .map(|solver| solver.name())
+                        .collect_vec()
+                };
+                (
+                    source.name(),
+                    SourceDebugState {
+                        ipv4: kinds(AddressKind::IPv4),
+                        ipv6: kinds(AddressKind::IPv6),
+                        static_addresses: source.static_addresses().to_vec(),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        for (name, err) in self.ip_sources.last_errors() {
+            let source = sources.entry(name.clone()).or_default();
+            source.last_error = Some(err.error.clone());
+            source.last_error_at = Some(unix_secs(err.timestamp));
+        }
+
+        let mut solvers = vec![];
+        for ((solver, kind), registered) in self.ip_sources.solvers() {
+            solvers.push(SolverDebugState {
+                kind: solver.name(),
+                family: kind.to_string(),
+                // The Debug representation redacts secret headers
+                config: format!("{solver:?}"),
+                cache: registered.cache_state().await,
+            });
+        }
+        solvers.sort_by(|a, b| (a.kind, &a.family, &a.config).cmp(&(b.kind, &b.family, &b.config)));
+
+        DebugState {
+            collected_at: unix_secs(SystemTime::now()),
+            registry_last_refresh: self.ip_sources.last_refresh().map(unix_secs),
+            sources,
+            solvers,
+        }
+    }
+}
+
+/// The latest [DebugState] of a manager, shared with [serve_debug_state]
+#[derive(Debug, Default)]
+pub struct SharedDebugState(Mutex<Option<DebugState>>);
+
+impl SharedDebugState {
+    pub fn set(&self, state: DebugState) {
+        *self.0.lock().expect("debug state lock poisoned") = Some(state);
+    }
+
+    fn render(&self) -> String {
+        let state = self.0.lock().expect("debug state lock poisoned");
+        serde_json::to_string_pretty(&*state).expect("debug state is serializable")
+    }
+}
+
+/// Serve the latest `state` as JSON on `addr` under `/debug/state` until the task is aborted
+pub async fn serve_debug_state(
+    addr: SocketAddr,
+    state: Arc<SharedDebugState>,
+) -> std::io::Result<()> {
+    serve_get(addr, "/debug/state", "application/json", move || {
+        state.render()
+    })
+    .await
+}
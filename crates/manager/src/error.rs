@@ -1,4 +1,4 @@
-use std::net::AddrParseError;
+use std::{net::AddrParseError, time::Duration};
 
 use crate::{external_ip_source::IpSourceError, svc::FinderError};
 
@@ -12,6 +12,29 @@ pub enum Error {
     Kube(kube::Error),
     #[error("IP source {name} failed: `{err}`")]
     IPSource { name: String, err: IpSourceError },
+    #[error("Could not find IP source `{0}`")]
+    UnknownIPSource(String),
+    #[error("Reconciling service `{svc}` timed out after {}s", timeout.as_secs())]
+    ReconcileTimeout { svc: String, timeout: Duration },
+    #[error("Reconciliation run timed out after {}s, remaining services are reconciled in the next run", timeout.as_secs())]
+    RunTimeout { timeout: Duration },
+    #[error("No ClusterExternalIPSources exist, create at least one before starting the manager")]
+    NoIPSources,
+    #[error("Could not list ClusterExternalIPSources, check that the manager is allowed to: `{0}`")]
+    ListIPSources(kube::Error),
+    #[error("Invalid proxy: {0}")]
+    InvalidProxy(String),
+}
+
+impl Error {
+    /// Whether reconciling a service failed because it timed out, so that it may succeed if retried soon
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Error::ReconcileTimeout { .. } => true,
+            Error::IPSource { err, .. } => err.is_timeout(),
+            _ => false,
+        }
+    }
 }
 
 impl From<kube::Error> for Error {
@@ -1,28 +1,44 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
 use k8s_openapi::api::core::v1::ObjectReference;
 use kube::{
     Client,
     runtime::events::{Event, EventType, Recorder, Reporter},
 };
-use tracing::warn;
+use tracing::{debug, warn};
+
+/// Minimum time between two identical events for the same object
+const EVENT_COALESCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
 #[derive(Clone)]
 pub struct EventRecorder {
     recorder: Recorder,
+    // Shared between clones, so that all call sites are throttled together
+    throttle: Arc<Mutex<EventThrottle>>,
 }
 
 impl EventRecorder {
-    pub fn new(client: Client, controller: String) -> EventRecorder {
+    /// Create a recorder publishing events as `controller`. `instance` identifies the replica publishing them, such as its pod name
+    pub fn new(client: Client, controller: String, instance: Option<String>) -> EventRecorder {
         EventRecorder {
             recorder: Recorder::new(
                 client,
                 Reporter {
                     controller,
-                    instance: None,
+                    instance,
                 },
             ),
+            throttle: Arc::new(Mutex::new(EventThrottle::new(EVENT_COALESCE_INTERVAL))),
         }
     }
 
+    /// Publish an event for the given object.
+    ///
+    /// Identical events (same object, reason, action and message) are only published once per [EVENT_COALESCE_INTERVAL].
     pub async fn publish(
         &self,
         reason: String,
@@ -31,6 +47,31 @@ impl EventRecorder {
         message: Option<String>,
         object_ref: &ObjectReference,
     ) {
+        let key = EventKey {
+            object: (
+                object_ref.kind.clone().unwrap_or_default(),
+                object_ref.namespace.clone().unwrap_or_default(),
+                object_ref.name.clone().unwrap_or_default(),
+            ),
+            reason: reason.clone(),
+            action: action.clone(),
+            note: message.clone(),
+        };
+        if !self
+            .throttle
+            .lock()
+            .expect("event throttle lock poisoned")
+            .allow(key, Instant::now())
+        {
+            debug!(
+                msg = "skipping event identical to a recently published one",
+                action,
+                reason,
+                note = message
+            );
+            return;
+        }
+
         if let Err(e) = self
             .recorder
             .publish(
@@ -49,3 +90,68 @@ impl EventRecorder {
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct EventKey {
+    /// kind, namespace and name of the involved object
+    object: (String, String, String),
+    reason: String,
+    action: String,
+    note: Option<String>,
+}
+
+/// Tracks when events were last published to suppress identical events within an interval
+#[derive(Debug)]
+struct EventThrottle {
+    interval: Duration,
+    last_published: HashMap<EventKey, Instant>,
+}
+
+impl EventThrottle {
+    fn new(interval: Duration) -> EventThrottle {
+        EventThrottle {
+            interval,
+            last_published: HashMap::new(),
+        }
+    }
+
+    /// Returns whether an event with `key` may be published at `now`, and records it if so
+    fn allow(&mut self, key: EventKey, now: Instant) -> bool {
+        // Forget expired entries so that the map doesn't grow with every distinct message
+        self.last_published
+            .retain(|_, published| now.duration_since(*published) < self.interval);
+        if self.last_published.contains_key(&key) {
+            return false;
+        }
+        self.last_published.insert(key, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(note: &str) -> EventKey {
+        EventKey {
+            object: (
+                "Service".to_string(),
+                "default".to_string(),
+                "svc".to_string(),
+            ),
+            reason: "FailedExternalIPLookup".to_string(),
+            action: "UpdateExternalIPs".to_string(),
+            note: Some(note.to_string()),
+        }
+    }
+
+    #[test]
+    fn coalesces_identical_events_within_interval() {
+        let mut throttle = EventThrottle::new(Duration::from_secs(60));
+        let start = Instant::now();
+        assert!(throttle.allow(key("a"), start));
+        assert!(!throttle.allow(key("a"), start + Duration::from_secs(30)));
+        assert!(throttle.allow(key("b"), start + Duration::from_secs(30)));
+        assert!(throttle.allow(key("a"), start + Duration::from_secs(60)));
+    }
+}
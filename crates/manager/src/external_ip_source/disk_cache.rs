@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+const CACHE_FILE: &str = "solver-cache.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct CacheEntry {
+    addresses: Vec<IpAddr>,
+    /// Seconds since the unix epoch
+    expires_at: u64,
+}
+
+/// Persists solver results on disk, so that they survive restarts of the manager.
+///
+/// Entries are keyed by a string identifying the solver and expire after the TTL of the original result.
+#[derive(Debug)]
+pub struct DiskCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl DiskCache {
+    /// Load the cache from `dir`. A missing, unreadable or corrupt cache file results in an empty cache.
+    pub fn load(dir: &Path) -> DiskCache {
+        let path = dir.join(CACHE_FILE);
+        let entries = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!(msg = "ignoring corrupt solver cache file", path = %path.display(), err = %e);
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                warn!(msg = "ignoring unreadable solver cache file", path = %path.display(), err = %e);
+                HashMap::new()
+            }
+        };
+        DiskCache {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Return the cached addresses for `key` and their remaining lifetime, unless they have expired
+    pub fn get(&self, key: &str) -> Option<(Vec<IpAddr>, Duration)> {
+        let entries = self.entries.lock().expect("disk cache lock poisoned");
+        let entry = entries.get(key)?;
+        let remaining = Duration::from_secs(entry.expires_at.saturating_sub(unix_now()));
+        (!remaining.is_zero()).then(|| (entry.addresses.clone(), remaining))
+    }
+
+    /// Store `addresses` for `key` for the duration of `ttl` and write the cache to disk
+    pub fn set(&self, key: &str, addresses: Vec<IpAddr>, ttl: Duration) {
+        let mut entries = self.entries.lock().expect("disk cache lock poisoned");
+        let now = unix_now();
+        entries.retain(|_, entry| entry.expires_at > now);
+        entries.insert(
+            key.to_string(),
+            CacheEntry {
+                addresses,
+                expires_at: now + ttl.as_secs(),
+            },
+        );
+        let content = serde_json::to_vec(&*entries).expect("cache entries should be serializable");
+        match fs::write(&self.path, content) {
+            Ok(()) => debug!(msg = "persisted solver cache", path = %self.path.display()),
+            Err(e) => {
+                warn!(msg = "failed to persist solver cache", path = %self.path.display(), err = %e)
+            }
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("externalip-manager-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn persists_entries_across_loads() {
+        let dir = test_dir("cache-persist");
+        let addrs: Vec<IpAddr> = vec!["192.0.2.1".parse().unwrap()];
+        DiskCache::load(&dir).set("solver", addrs.clone(), Duration::from_secs(300));
+
+        let (cached, remaining) = DiskCache::load(&dir).get("solver").unwrap();
+        assert_eq!(cached, addrs);
+        assert!(remaining <= Duration::from_secs(300));
+        assert!(DiskCache::load(&dir).get("other").is_none());
+    }
+
+    #[test]
+    fn ignores_expired_entries() {
+        let dir = test_dir("cache-expired");
+        let cache = DiskCache::load(&dir);
+        cache.set("solver", vec!["192.0.2.1".parse().unwrap()], Duration::ZERO);
+        assert!(cache.get("solver").is_none());
+    }
+
+    #[test]
+    fn ignores_corrupt_file() {
+        let dir = test_dir("cache-corrupt");
+        fs::write(dir.join(CACHE_FILE), "{not json").unwrap();
+        let cache = DiskCache::load(&dir);
+        assert!(cache.get("solver").is_none());
+        // Writing replaces the corrupt file
+        cache.set(
+            "solver",
+            vec!["192.0.2.1".parse().unwrap()],
+            Duration::from_secs(60),
+        );
+        assert!(DiskCache::load(&dir).get("solver").is_some());
+    }
+}
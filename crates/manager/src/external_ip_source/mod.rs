@@ -1,10 +1,15 @@
 use std::fmt::Debug;
 
+mod disk_cache;
 mod registry;
 mod solvers;
 mod source;
 
-pub use registry::IPSourceRegistry;
+pub use disk_cache::DiskCache;
+pub use registry::{IPSourceRegistry, LastSourceError};
+pub use solvers::{
+    HttpClientConfig, OutboundLimit, ResolverProtocol, SolverCacheState, SolverContext, socks_proxy,
+};
 pub use source::{AddressKind, ExternalIpSource, ExternalIpSourceKind};
 
 use crate::external_ip_source::solvers::SolverError;
@@ -17,6 +22,21 @@ pub enum IpSourceError {
     Solver(SolverError),
     #[error("IP address source is invalid: `{0}`")]
     Malformed(String),
+    #[error("found {found} {kind} addresses, but at least {min} are required")]
+    TooFewAddresses {
+        kind: AddressKind,
+        found: usize,
+        min: usize,
+    },
+    #[error("timed out waiting for solver {solver}")]
+    Timeout { solver: String },
+}
+
+impl IpSourceError {
+    /// Whether this error was caused by a solver timing out, which is often transient
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, IpSourceError::Timeout { .. })
+    }
 }
 
 impl From<kube::Error> for IpSourceError {
@@ -1,39 +1,83 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use itertools::Itertools;
-use kube::{Api, Client, Resource, api::ListParams, runtime::events::EventType};
-use tokio::sync::RwLock;
-use tracing::error;
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::{
+    Api, Resource, ResourceExt,
+    api::{ListParams, Patch, PatchParams},
+    runtime::events::EventType,
+};
+use tracing::{error, info};
 
 use crate::{
     crd::v1alpha1::{ClusterExternalIPSource, SolverKind},
     events::EventRecorder,
-    external_ip_source::{AddressKind, ExternalIpSource, IpSourceError, solvers::Solver},
+    external_ip_source::{
+        AddressKind, ExternalIpSource, IpSourceError,
+        solvers::{RegisteredSolver, SolverContext},
+        source::duplicate_solvers,
+    },
+    retry::retry_transient,
 };
 
 // Solvers are registered globally so that multiple solvers with the same config can be reused for caching.
 // Since some solvers like merge call other sub-solvers, the RwLock is needed to ensure consistency + Sync.
 // Solvers must not refer to themselves in a nested fashion, else we deadlock. This is ensured by the CRD tree structure.
-pub type SolverRegistry = HashMap<(SolverKind, AddressKind), RwLock<Box<dyn Solver>>>;
+// As entries are shared across services, only global solvers may cache, see [RegisteredSolver].
+pub type SolverRegistry = HashMap<(SolverKind, AddressKind), RegisteredSolver>;
 
 const REASON_EIP_ERROR: &str = "InvalidIPSource";
+const ACTION_PARSE_EIP: &str = "ParsingClusterExternalIPSource";
+const ACTION_QUERY_EIP: &str = "QueryingClusterExternalIPSource";
+/// The most recent error encountered for a [ClusterExternalIPSource], either while parsing or querying it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastSourceError {
+    pub error: String,
+    pub timestamp: SystemTime,
+}
 
 pub struct IPSourceRegistry {
+    solver_ctx: SolverContext,
     ceips_api: Api<ClusterExternalIPSource>,
     cluster_eip_sources: HashMap<String, ExternalIpSource>,
+    /// Spec of each source as of the last refresh, to tell whether a source changed
+    source_specs: HashMap<String, serde_json::Value>,
     solvers: SolverRegistry,
+    last_errors: HashMap<String, LastSourceError>,
+    /// Key of the annotation listing the services referencing a source
+    referenced_by_key: String,
+    /// Current value of the `referenced_by_key` annotation of each source, keyed by source name
+    referenced_by: HashMap<String, String>,
+    /// Number of sources found by the last refresh, including invalid ones
+    listed_sources: usize,
+    /// When the sources were last refreshed successfully. Sources become stale if refreshing keeps failing, such as after RBAC changes
+    last_refresh: Option<SystemTime>,
+    /// References to the objects of all sources found by the last refresh, keyed by source name
+    source_refs: HashMap<String, ObjectReference>,
     events: EventRecorder,
 }
 
 impl IPSourceRegistry {
     pub async fn new(
-        client: Client,
+        solver_ctx: SolverContext,
         events: EventRecorder,
+        referenced_by_key: String,
     ) -> Result<IPSourceRegistry, IpSourceError> {
         let mut registry = IPSourceRegistry {
-            ceips_api: Api::all(client.clone()),
+            ceips_api: Api::all(solver_ctx.client.clone()),
+            solver_ctx,
             cluster_eip_sources: HashMap::new(),
+            source_specs: HashMap::new(),
             solvers: HashMap::new(),
+            last_errors: HashMap::new(),
+            referenced_by_key,
+            referenced_by: HashMap::new(),
+            listed_sources: 0,
+            last_refresh: None,
+            source_refs: HashMap::new(),
             events,
         };
         registry.refresh().await?;
@@ -41,7 +85,16 @@ impl IPSourceRegistry {
     }
 
     pub async fn refresh(&mut self) -> Result<(), IpSourceError> {
-        let cluster_eip_apiobjs = self.ceips_api.list(&ListParams::default()).await?;
+        let list_params = ListParams::default();
+        let cluster_eip_apiobjs = retry_transient("list ClusterExternalIPSources", || {
+            self.ceips_api.list(&list_params)
+        })
+        .await?;
+        self.listed_sources = cluster_eip_apiobjs.items.len();
+        self.source_refs = cluster_eip_apiobjs
+            .iter()
+            .map(|ceips| (ceips.name_any(), ceips.object_ref(&())))
+            .collect();
 
         #[allow(clippy::result_large_err)]
         let (ceips_list, errs): (Vec<_>, Vec<_>) = cluster_eip_apiobjs
@@ -51,85 +104,396 @@ impl IPSourceRegistry {
                 ExternalIpSource::try_from(ceips).map_err(|e| (e, ceips_ref))
             })
             .partition(Result::is_ok);
+        let specs: HashMap<String, serde_json::Value> = cluster_eip_apiobjs
+            .iter()
+            .map(|ceips| {
+                let spec = serde_json::to_value(&ceips.spec).expect("spec is serializable");
+                (ceips.name_any(), spec)
+            })
+            .collect();
+        // Unchanged sources keep their last results, so that their refresh interval isn't reset on every refresh
+        let mut previous = std::mem::take(&mut self.cluster_eip_sources);
         self.cluster_eip_sources = ceips_list
             .into_iter()
             .map(Result::unwrap)
-            .map(|ceips| (ceips.name(), ceips))
+            .map(|mut ceips| {
+                let name = ceips.name();
+                if self.source_specs.get(&name) == specs.get(&name)
+                    && let Some(previous) = previous.remove(&name)
+                {
+                    ceips.keep_results_of(previous);
+                }
+                (name, ceips)
+            })
             .collect();
+        self.source_specs = specs;
         let errs = errs.into_iter().map(Result::unwrap_err).collect_vec();
         for (e, ceips_ref) in errs {
-            error!(msg = "failed to parse ClusterExternalIPSource", err = ?e, name = ceips_ref.name, namespace = ceips_ref.namespace);
-            self.events
-                .publish(
-                    REASON_EIP_ERROR.to_string(),
-                    "ParsingClusterExternalIPSource".to_string(),
-                    EventType::Warning,
-                    Some(format!("Invalid ClusterExternalIPSource: {e}")),
-                    &ceips_ref,
-                )
-                .await;
+            self.reject_source(&e, &ceips_ref).await;
+        }
+        self.referenced_by = cluster_eip_apiobjs
+            .iter()
+            .filter_map(|ceips| {
+                let references = ceips.annotations().get(&self.referenced_by_key)?;
+                Some((ceips.name_any(), references.clone()))
+            })
+            .collect();
+        for ceips in &cluster_eip_apiobjs {
+            let duplicates = [&ceips.spec.ipv4, &ceips.spec.ipv6]
+                .into_iter()
+                .flatten()
+                .flat_map(duplicate_solvers)
+                .collect_vec();
+            if !duplicates.is_empty() {
+                self.events
+                    .publish(
+                        "DuplicateSolvers".to_string(),
+                        ACTION_PARSE_EIP.to_string(),
+                        EventType::Warning,
+                        Some(format!(
+                            "Solvers are listed more than once and only queried once: {duplicates:?}"
+                        )),
+                        &ceips.object_ref(&()),
+                    )
+                    .await;
+            }
         }
 
-        // Populate solver map globally so multiple solvers with the same config reference one solver for caching purposes
-        let current_solver_refs = cluster_eip_apiobjs
+        // Populate solver map globally so multiple solvers with the same config reference one solver for caching purposes.
+        // Sources whose solvers can't be built are rejected on their own, so that they don't affect any other source
+        let valid_sources = cluster_eip_apiobjs
+            .items
             .into_iter()
-            .flat_map(|ceips_apiobj| {
-                let mut solvers = vec![];
-                if let Some(ipv4) = ceips_apiobj.spec.ipv4 {
-                    solvers.extend(ipv4.solvers.clone().into_iter().flat_map(|s| {
-                        match &s {
-                            SolverKind::Merge(merge_config) => {
-                                let mut solvers = merge_config
-                                    .partial_solvers
-                                    .iter()
-                                    .map(|ps| (SolverKind::from(&ps.solver), AddressKind::IPv4))
-                                    .collect_vec();
-                                solvers.push((s, AddressKind::IPv4));
-                                solvers.into_iter()
-                            }
-                            _ => vec![(s, AddressKind::IPv4)]
-                                .into_iter()
-                                .collect_vec()
-                                .into_iter(),
-                        }
-                    }))
-                }
-                if let Some(ipv6) = ceips_apiobj.spec.ipv6 {
-                    solvers.extend(ipv6.solvers.clone().into_iter().flat_map(|s| {
-                        match &s {
-                            SolverKind::Merge(merge_config) => {
-                                let mut solvers = merge_config
-                                    .partial_solvers
-                                    .iter()
-                                    .map(|ps| (SolverKind::from(&ps.solver), AddressKind::IPv6))
-                                    .collect_vec();
-                                solvers.push((s, AddressKind::IPv6));
-                                solvers.into_iter()
-                            }
-                            _ => vec![(s, AddressKind::IPv6)]
-                                .into_iter()
-                                .collect_vec()
-                                .into_iter(),
-                        }
-                    }))
-                }
-                solvers.into_iter()
-            })
-            .collect::<HashSet<(SolverKind, AddressKind)>>();
-        for solver_ref in &current_solver_refs {
-            self.solvers
-                .entry(solver_ref.clone())
-                .or_insert(RwLock::new(solver_ref.0.clone().try_into()?));
+            .filter(|ceips| self.cluster_eip_sources.contains_key(&ceips.name_any()))
+            .sorted_by_key(|ceips| ceips.name_any());
+        for ceips in valid_sources {
+            if let Err(e) = self.register_solvers(&ceips) {
+                self.cluster_eip_sources.remove(&ceips.name_any());
+                self.reject_source(&e, &ceips.object_ref(&())).await;
+            }
         }
 
+        let now = SystemTime::now();
+        self.last_refresh = Some(now);
+        if let Some(metrics) = &self.solver_ctx.metrics {
+            let timestamp = now
+                .duration_since(UNIX_EPOCH)
+                .expect("system time is before the unix epoch");
+            metrics
+                .registry_last_refresh_timestamp
+                .set(timestamp.as_secs_f64());
+        }
         Ok(())
     }
 
+    /// Build the solvers of `ceips` that aren't registered yet. Nothing is registered if any of them fails
+    fn register_solvers(&mut self, ceips: &ClusterExternalIPSource) -> Result<(), IpSourceError> {
+        let missing = solver_refs(vec![ceips.clone()])
+            .into_iter()
+            .filter(|solver_ref| !self.solvers.contains_key(solver_ref))
+            .map(|(solver, kind)| {
+                let registered = (solver.clone(), kind, self.solver_ctx.clone()).try_into()?;
+                Ok(((solver, kind), registered))
+            })
+            .collect::<Result<Vec<_>, IpSourceError>>()?;
+        self.solvers.extend(missing);
+        Ok(())
+    }
+
+    /// Report a source that is invalid or whose solvers can't be built, so that its authors see the problem on the source itself
+    async fn reject_source(&mut self, err: &IpSourceError, ceips_ref: &ObjectReference) {
+        error!(msg = "failed to parse ClusterExternalIPSource", err = ?err, name = ceips_ref.name, namespace = ceips_ref.namespace);
+        self.record_error(ceips_ref.name.as_deref().unwrap_or_default(), err);
+        self.events
+            .publish(
+                REASON_EIP_ERROR.to_string(),
+                ACTION_PARSE_EIP.to_string(),
+                EventType::Warning,
+                Some(format!("Invalid ClusterExternalIPSource: {err}")),
+                ceips_ref,
+            )
+            .await;
+    }
+
+    /// When the sources were last refreshed successfully, [None] if they never were
+    pub fn last_refresh(&self) -> Option<SystemTime> {
+        self.last_refresh
+    }
+
+    /// Number of ClusterExternalIPSources that exist in the cluster, including ones that failed to parse
+    pub fn source_count(&self) -> usize {
+        self.listed_sources
+    }
+
     pub fn get_cluster(&self, name: &str) -> Option<&ExternalIpSource> {
         self.cluster_eip_sources.get(name)
     }
 
+    /// All valid ClusterExternalIPSources, in no particular order
+    pub fn sources(&self) -> impl Iterator<Item = &ExternalIpSource> {
+        self.cluster_eip_sources.values()
+    }
+
     pub fn solvers(&self) -> &SolverRegistry {
         &self.solvers
     }
+
+    /// Remember `err` as the most recent error of the source `name`
+    pub fn record_error(&mut self, name: &str, err: &IpSourceError) {
+        self.last_errors.insert(
+            name.to_string(),
+            LastSourceError {
+                error: err.to_string(),
+                timestamp: SystemTime::now(),
+            },
+        );
+    }
+
+    /// Publish a warning on the source `name` after querying it failed, so that its authors see the problem on the source itself.
+    ///
+    /// The event doesn't name the service being reconciled, so that identical failures across all services
+    /// referencing the source are coalesced by the [EventRecorder].
+    pub async fn publish_query_error(&self, name: &str, err: &IpSourceError) {
+        let Some(source_ref) = self.source_refs.get(name) else {
+            return;
+        };
+        self.events
+            .publish(
+                "FailedExternalIPLookup".to_string(),
+                ACTION_QUERY_EIP.to_string(),
+                EventType::Warning,
+                Some(format!("Failed to query external IP addresses: {err}")),
+                source_ref,
+            )
+            .await;
+    }
+
+    /// The most recent error of each source that has failed since the manager started, keyed by source name
+    pub fn last_errors(&self) -> &HashMap<String, LastSourceError> {
+        &self.last_errors
+    }
+
+    /// Annotate each known source with the services referencing it, given as namespace/name keyed by source name.
+    ///
+    /// Only sources whose references changed are patched, and sources that are no longer referenced have the annotation removed.
+    /// Returns the sources that could not be patched.
+    pub async fn annotate_references(
+        &mut self,
+        references: &BTreeMap<String, BTreeSet<String>>,
+    ) -> Vec<(String, IpSourceError)> {
+        let mut errs = vec![];
+        let names = self
+            .cluster_eip_sources
+            .keys()
+            .cloned()
+            .sorted()
+            .collect_vec();
+        for name in names {
+            let desired = references.get(&name).map(|svcs| svcs.iter().join(","));
+            if desired.as_ref() == self.referenced_by.get(&name) {
+                continue;
+            }
+            let patch = serde_json::json!({
+                "metadata": {"annotations": {&self.referenced_by_key: desired}}
+            });
+            match self
+                .ceips_api
+                .patch(&name, &PatchParams::default(), &Patch::Merge(patch))
+                .await
+            {
+                Ok(_) => {
+                    info!(
+                        msg = "updated services referencing ClusterExternalIPSource",
+                        source = name,
+                        services = desired
+                    );
+                    match desired {
+                        Some(desired) => self.referenced_by.insert(name, desired),
+                        None => self.referenced_by.remove(&name),
+                    };
+                }
+                Err(e) => errs.push((name, e.into())),
+            }
+        }
+        errs
+    }
+}
+
+/// Return the registry keys of all solvers of `sources`, without duplicates.
+///
+/// Keys are ordered by source name and then by their position within the source, so that solvers are always constructed
+/// in the same order and the same construction error is reported for the same sources.
+fn solver_refs(mut sources: Vec<ClusterExternalIPSource>) -> Vec<(SolverKind, AddressKind)> {
+    sources.sort_by_key(|ceips| ceips.name_any());
+    let mut seen = HashSet::new();
+    sources
+        .into_iter()
+        .flat_map(|ceips| {
+            let mut solvers = vec![];
+            if let Some(ipv4) = ceips.spec.ipv4 {
+                solvers.extend(
+                    ipv4.enabled_solvers()
+                        .flat_map(|s| with_subsolvers(s.clone(), AddressKind::IPv4)),
+                )
+            }
+            if let Some(ipv6) = ceips.spec.ipv6 {
+                // NAT64 blocks resolve IPv4 addresses and embed them into the IPv6 prefix afterwards
+                let kind = match ipv6.embed_v4_in_v6_prefix {
+                    Some(_) => AddressKind::IPv4,
+                    None => AddressKind::IPv6,
+                };
+                solvers.extend(
+                    ipv6.enabled_solvers()
+                        .flat_map(|s| with_subsolvers(s.clone(), kind)),
+                )
+            }
+            solvers
+        })
+        .filter(|solver_ref| seen.insert(solver_ref.clone()))
+        .collect()
+}
+
+/// Return the registry keys for `solver` and any sub-solvers it queries, such as the parts of a [SolverKind::Merge]
+fn with_subsolvers(solver: SolverKind, kind: AddressKind) -> Vec<(SolverKind, AddressKind)> {
+    let mut solvers = match &solver {
+        SolverKind::Merge(merge_config) => merge_config
+            .partial_solvers
+            .iter()
+            .filter_map(|ps| ps.solver.as_ref())
+            .map(|solver| (SolverKind::from(solver), kind))
+            .collect_vec(),
+        SolverKind::Template(template_config) => template_config
+            .solvers
+            .values()
+            .map(|ps| (SolverKind::from(ps), kind))
+            .collect_vec(),
+        _ => vec![],
+    };
+    solvers.push((solver, kind));
+    solvers
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use kube::Client;
+
+    use crate::{
+        crd::v1alpha1::{ClusterExternalIpSourceSpec, IpSolversConfig, StaticConfig},
+        external_ip_source::HttpClientConfig,
+    };
+
+    use super::*;
+
+    fn static_solver(addr: &str) -> SolverKind {
+        SolverKind::Static(StaticConfig {
+            addresses: vec![addr.parse().unwrap()],
+            ranges: vec![],
+        })
+    }
+
+    fn source(name: &str, v4: &[&str], v6: &[&str]) -> ClusterExternalIPSource {
+        let solvers = |addrs: &[&str]| IpSolversConfig {
+            solvers: addrs
+                .iter()
+                .map(|addr| static_solver(addr).into())
+                .collect(),
+            ..Default::default()
+        };
+        ClusterExternalIPSource::new(
+            name,
+            ClusterExternalIpSourceSpec {
+                ipv4: Some(solvers(v4)),
+                ipv6: Some(solvers(v6)),
+                static_addresses: vec![],
+                dual_stack_policy: None,
+                refresh_interval_seconds: None,
+            },
+        )
+    }
+
+    #[test]
+    fn orders_solvers_by_source() {
+        let sources = vec![
+            source("b", &["192.0.2.2", "192.0.2.1"], &[]),
+            source("a", &["192.0.2.3"], &["2001:db8::1"]),
+            source("c", &["192.0.2.1"], &[]),
+        ];
+        let expected = vec![
+            (static_solver("192.0.2.3"), AddressKind::IPv4),
+            (static_solver("2001:db8::1"), AddressKind::IPv6),
+            (static_solver("192.0.2.2"), AddressKind::IPv4),
+            (static_solver("192.0.2.1"), AddressKind::IPv4),
+        ];
+        assert_eq!(solver_refs(sources.clone()), expected);
+        assert_eq!(solver_refs(sources.into_iter().rev().collect()), expected);
+    }
+
+    /// A client listing `sources`, on which all other requests fail
+    fn source_list_client(sources: Vec<ClusterExternalIPSource>) -> Client {
+        let service = tower::service_fn(move |req: http::Request<kube::client::Body>| {
+            let sources = sources.clone();
+            async move {
+                let (status, body) = if req.method() == http::Method::GET {
+                    let list = serde_json::json!({
+                        "apiVersion": "externalip.spacebird.dev/v1alpha1",
+                        "kind": "ClusterExternalIPSourceList",
+                        "metadata": {},
+                        "items": sources
+                    });
+                    (200, list)
+                } else {
+                    (404, serde_json::json!({}))
+                };
+                Ok::<_, std::convert::Infallible>(
+                    http::Response::builder()
+                        .status(status)
+                        .body(kube::client::Body::from(body.to_string().into_bytes()))
+                        .unwrap(),
+                )
+            }
+        });
+        Client::new(service, "default")
+    }
+
+    #[tokio::test]
+    async fn rejects_only_broken_sources() {
+        let mut broken = source("broken", &[], &[]);
+        broken.spec.ipv4 = Some(IpSolversConfig {
+            solvers: vec![
+                SolverKind::Static(StaticConfig {
+                    addresses: vec![],
+                    ranges: vec!["192.0.2.1-192.0.3.1".to_string()],
+                })
+                .into(),
+            ],
+            ..Default::default()
+        });
+        broken.spec.ipv6 = None;
+        let working = source("working", &["192.0.2.1"], &["2001:db8::1"]);
+        let client = source_list_client(vec![broken, working]);
+        let registry = IPSourceRegistry::new(
+            SolverContext {
+                client: client.clone(),
+                http_client: HttpClientConfig::default().build(),
+                http_config: HttpClientConfig::default(),
+                resolver_protocol: Default::default(),
+                disk_cache: None,
+                metrics: None,
+                outbound_limit: Default::default(),
+                min_cache_ttl: Duration::ZERO,
+            },
+            EventRecorder::new(client, "test".to_string(), None),
+            "referenced-by".to_string(),
+        )
+        .await
+        .unwrap();
+
+        assert!(registry.get_cluster("working").is_some());
+        assert!(registry.get_cluster("broken").is_none());
+        assert!(registry.last_errors().contains_key("broken"));
+        assert_eq!(registry.solvers().len(), 2);
+        assert_eq!(registry.source_count(), 2);
+    }
 }
@@ -1,28 +1,317 @@
+use std::{fmt::Debug, net::IpAddr, sync::Arc, time::Duration};
+
 use async_trait::async_trait;
-use hickory_resolver::{Resolver, net::runtime::TokioRuntimeProvider};
+use hickory_resolver::{
+    Resolver,
+    config::{NameServerConfig, ResolverConfig},
+    net::runtime::TokioRuntimeProvider,
+    proto::rr::RData,
+    system_conf::read_system_conf,
+};
+use itertools::Itertools;
+
+use tracing::{debug, error, instrument, warn};
+
+use crate::{
+    crd::v1alpha1::{DnsHostnameConfig, DnsRecordType, DnsSelection},
+    external_ip_source::{self, IpSourceError, solvers::SolverError},
+};
+
+use super::{OutboundLimit, Solver};
+
+/// Placeholder marking the position of the address in a TXT pattern
+const TXT_ADDRESS_PLACEHOLDER: &str = "{address}";
+
+/// Protocol used by [DnsHostname] solvers to reach name servers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ResolverProtocol {
+    /// The system name servers over UDP, retrying truncated responses over TCP
+    #[default]
+    Udp,
+    /// The system name servers over TCP only
+    Tcp,
+    /// DNS-over-HTTPS to `server`, using `server_name` to verify its certificate
+    Https { server: IpAddr, server_name: String },
+}
+
+/// Apply `protocol` to the `system` resolver configuration
+fn resolver_config(protocol: &ResolverProtocol, system: ResolverConfig) -> ResolverConfig {
+    match protocol {
+        ResolverProtocol::Udp => system,
+        ResolverProtocol::Tcp => {
+            let (domain, search, name_servers) = system.into_parts();
+            let name_servers = name_servers
+                .iter()
+                .map(|ns| ns.ip)
+                .unique()
+                .map(NameServerConfig::tcp)
+                .collect();
+            ResolverConfig::from_parts(domain, search, name_servers)
+        }
+        ResolverProtocol::Https {
+            server,
+            server_name,
+        } => ResolverConfig::from_parts(
+            None,
+            vec![],
+            vec![NameServerConfig::https(
+                *server,
+                Arc::from(server_name.as_str()),
+                None,
+            )],
+        ),
+    }
+}
+
+/// The DNS lookups required by [DnsHostname], split out so that they can be replaced in tests
+#[async_trait]
+trait DnsLookup: Debug + Send + Sync {
+    async fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>, SolverError>;
+    /// Returns the contents of each TXT record, with multiple character-strings concatenated
+    async fn lookup_txt(&self, host: &str) -> Result<Vec<String>, SolverError>;
+    /// Returns the target host of each SRV record, skipping targets of "." that mark the service as unavailable
+    async fn lookup_srv(&self, host: &str) -> Result<Vec<String>, SolverError>;
+}
+
+#[async_trait]
+impl DnsLookup for Resolver<TokioRuntimeProvider> {
+    async fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>, SolverError> {
+        Ok(Resolver::lookup_ip(self, host)
+            .await
+            .map_err(|e| SolverError {
+                reason: e.to_string(),
+            })?
+            .iter()
+            .collect())
+    }
+
+    async fn lookup_txt(&self, host: &str) -> Result<Vec<String>, SolverError> {
+        Ok(self
+            .txt_lookup(host)
+            .await
+            .map_err(|e| SolverError {
+                reason: e.to_string(),
+            })?
+            .answers()
+            .iter()
+            .filter_map(|record| match &record.data {
+                RData::TXT(txt) => Some(
+                    txt.txt_data
+                        .iter()
+                        .map(|data| String::from_utf8_lossy(data))
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn lookup_srv(&self, host: &str) -> Result<Vec<String>, SolverError> {
+        Ok(self
+            .srv_lookup(host)
+            .await
+            .map_err(|e| SolverError {
+                reason: e.to_string(),
+            })?
+            .answers()
+            .iter()
+            .filter_map(|record| match &record.data {
+                RData::SRV(srv) if !srv.target.is_root() => Some(srv.target.to_ascii()),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+/// Build a resolver using the system name servers over `protocol`
+fn build_resolver(
+    protocol: &ResolverProtocol,
+    min_cache_ttl: Duration,
+) -> Result<Resolver<TokioRuntimeProvider>, SolverError> {
+    let (system_config, mut options) = read_system_conf().map_err(|e| SolverError {
+        reason: format!("could not read system DNS configuration: {e}"),
+    })?;
+    if !min_cache_ttl.is_zero() {
+        for min_ttl in [&mut options.positive_min_ttl, &mut options.negative_min_ttl] {
+            *min_ttl = Some(min_ttl.unwrap_or_default().max(min_cache_ttl));
+        }
+    }
+    Resolver::builder_with_config(
+        resolver_config(protocol, system_config),
+        TokioRuntimeProvider::default(),
+    )
+    .with_options(options)
+    .build()
+    .map_err(|e| SolverError {
+        reason: format!("could not build the resolver: {e}"),
+    })
+}
+
+/// Stands in for a resolver that could not be built, failing every lookup with the reason
+#[derive(Debug)]
+struct UnavailableResolver(SolverError);
 
-use k8s_openapi::api::core::v1::Service;
-use tracing::instrument;
+#[async_trait]
+impl DnsLookup for UnavailableResolver {
+    async fn lookup_ip(&self, _: &str) -> Result<Vec<IpAddr>, SolverError> {
+        Err(self.0.clone())
+    }
 
-use crate::external_ip_source::{self, registry::SolverRegistry, solvers::SolverError};
+    async fn lookup_txt(&self, _: &str) -> Result<Vec<String>, SolverError> {
+        Err(self.0.clone())
+    }
 
-use super::Solver;
+    async fn lookup_srv(&self, _: &str) -> Result<Vec<String>, SolverError> {
+        Err(self.0.clone())
+    }
+}
 
 #[derive(Debug)]
 pub struct DnsHostname {
     host: String,
-    resolver: Resolver<TokioRuntimeProvider>,
+    record_type: Option<DnsRecordType>,
+    /// Text around the address in a TXT record, such as `("ip=", "")`
+    txt_pattern: (String, String),
+    selection: DnsSelection,
+    resolver: Box<dyn DnsLookup>,
+    outbound_limit: OutboundLimit,
 }
 
 impl DnsHostname {
-    pub fn new(host: String) -> DnsHostname {
-        DnsHostname {
-            host,
-            resolver: Resolver::builder_tokio()
-                .expect("could not build DNS resolver")
-                .build()
-                .expect("could not build the resolver"),
+    /// Create a solver resolving through the system name servers. Records are cached for at least `min_cache_ttl`,
+    /// even if their TTL is shorter
+    pub fn new(
+        config: DnsHostnameConfig,
+        protocol: &ResolverProtocol,
+        min_cache_ttl: Duration,
+    ) -> Result<DnsHostname, IpSourceError> {
+        // Without name servers only this solver fails, when it is queried
+        let resolver: Box<dyn DnsLookup> = match build_resolver(protocol, min_cache_ttl) {
+            Ok(resolver) => Box::new(resolver),
+            Err(e) => {
+                error!(msg = "unable to create DNS resolver, dnsHostname solver will fail", host = config.host, err = ?e);
+                Box::new(UnavailableResolver(e))
+            }
+        };
+        DnsHostname::with_resolver(config, resolver)
+    }
+
+    fn with_resolver(
+        config: DnsHostnameConfig,
+        resolver: Box<dyn DnsLookup>,
+    ) -> Result<DnsHostname, IpSourceError> {
+        let pattern = config
+            .txt_pattern
+            .unwrap_or(TXT_ADDRESS_PLACEHOLDER.to_string());
+        let txt_pattern = match pattern.split(TXT_ADDRESS_PLACEHOLDER).collect::<Vec<_>>()[..] {
+            [prefix, suffix] => (prefix.to_string(), suffix.to_string()),
+            _ => {
+                return Err(IpSourceError::Malformed(format!(
+                    "txtPattern `{pattern}` must contain {TXT_ADDRESS_PLACEHOLDER} exactly once"
+                )));
+            }
+        };
+        Ok(DnsHostname {
+            host: config.host,
+            record_type: config.record_type,
+            txt_pattern,
+            selection: config.selection.unwrap_or_default(),
+            resolver,
+            outbound_limit: OutboundLimit::default(),
+        })
+    }
+
+    /// Wait for `outbound_limit` before sending queries to name servers
+    pub fn with_outbound_limit(mut self, outbound_limit: OutboundLimit) -> DnsHostname {
+        self.outbound_limit = outbound_limit;
+        self
+    }
+
+    /// Extract all addresses matching the TXT pattern from the whitespace-separated words in `records`.
+    /// TXT RRsets commonly hold unrelated records such as SPF policies, so words that don't match the pattern or
+    /// don't parse as an address are skipped. Fails only if none of the records contain an address
+    fn parse_txt(&self, records: &[String]) -> Result<Vec<IpAddr>, SolverError> {
+        let (prefix, suffix) = &self.txt_pattern;
+        let mut invalid = vec![];
+        let addrs = records
+            .iter()
+            .flat_map(|record| record.split_whitespace())
+            .filter_map(|word| {
+                word.strip_prefix(prefix.as_str())?
+                    .strip_suffix(suffix.as_str())
+            })
+            .filter_map(|value| match value.parse::<IpAddr>() {
+                Ok(addr) => Some(addr),
+                Err(e) => {
+                    debug!(msg = "skipping invalid address in TXT record", host = self.host, value, %e);
+                    invalid.push(format!("`{value}`"));
+                    None
+                }
+            })
+            .collect_vec();
+        if addrs.is_empty() {
+            return Err(SolverError {
+                reason: format!(
+                    "TXT records of {} contain no valid address{}",
+                    self.host,
+                    if invalid.is_empty() {
+                        String::new()
+                    } else {
+                        format!(", skipped {}", invalid.join(", "))
+                    }
+                ),
+            });
         }
+        Ok(addrs)
+    }
+
+    /// Look up the configured record type, returning addresses of both families
+    async fn lookup(&self) -> Result<Vec<IpAddr>, SolverError> {
+        Ok(match self.record_type {
+            None => self.resolver.lookup_ip(&self.host).await?,
+            Some(DnsRecordType::A) => self
+                .resolver
+                .lookup_ip(&self.host)
+                .await?
+                .into_iter()
+                .filter(IpAddr::is_ipv4)
+                .collect(),
+            Some(DnsRecordType::Aaaa) => self
+                .resolver
+                .lookup_ip(&self.host)
+                .await?
+                .into_iter()
+                .filter(IpAddr::is_ipv6)
+                .collect(),
+            Some(DnsRecordType::Txt) => {
+                self.parse_txt(&self.resolver.lookup_txt(&self.host).await?)?
+            }
+            Some(DnsRecordType::Srv) => {
+                let mut addrs = vec![];
+                let mut last_err = None;
+                let mut resolved = false;
+                for target in self.resolver.lookup_srv(&self.host).await?.iter().unique() {
+                    // a single unreachable target shouldn't take down the others
+                    match self.resolver.lookup_ip(target).await {
+                        Ok(target_addrs) => {
+                            resolved = true;
+                            addrs.extend(target_addrs);
+                        }
+                        Err(e) => {
+                            warn!(msg = "skipping SRV target that failed to resolve", host = self.host, target, %e);
+                            last_err = Some(e);
+                        }
+                    }
+                }
+                if let Some(e) = last_err.filter(|_| !resolved) {
+                    return Err(SolverError {
+                        reason: format!("none of the SRV targets of {} resolved: {e}", self.host),
+                    });
+                }
+                // multiple SRV records may point at the same target or at targets sharing an address
+                addrs.into_iter().unique().collect()
+            }
+        })
     }
 }
 
@@ -32,21 +321,300 @@ impl Solver for DnsHostname {
     async fn get_addresses(
         &mut self,
         kind: external_ip_source::AddressKind,
-        _: &Service,
-        _: &SolverRegistry,
     ) -> Result<Vec<std::net::IpAddr>, SolverError> {
-        Ok(self
-            .resolver
-            .lookup_ip(self.host.clone())
-            .await
-            .map_err(|e| SolverError {
-                reason: e.to_string(),
-            })?
-            .iter()
+        let addrs = self
+            .outbound_limit
+            .run(self.lookup())
+            .await?
+            .into_iter()
             .filter(|addr| match kind {
                 external_ip_source::AddressKind::IPv4 => addr.is_ipv4(),
                 external_ip_source::AddressKind::IPv6 => addr.is_ipv6(),
+            });
+        Ok(match self.selection {
+            DnsSelection::All => addrs.collect(),
+            DnsSelection::First => addrs.take(1).collect(),
+            DnsSelection::Min => addrs.min().into_iter().collect(),
+            DnsSelection::Max => addrs.max().into_iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use external_ip_source::AddressKind;
+    use hickory_resolver::config::ProtocolConfig;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct MockResolver {
+        ips: std::collections::HashMap<String, Vec<IpAddr>>,
+        txt: Vec<String>,
+        srv: Vec<String>,
+    }
+    #[async_trait]
+    impl DnsLookup for MockResolver {
+        async fn lookup_ip(&self, host: &str) -> Result<Vec<IpAddr>, SolverError> {
+            self.ips.get(host).cloned().ok_or_else(|| SolverError {
+                reason: format!("no records for {host}"),
             })
-            .collect())
+        }
+        async fn lookup_txt(&self, _: &str) -> Result<Vec<String>, SolverError> {
+            Ok(self.txt.clone())
+        }
+        async fn lookup_srv(&self, _: &str) -> Result<Vec<String>, SolverError> {
+            Ok(self.srv.clone())
+        }
+    }
+
+    fn txt_solver(txt: &[&str], pattern: Option<&str>) -> Result<DnsHostname, IpSourceError> {
+        DnsHostname::with_resolver(
+            DnsHostnameConfig {
+                host: "egress.example.com".to_string(),
+                record_type: Some(DnsRecordType::Txt),
+                txt_pattern: pattern.map(str::to_string),
+                selection: None,
+            },
+            Box::new(MockResolver {
+                txt: txt.iter().map(|t| t.to_string()).collect(),
+                ..Default::default()
+            }),
+        )
+    }
+
+    fn protocols(config: &ResolverConfig) -> Vec<Vec<ProtocolConfig>> {
+        config
+            .name_servers()
+            .iter()
+            .map(|ns| ns.connections.iter().map(|c| c.protocol.clone()).collect())
+            .collect()
+    }
+
+    fn system_config() -> ResolverConfig {
+        ResolverConfig::from_parts(
+            None,
+            vec![],
+            vec![
+                NameServerConfig::udp_and_tcp("192.0.2.53".parse().unwrap()),
+                NameServerConfig::udp_and_tcp("2001:db8::53".parse().unwrap()),
+            ],
+        )
+    }
+
+    #[test]
+    fn builds_resolver_for_protocol() {
+        let udp = resolver_config(&ResolverProtocol::Udp, system_config());
+        assert_eq!(
+            protocols(&udp),
+            vec![vec![ProtocolConfig::Udp, ProtocolConfig::Tcp]; 2]
+        );
+
+        let tcp = resolver_config(&ResolverProtocol::Tcp, system_config());
+        assert_eq!(protocols(&tcp), vec![vec![ProtocolConfig::Tcp]; 2]);
+        assert_eq!(
+            tcp.name_servers().iter().map(|ns| ns.ip).collect_vec(),
+            system_config()
+                .name_servers()
+                .iter()
+                .map(|ns| ns.ip)
+                .collect_vec()
+        );
+
+        let https = resolver_config(
+            &ResolverProtocol::Https {
+                server: "1.1.1.1".parse().unwrap(),
+                server_name: "cloudflare-dns.com".to_string(),
+            },
+            system_config(),
+        );
+        assert_eq!(
+            https.name_servers().iter().map(|ns| ns.ip).collect_vec(),
+            vec!["1.1.1.1".parse::<IpAddr>().unwrap()]
+        );
+        assert!(matches!(
+            &protocols(&https)[..],
+            [p] if matches!(&p[..], [ProtocolConfig::Https { server_name, .. }] if &**server_name == "cloudflare-dns.com")
+        ));
+    }
+
+    #[tokio::test]
+    async fn extracts_address_from_txt_record() {
+        let mut solver = txt_solver(
+            &["v=spf1 -all", "ip=192.0.2.1 ip=2001:db8::1 region=eu"],
+            Some("ip={address}"),
+        )
+        .unwrap();
+        assert_eq!(
+            solver.get_addresses(AddressKind::IPv4).await.unwrap(),
+            vec!["192.0.2.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            solver.get_addresses(AddressKind::IPv6).await.unwrap(),
+            vec!["2001:db8::1".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn selects_from_round_robin_records() {
+        let records = ["192.0.2.2", "2001:db8::1", "192.0.2.3", "192.0.2.1"];
+        for (selection, expected) in [
+            (None, vec!["192.0.2.2", "192.0.2.3", "192.0.2.1"]),
+            (Some(DnsSelection::First), vec!["192.0.2.2"]),
+            (Some(DnsSelection::Min), vec!["192.0.2.1"]),
+            (Some(DnsSelection::Max), vec!["192.0.2.3"]),
+        ] {
+            let mut solver = DnsHostname::with_resolver(
+                DnsHostnameConfig {
+                    host: "rr.example.com".to_string(),
+                    record_type: None,
+                    txt_pattern: None,
+                    selection,
+                },
+                Box::new(MockResolver {
+                    ips: [(
+                        "rr.example.com".to_string(),
+                        records.iter().map(|a| a.parse().unwrap()).collect(),
+                    )]
+                    .into(),
+                    ..Default::default()
+                }),
+            )
+            .unwrap();
+            assert_eq!(
+                solver.get_addresses(AddressKind::IPv4).await.unwrap(),
+                expected
+                    .iter()
+                    .map(|a| a.parse::<IpAddr>().unwrap())
+                    .collect::<Vec<_>>(),
+                "{selection:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_srv_targets() {
+        let addrs =
+            |addrs: &[&str]| -> Vec<IpAddr> { addrs.iter().map(|a| a.parse().unwrap()).collect() };
+        let mut solver = DnsHostname::with_resolver(
+            DnsHostnameConfig {
+                host: "_https._tcp.example.com".to_string(),
+                record_type: Some(DnsRecordType::Srv),
+                txt_pattern: None,
+                selection: None,
+            },
+            Box::new(MockResolver {
+                ips: [
+                    (
+                        "a.example.com.".to_string(),
+                        addrs(&["192.0.2.1", "2001:db8::1"]),
+                    ),
+                    ("b.example.com.".to_string(), addrs(&["192.0.2.1"])),
+                    ("c.example.com.".to_string(), addrs(&["192.0.2.3"])),
+                ]
+                .into(),
+                srv: [
+                    "a.example.com.",
+                    "b.example.com.",
+                    "c.example.com.",
+                    "a.example.com.",
+                ]
+                .map(str::to_string)
+                .to_vec(),
+                ..Default::default()
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            solver.get_addresses(AddressKind::IPv4).await.unwrap(),
+            addrs(&["192.0.2.1", "192.0.2.3"])
+        );
+        assert_eq!(
+            solver.get_addresses(AddressKind::IPv6).await.unwrap(),
+            addrs(&["2001:db8::1"])
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_failing_srv_targets() {
+        let srv_solver = |srv: &[&str]| {
+            DnsHostname::with_resolver(
+                DnsHostnameConfig {
+                    host: "_https._tcp.example.com".to_string(),
+                    record_type: Some(DnsRecordType::Srv),
+                    txt_pattern: None,
+                    selection: None,
+                },
+                Box::new(MockResolver {
+                    ips: [(
+                        "b.example.com.".to_string(),
+                        vec!["192.0.2.2".parse().unwrap()],
+                    )]
+                    .into(),
+                    srv: srv.iter().map(|t| t.to_string()).collect(),
+                    ..Default::default()
+                }),
+            )
+            .unwrap()
+        };
+        let mut solver = srv_solver(&["a.example.com.", "b.example.com."]);
+        assert_eq!(
+            solver.get_addresses(AddressKind::IPv4).await.unwrap(),
+            vec!["192.0.2.2".parse::<IpAddr>().unwrap()]
+        );
+
+        let mut solver = srv_solver(&["a.example.com.", "c.example.com."]);
+        let err = solver.get_addresses(AddressKind::IPv4).await.unwrap_err();
+        assert!(err.reason.contains("none of the SRV targets"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn skips_unrelated_txt_records() {
+        let mut solver = txt_solver(
+            &[
+                "v=spf1 -all",
+                "google-site-verification=abc123",
+                "192.0.2.300 192.0.2.1",
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            solver.get_addresses(AddressKind::IPv4).await.unwrap(),
+            vec!["192.0.2.1".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_txt_records_without_address() {
+        let mut solver = txt_solver(&["v=spf1 -all", "192.0.2.300"], None).unwrap();
+        let err = solver.get_addresses(AddressKind::IPv4).await.unwrap_err();
+        assert!(err.reason.contains("`192.0.2.300`"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn fails_queries_without_resolver() {
+        let mut solver = DnsHostname::with_resolver(
+            DnsHostnameConfig {
+                host: "egress.example.com".to_string(),
+                record_type: None,
+                txt_pattern: None,
+                selection: None,
+            },
+            Box::new(UnavailableResolver(SolverError {
+                reason: "could not read system DNS configuration".to_string(),
+            })),
+        )
+        .unwrap();
+        let err = solver.get_addresses(AddressKind::IPv4).await.unwrap_err();
+        assert!(err.reason.contains("system DNS configuration"), "{err}");
+    }
+
+    #[test]
+    fn rejects_pattern_without_placeholder() {
+        assert!(matches!(
+            txt_solver(&[], Some("ip=")),
+            Err(IpSourceError::Malformed(_))
+        ));
     }
 }
@@ -1,10 +1,9 @@
 use std::net::IpAddr;
 
 use async_trait::async_trait;
-use k8s_openapi::api::core::v1::Service;
 use tracing::instrument;
 
-use crate::external_ip_source::{self, registry::SolverRegistry, solvers::SolverError};
+use crate::external_ip_source::{self, solvers::SolverError};
 
 use super::Solver;
 
@@ -25,8 +24,6 @@ impl Solver for Interface {
     async fn get_addresses(
         &mut self,
         kind: external_ip_source::AddressKind,
-        _: &Service,
-        _: &SolverRegistry,
     ) -> Result<Vec<std::net::IpAddr>, SolverError> {
         Ok(match kind {
             external_ip_source::AddressKind::IPv4 => {
@@ -1,22 +1,191 @@
+use std::collections::BTreeMap;
 use std::net::IpAddr;
 use std::time::Instant;
 use std::{fmt::Debug, time::Duration};
 
 use async_trait::async_trait;
 use provider_myip::MyIp;
-use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::redirect::Policy;
+use reqwest::{Client, ClientBuilder, Identity, Proxy, StatusCode};
 
+use crate::crd::v1alpha1::is_sensitive_header;
+use crate::external_ip_source::IpSourceError;
 use crate::external_ip_source::solvers::SolverError;
 
 use super::{AddressKind, Solver};
 pub use solver::IpApiSolver;
 
+mod pinning;
 mod provider_ipify;
+mod provider_json_array;
+#[cfg(feature = "testing")]
+mod provider_mock;
 mod provider_myip;
 mod solver;
 
 use thiserror::Error;
 
+const USER_AGENT: &str = concat!("externalip-manager/", env!("CARGO_PKG_VERSION"));
+/// Total time allowed for a single request to an IP provider, including connecting
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Settings for the HTTP clients used by IP API solvers.
+///
+/// `connect_timeout` only covers establishing the connection (DNS, TCP and TLS) and should be shorter than [REQUEST_TIMEOUT],
+/// so that unreachable providers fail fast. `pool_idle_timeout` overrides how long idle connections are kept open.
+///
+/// Providers may redirect at most `max_redirects` times, so that a misconfigured or compromised endpoint can't send
+/// requests elsewhere freely. With `deny_private_redirects`, redirects to private, loopback or link-local addresses
+/// are refused as well. Only address literals are checked, as host names are resolved after the redirect is followed.
+///
+/// If `socks_proxy` is set, all requests are sent through it, see [socks_proxy].
+#[derive(Debug, Clone, Default)]
+pub struct HttpClientConfig {
+    pub connect_timeout: Option<Duration>,
+    pub pool_idle_timeout: Option<Duration>,
+    pub max_redirects: usize,
+    pub deny_private_redirects: bool,
+    pub socks_proxy: Option<Proxy>,
+}
+
+impl HttpClientConfig {
+    /// Build the HTTP client shared by all IP API solvers
+    pub fn build(&self) -> Client {
+        self.builder().build().expect("could not build HTTP client")
+    }
+
+    /// Build a dedicated client presenting `identity` to providers requiring mutual TLS
+    fn build_with_identity(&self, identity: Identity) -> Result<Client, reqwest::Error> {
+        self.builder().identity(identity).build()
+    }
+
+    fn builder(&self) -> ClientBuilder {
+        let mut builder = Client::builder()
+            .user_agent(USER_AGENT)
+            .redirect(self.redirect_policy());
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(pool_idle_timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(pool_idle_timeout);
+        }
+        if let Some(proxy) = &self.socks_proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        builder
+    }
+
+    fn redirect_policy(&self) -> Policy {
+        let max_redirects = self.max_redirects;
+        let deny_private = self.deny_private_redirects;
+        Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                return attempt.error(format!(
+                    "IP provider redirected more than {max_redirects} times"
+                ));
+            }
+            let target = attempt
+                .url()
+                .host_str()
+                .map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+                .and_then(|host| host.parse::<IpAddr>().ok());
+            match target {
+                Some(addr) if deny_private && is_private_address(&addr) => {
+                    attempt.error(format!("IP provider redirected to private address {addr}"))
+                }
+                _ => attempt.follow(),
+            }
+        })
+    }
+}
+
+/// Parse the URL of a SOCKS5 proxy, such as `socks5h://proxy:1080`.
+///
+/// With `socks5h`, host names are resolved by the proxy instead of locally.
+pub fn socks_proxy(url: &str) -> Result<Proxy, String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid proxy URL {url}: {e}"))?;
+    if !matches!(parsed.scheme(), "socks5" | "socks5h") || parsed.host_str().is_none() {
+        return Err(format!(
+            "expected a proxy URL such as socks5h://proxy:1080, got {url}"
+        ));
+    }
+    Proxy::all(parsed).map_err(|e| format!("invalid proxy URL {url}: {e}"))
+}
+
+/// Whether `addr` belongs to a private, loopback, link-local or unspecified range
+fn is_private_address(addr: &IpAddr) -> bool {
+    match addr.to_canonical() {
+        IpAddr::V4(v4) => {
+            v4.is_private() || v4.is_loopback() || v4.is_link_local() || v4.is_unspecified()
+        }
+        IpAddr::V6(v6) => {
+            v6.is_unique_local()
+                || v6.is_loopback()
+                || v6.is_unicast_link_local()
+                || v6.is_unspecified()
+        }
+    }
+}
+
+/// Load the PEM-encoded client certificate and key from disk
+fn read_client_certificate(
+    config: &crate::crd::v1alpha1::ClientCertificateConfig,
+) -> Result<(Vec<u8>, Vec<u8>), IpSourceError> {
+    let read = |path: &str| {
+        std::fs::read(path).map_err(|e| {
+            IpSourceError::Malformed(format!(
+                "unable to read client certificate file {path}: {e}"
+            ))
+        })
+    };
+    Ok((read(&config.cert_file)?, read(&config.key_file)?))
+}
+
+/// Load the client certificate and key from disk and build a dedicated client for mutual TLS
+fn build_mtls_client(
+    config: &crate::crd::v1alpha1::ClientCertificateConfig,
+    http_config: &HttpClientConfig,
+) -> Result<Client, IpSourceError> {
+    let (mut pem, key) = read_client_certificate(config)?;
+    pem.push(b'\n');
+    pem.extend(key);
+    let identity = Identity::from_pem(&pem)
+        .map_err(|e| IpSourceError::Malformed(format!("invalid client certificate or key: {e}")))?;
+    http_config
+        .build_with_identity(identity)
+        .map_err(|e| IpSourceError::Malformed(format!("unable to use client certificate: {e}")))
+}
+
+/// Convert user-supplied headers into a [HeaderMap], marking likely secrets as sensitive so they are redacted in logs
+fn build_headers(headers: &BTreeMap<String, String>) -> Result<HeaderMap, IpSourceError> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let header_name = HeaderName::try_from(name).map_err(|e| {
+                IpSourceError::Malformed(format!("invalid header name {name}: {e}"))
+            })?;
+            let mut header_value = HeaderValue::try_from(value).map_err(|e| {
+                IpSourceError::Malformed(format!("invalid value for header {name}: {e}"))
+            })?;
+            header_value.set_sensitive(is_sensitive_header(name));
+            Ok((header_name, header_value))
+        })
+        .collect()
+}
+
+/// Validate the user-supplied status codes with which a provider signals rate limiting
+fn ratelimit_status_codes(codes: &[u16]) -> Result<Vec<StatusCode>, IpSourceError> {
+    codes
+        .iter()
+        .map(|code| {
+            StatusCode::from_u16(*code).map_err(|e| {
+                IpSourceError::Malformed(format!("invalid ratelimit status code {code}: {e}"))
+            })
+        })
+        .collect()
+}
+
 #[derive(Debug, Error, Clone)]
 pub enum IpProviderError {
     #[error("rate limited by IP provider")]
@@ -69,6 +238,14 @@ impl IpProviderResponse {
             response,
         }
     }
+    /// Extend the response to be cached for at least `min_timeout`
+    fn with_min_timeout(mut self, min_timeout: Duration) -> IpProviderResponse {
+        if self.timeout < min_timeout {
+            self.timeout = min_timeout;
+            self.expires_at = self.timestamp + min_timeout;
+        }
+        self
+    }
     fn expired(&self) -> bool {
         self.expires_at < Instant::now()
     }
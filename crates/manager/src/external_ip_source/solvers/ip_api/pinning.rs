@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use reqwest::Client;
+use rustls::{
+    ClientConfig, DigitallySignedStruct, Error, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::aws_lc_rs,
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+};
+use rustls_platform_verifier::Verifier;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::{
+    crd::v1alpha1::ClientCertificateConfig,
+    external_ip_source::{
+        IpSourceError,
+        solvers::ip_api::{HttpClientConfig, read_client_certificate},
+    },
+};
+
+const PIN_PREFIX: &str = "sha256/";
+
+/// SHA-256 hash of the DER-encoded SubjectPublicKeyInfo of a certificate
+type PublicKeyPin = [u8; 32];
+
+/// Parse pins of the form `sha256/<base64>`, as used by curl's `--pinnedpubkey`
+pub(super) fn parse_pins(pins: &[String]) -> Result<Vec<PublicKeyPin>, IpSourceError> {
+    pins.iter()
+        .map(|pin| {
+            pin.strip_prefix(PIN_PREFIX)
+                .and_then(|hash| STANDARD.decode(hash).ok())
+                .and_then(|hash| PublicKeyPin::try_from(hash).ok())
+                .ok_or_else(|| {
+                    IpSourceError::Malformed(format!(
+                        "invalid public key pin {pin}, expected the base64-encoded SHA-256 hash of a public key such as sha256/{}",
+                        STANDARD.encode([0; 32])
+                    ))
+                })
+        })
+        .collect()
+}
+
+fn public_key_pin(cert: &CertificateDer<'_>) -> Result<PublicKeyPin, Error> {
+    let cert = webpki::EndEntityCert::try_from(cert)
+        .map_err(|e| Error::General(format!("unable to parse server certificate: {e}")))?;
+    Ok(Sha256::digest(cert.subject_public_key_info().as_ref()).into())
+}
+
+/// Verifies server certificates like the default client, but additionally requires the public key of the server
+/// certificate to match one of `pins`. Matching the public key instead of the certificate keeps pins valid when
+/// certificates are renewed with the same key.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Verifier,
+    pins: Vec<PublicKeyPin>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let pin = public_key_pin(end_entity)?;
+        if !self.pins.contains(&pin) {
+            let actual = format!("{PIN_PREFIX}{}", STANDARD.encode(pin));
+            warn!(msg = "certificate of IP provider does not match any pinned public key", server = %server_name.to_str(), actual);
+            return Err(Error::General(format!(
+                "public key of the server certificate ({actual}) does not match any pinned public key"
+            )));
+        }
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Build a dedicated client that only connects to servers whose public key matches one of `pins`,
+/// presenting `client_certificate` to providers requiring mutual TLS
+pub(super) fn build_pinned_client(
+    pins: Vec<PublicKeyPin>,
+    client_certificate: Option<&ClientCertificateConfig>,
+    http_config: &HttpClientConfig,
+) -> Result<Client, IpSourceError> {
+    let tls_error = |e: Error| IpSourceError::Malformed(format!("unable to set up TLS: {e}"));
+    let provider = Arc::new(aws_lc_rs::default_provider());
+    let verifier = PinnedVerifier {
+        inner: Verifier::new(provider.clone()).map_err(tls_error)?,
+        pins,
+    };
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+    let tls = match client_certificate {
+        Some(cert) => {
+            let (cert_pem, key_pem) = read_client_certificate(cert)?;
+            let invalid =
+                |e| IpSourceError::Malformed(format!("invalid client certificate or key: {e}"));
+            let certs = CertificateDer::pem_slice_iter(&cert_pem)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(invalid)?;
+            let key = PrivateKeyDer::from_pem_slice(&key_pem).map_err(invalid)?;
+            builder.with_client_auth_cert(certs, key).map_err(|e| {
+                IpSourceError::Malformed(format!("unable to use client certificate: {e}"))
+            })?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    http_config
+        .builder()
+        .tls_backend_preconfigured(tls)
+        .build()
+        .map_err(|e| IpSourceError::Malformed(format!("unable to build HTTP client: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pins() {
+        let hash = STANDARD.encode([7; 32]);
+        assert_eq!(
+            parse_pins(&[format!("sha256/{hash}")]).unwrap(),
+            vec![[7; 32]]
+        );
+        for invalid in [
+            hash.clone(),
+            format!("sha1/{hash}"),
+            "sha256/AAAA".to_string(),
+            "sha256/!".to_string(),
+        ] {
+            assert!(parse_pins(&[invalid]).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_certificates_not_matching_pin() {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["provider.example".to_string()])
+            .unwrap()
+            .self_signed(&key)
+            .unwrap();
+        let provider = Arc::new(aws_lc_rs::default_provider());
+        let verify = |pins: Vec<PublicKeyPin>| {
+            PinnedVerifier {
+                inner: Verifier::new(provider.clone()).unwrap(),
+                pins,
+            }
+            .verify_server_cert(
+                cert.der(),
+                &[],
+                &ServerName::try_from("provider.example").unwrap(),
+                &[],
+                UnixTime::now(),
+            )
+            .unwrap_err()
+            .to_string()
+        };
+
+        assert!(verify(vec![[0; 32]]).contains("does not match any pinned public key"));
+        // With a matching pin, the certificate is still verified as usual and rejected for being self-signed
+        let pin = Sha256::digest(rcgen::PublicKeyData::subject_public_key_info(&key)).into();
+        assert!(!verify(vec![pin]).contains("pinned"));
+    }
+}
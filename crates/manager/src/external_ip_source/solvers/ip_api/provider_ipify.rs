@@ -1,13 +1,13 @@
 use std::{net::IpAddr, time::Duration};
 
 use async_trait::async_trait;
-use reqwest::{Client, StatusCode};
+use reqwest::{Client, StatusCode, header::HeaderMap};
 use serde::Deserialize;
 use tracing::instrument;
 
 use crate::external_ip_source::{
     AddressKind,
-    solvers::ip_api::{IpProvider, IpProviderError, IpProviderResponse},
+    solvers::ip_api::{IpProvider, IpProviderError, IpProviderResponse, REQUEST_TIMEOUT},
 };
 
 const IPIFY_URL_V4: &str = "https://api.ipify.org?format=json";
@@ -21,10 +21,16 @@ struct IpifyResponse {
 }
 
 #[derive(Debug)]
-pub struct Ipify {}
+pub struct Ipify {
+    headers: HeaderMap,
+    ratelimit_status_codes: Vec<StatusCode>,
+}
 impl Ipify {
-    pub fn new() -> Ipify {
-        Ipify {}
+    pub fn new(headers: HeaderMap, ratelimit_status_codes: Vec<StatusCode>) -> Ipify {
+        Ipify {
+            headers,
+            ratelimit_status_codes,
+        }
     }
 }
 #[async_trait]
@@ -36,14 +42,15 @@ impl IpProvider for Ipify {
                 AddressKind::IPv4 => IPIFY_URL_V4,
                 AddressKind::IPv6 => IPIFY_URL_V6,
             })
-            .timeout(Duration::from_secs(10))
+            .headers(self.headers.clone())
+            .timeout(REQUEST_TIMEOUT)
             .send()
             .await
         {
             Ok(res) => res,
             Err(e) => return IpProviderResponse::new(CACHE_DURATION, Err(e.into())),
         };
-        if res.status() == StatusCode::TOO_MANY_REQUESTS {
+        if self.ratelimit_status_codes.contains(&res.status()) {
             return IpProviderResponse::new(CACHE_DURATION, Err(IpProviderError::RateLimited));
         }
         let body = match res.error_for_status() {
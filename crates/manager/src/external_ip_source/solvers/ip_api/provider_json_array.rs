@@ -0,0 +1,244 @@
+use std::{net::IpAddr, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode, header::HeaderMap};
+use tracing::instrument;
+
+use crate::external_ip_source::{
+    AddressKind,
+    solvers::ip_api::{IpProvider, IpProviderError, IpProviderResponse, REQUEST_TIMEOUT},
+};
+
+const CACHE_DURATION: Duration = Duration::from_secs(300);
+
+/// Generic provider for endpoints that return a JSON array of addresses, such as `["192.0.2.1", "2001:db8::1"]`
+#[derive(Debug)]
+pub struct JsonArray {
+    url: String,
+    headers: HeaderMap,
+    ratelimit_status_codes: Vec<StatusCode>,
+}
+impl JsonArray {
+    pub fn new(
+        url: String,
+        headers: HeaderMap,
+        ratelimit_status_codes: Vec<StatusCode>,
+    ) -> JsonArray {
+        JsonArray {
+            url,
+            headers,
+            ratelimit_status_codes,
+        }
+    }
+}
+
+/// Parse a JSON array of addresses, returning only those of the requested kind
+fn parse_addresses(body: &str, kind: AddressKind) -> Result<Vec<IpAddr>, IpProviderError> {
+    let addrs: Vec<IpAddr> =
+        serde_json::from_str(body).map_err(|e| IpProviderError::InvalidResponse(e.to_string()))?;
+    Ok(addrs
+        .into_iter()
+        .filter(|addr| match kind {
+            AddressKind::IPv4 => addr.is_ipv4(),
+            AddressKind::IPv6 => addr.is_ipv6(),
+        })
+        .collect())
+}
+
+#[async_trait]
+impl IpProvider for JsonArray {
+    #[instrument(skip(self, client))]
+    async fn get_addresses(&mut self, kind: AddressKind, client: &Client) -> IpProviderResponse {
+        let res = match client
+            .get(&self.url)
+            .headers(self.headers.clone())
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+        {
+            Ok(res) => res,
+            Err(e) => return IpProviderResponse::new(CACHE_DURATION, Err(e.into())),
+        };
+        if self.ratelimit_status_codes.contains(&res.status()) {
+            return IpProviderResponse::new(CACHE_DURATION, Err(IpProviderError::RateLimited));
+        }
+        let body = match res.error_for_status() {
+            Ok(body) => body,
+            Err(e) => return IpProviderResponse::new(CACHE_DURATION, Err(e.into())),
+        };
+        let body = match body.text().await {
+            Ok(body) => body,
+            Err(e) => return IpProviderResponse::new(CACHE_DURATION, Err(e.into())),
+        };
+        IpProviderResponse::new(CACHE_DURATION, parse_addresses(&body, kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::external_ip_source::{HttpClientConfig, socks_proxy};
+
+    #[test]
+    fn parses_address_array() {
+        let addrs = parse_addresses(r#"["192.0.2.1", "192.0.2.2"]"#, AddressKind::IPv4).unwrap();
+        assert_eq!(
+            addrs,
+            vec![
+                "192.0.2.1".parse::<IpAddr>().unwrap(),
+                "192.0.2.2".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_mixed_array_by_kind() {
+        let body = r#"["192.0.2.1", "2001:db8::1", "192.0.2.2", "2001:db8::2"]"#;
+        assert_eq!(
+            parse_addresses(body, AddressKind::IPv4).unwrap(),
+            vec![
+                "192.0.2.1".parse::<IpAddr>().unwrap(),
+                "192.0.2.2".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            parse_addresses(body, AddressKind::IPv6).unwrap(),
+            vec![
+                "2001:db8::1".parse::<IpAddr>().unwrap(),
+                "2001:db8::2".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_array() {
+        assert!(matches!(
+            parse_addresses(r#"["192.0.2.1", "not-an-ip"]"#, AddressKind::IPv4),
+            Err(IpProviderError::InvalidResponse(_))
+        ));
+    }
+
+    /// Serve a single raw HTTP response on a local port, returning its URL
+    async fn serve(response: String) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await;
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        url
+    }
+
+    /// Serve a single HTTP response with the given status on a local port, returning its URL
+    async fn serve_status(status: &'static str) -> String {
+        serve(format!("HTTP/1.1 {status}\r\ncontent-length: 0\r\n\r\n")).await
+    }
+
+    async fn query_with_codes(codes: &[u16]) -> Result<Vec<IpAddr>, IpProviderError> {
+        let url = serve_status("503 Service Unavailable").await;
+        let mut provider = JsonArray::new(
+            url,
+            HeaderMap::new(),
+            codes
+                .iter()
+                .map(|c| StatusCode::from_u16(*c).unwrap())
+                .collect(),
+        );
+        provider
+            .get_addresses(AddressKind::IPv4, &Client::new())
+            .await
+            .response
+    }
+
+    /// Query a provider that redirects once to an address array on a loopback address
+    async fn query_redirected(config: HttpClientConfig) -> Result<Vec<IpAddr>, IpProviderError> {
+        let body = r#"["192.0.2.1"]"#;
+        let target = serve(format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        ))
+        .await;
+        let url = serve(format!(
+            "HTTP/1.1 302 Found\r\nlocation: {target}\r\ncontent-length: 0\r\n\r\n"
+        ))
+        .await;
+        JsonArray::new(url, HeaderMap::new(), vec![])
+            .get_addresses(AddressKind::IPv4, &config.build())
+            .await
+            .response
+    }
+
+    #[tokio::test]
+    async fn honors_redirect_policy() {
+        assert!(matches!(
+            query_redirected(HttpClientConfig::default()).await,
+            Err(IpProviderError::RequestFailed(_))
+        ));
+        assert_eq!(
+            query_redirected(HttpClientConfig {
+                max_redirects: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+            vec!["192.0.2.1".parse::<IpAddr>().unwrap()]
+        );
+        assert!(matches!(
+            query_redirected(HttpClientConfig {
+                max_redirects: 1,
+                deny_private_redirects: true,
+                ..Default::default()
+            })
+            .await,
+            Err(IpProviderError::RequestFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sends_requests_through_socks_proxy() {
+        let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_url = format!("socks5h://{}", proxy.local_addr().unwrap());
+        let greeting = tokio::spawn(async move {
+            let (mut stream, _) = proxy.accept().await.unwrap();
+            stream.read_u8().await.unwrap()
+        });
+        let config = HttpClientConfig {
+            socks_proxy: Some(socks_proxy(&proxy_url).unwrap()),
+            ..Default::default()
+        };
+        let result = JsonArray::new(
+            "http://provider.invalid/".to_string(),
+            HeaderMap::new(),
+            vec![],
+        )
+        .get_addresses(AddressKind::IPv4, &config.build())
+        .await
+        .response;
+        assert!(result.is_err());
+        // SOCKS5 handshakes start with the protocol version
+        assert_eq!(greeting.await.unwrap(), 5);
+    }
+
+    #[test]
+    fn rejects_invalid_socks_proxy() {
+        for url in ["proxy:1080", "http://proxy:1080", "socks5://"] {
+            assert!(socks_proxy(url).is_err(), "{url} should be rejected");
+        }
+    }
+
+    #[tokio::test]
+    async fn treats_configured_status_as_ratelimit() {
+        assert!(matches!(
+            query_with_codes(&[429, 503]).await,
+            Err(IpProviderError::RateLimited)
+        ));
+        assert!(matches!(
+            query_with_codes(&[429]).await,
+            Err(IpProviderError::RequestFailed(_))
+        ));
+    }
+}
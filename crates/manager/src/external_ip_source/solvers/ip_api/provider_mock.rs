@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use tracing::instrument;
+
+use crate::external_ip_source::{
+    AddressKind,
+    solvers::ip_api::{IpProvider, IpProviderError, IpProviderResponse},
+};
+
+/// Rate limits every request after an optional delay, so that mock solvers go through the backoff of IP API solvers
+#[derive(Debug)]
+pub struct RateLimitedMock {
+    delay: Option<Duration>,
+    backoff: Duration,
+}
+impl RateLimitedMock {
+    pub fn new(delay: Option<Duration>, backoff: Duration) -> RateLimitedMock {
+        RateLimitedMock { delay, backoff }
+    }
+}
+#[async_trait]
+impl IpProvider for RateLimitedMock {
+    #[instrument(skip(self, _client))]
+    async fn get_addresses(&mut self, _kind: AddressKind, _client: &Client) -> IpProviderResponse {
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+        IpProviderResponse::new(self.backoff, Err(IpProviderError::RateLimited))
+    }
+}
@@ -1,13 +1,13 @@
 use std::{net::IpAddr, time::Duration};
 
 use async_trait::async_trait;
-use reqwest::{Client, StatusCode};
+use reqwest::{Client, StatusCode, header::HeaderMap};
 use serde::Deserialize;
 use tracing::instrument;
 
 use crate::external_ip_source::{
     AddressKind,
-    solvers::ip_api::{IpProvider, IpProviderError, IpProviderResponse},
+    solvers::ip_api::{IpProvider, IpProviderError, IpProviderResponse, REQUEST_TIMEOUT},
 };
 
 const MY_IP_URL_V4: &str = "https://api4.my-ip.io/v2/ip.json";
@@ -28,10 +28,16 @@ struct MyIpResponse {
 }
 
 #[derive(Debug)]
-pub struct MyIp {}
+pub struct MyIp {
+    headers: HeaderMap,
+    ratelimit_status_codes: Vec<StatusCode>,
+}
 impl MyIp {
-    pub fn new() -> MyIp {
-        MyIp {}
+    pub fn new(headers: HeaderMap, ratelimit_status_codes: Vec<StatusCode>) -> MyIp {
+        MyIp {
+            headers,
+            ratelimit_status_codes,
+        }
     }
 }
 #[async_trait]
@@ -43,14 +49,15 @@ impl IpProvider for MyIp {
                 AddressKind::IPv4 => MY_IP_URL_V4,
                 AddressKind::IPv6 => MY_IP_URL_V6,
             })
-            .timeout(Duration::from_secs(10))
+            .headers(self.headers.clone())
+            .timeout(REQUEST_TIMEOUT)
             .send()
             .await
         {
             Ok(res) => res,
             Err(e) => return IpProviderResponse::new(CACHE_DURATION, Err(e.into())),
         };
-        if res.status() == StatusCode::TOO_MANY_REQUESTS {
+        if self.ratelimit_status_codes.contains(&res.status()) {
             return IpProviderResponse::new(CACHE_DURATION, Err(IpProviderError::RateLimited));
         }
         let body = match res.error_for_status() {
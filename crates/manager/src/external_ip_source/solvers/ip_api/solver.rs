@@ -1,65 +1,235 @@
-use std::{fmt::Debug, time::Duration};
+use std::{fmt::Debug, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use k8s_openapi::api::core::v1::Service;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use reqwest::Client;
+use sha2::{Digest, Sha256};
 use tracing::{debug, info, instrument};
 
 use crate::{
     crd::v1alpha1,
     external_ip_source::{
-        self,
-        registry::SolverRegistry,
+        self, IpSourceError,
+        disk_cache::DiskCache,
         solvers::{
-            SolverError,
-            ip_api::{IpProviderResponse, provider_ipify::Ipify},
+            OutboundLimit, SolverCacheState, SolverError,
+            ip_api::{
+                HttpClientConfig, IpProviderResponse, build_headers, build_mtls_client,
+                pinning::{build_pinned_client, parse_pins},
+                provider_ipify::Ipify,
+                provider_json_array::JsonArray,
+                ratelimit_status_codes, read_client_certificate,
+            },
         },
     },
+    metrics::{Metrics, SolverLabels},
 };
 
 use super::{IpProvider, IpProviderError, MyIp, Solver};
 
 const RATELIMIT_BACKOFF_DURATION_MAX: Duration = Duration::from_secs(60 * 60 * 2);
+const METRICS_LABELS: SolverLabels = SolverLabels { solver: "ipAPI" };
 
 #[derive(Debug)]
 pub struct IpApiSolver {
     client: Client,
+    /// Providers in the order they are tried, see [v1alpha1::IpAPIConfig::fallback_providers]
+    providers: Vec<ProviderState>,
+    disk_cache: Option<Arc<DiskCache>>,
+    /// Identifies this solver in the disk cache
+    cache_key: String,
+    metrics: Option<Arc<Metrics>>,
+    outbound_limit: OutboundLimit,
+    /// Lower bound for the cache duration of provider responses
+    min_cache_ttl: Duration,
+}
+
+/// A provider of an [IpApiSolver] along with its last response, so that each provider is cached and backs off separately
+#[derive(Debug)]
+struct ProviderState {
     inner: Box<dyn IpProvider>,
     cache: Option<IpProviderResponse>,
 }
 
+impl ProviderState {
+    fn new(inner: Box<dyn IpProvider>) -> ProviderState {
+        ProviderState { inner, cache: None }
+    }
+}
+
+/// Key identifying the answers of a solver in the disk cache and metrics.
+/// The Debug representation redacts secret headers, so configs that only differ in an API key are told apart by a
+/// hash of the full config instead, which is also safe to be written to disk
+fn cache_key(config: &v1alpha1::IpAPIConfig) -> String {
+    let serialized = serde_json::to_vec(config).expect("IpAPIConfig is always serializable");
+    format!(
+        "ipAPI/{}",
+        URL_SAFE_NO_PAD.encode(Sha256::digest(serialized))
+    )
+}
+
 impl IpApiSolver {
-    pub fn new(provider: v1alpha1::IpSolverProvider) -> IpApiSolver {
-        let inner: Box<dyn IpProvider> = match provider {
-            v1alpha1::IpSolverProvider::MyIp => Box::new(MyIp::new()),
-            v1alpha1::IpSolverProvider::Ipify => Box::new(Ipify::new()),
+    /// Check the settings of `config` that would make building the solver fail, such as invalid headers, pins or
+    /// unreadable client certificate files, so that sources using it can be rejected when they are parsed
+    pub fn validate(config: &v1alpha1::IpAPIConfig) -> Result<(), IpSourceError> {
+        build_headers(&config.headers)?;
+        ratelimit_status_codes(&config.ratelimit_status_codes)?;
+        parse_pins(&config.pinned_public_keys)?;
+        if let Some(cert) = &config.client_certificate {
+            read_client_certificate(cert)?;
+        }
+        let uses_json_array = std::iter::once(&config.provider)
+            .chain(&config.fallback_providers)
+            .any(|provider| *provider == v1alpha1::IpSolverProvider::JsonArray);
+        if uses_json_array && config.url.is_none() {
+            return Err(IpSourceError::Malformed(
+                "the jsonArray provider requires a url".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Create a new solver. Solvers with a client certificate or pinned public keys get a dedicated client built
+    /// from `http_config`, all others use the shared `client`
+    pub fn new(
+        config: v1alpha1::IpAPIConfig,
+        client: Client,
+        http_config: &HttpClientConfig,
+    ) -> Result<IpApiSolver, IpSourceError> {
+        let cache_key = cache_key(&config);
+        let headers = build_headers(&config.headers)?;
+        let ratelimit_codes = ratelimit_status_codes(&config.ratelimit_status_codes)?;
+        let pins = parse_pins(&config.pinned_public_keys)?;
+        let client = match &config.client_certificate {
+            _ if !pins.is_empty() => {
+                build_pinned_client(pins, config.client_certificate.as_ref(), http_config)?
+            }
+            Some(cert) => build_mtls_client(cert, http_config)?,
+            None => client,
         };
+        let providers = std::iter::once(config.provider)
+            .chain(config.fallback_providers.iter().copied())
+            .map(|provider| {
+                let inner: Box<dyn IpProvider> = match provider {
+                    v1alpha1::IpSolverProvider::MyIp => {
+                        Box::new(MyIp::new(headers.clone(), ratelimit_codes.clone()))
+                    }
+                    v1alpha1::IpSolverProvider::Ipify => {
+                        Box::new(Ipify::new(headers.clone(), ratelimit_codes.clone()))
+                    }
+                    v1alpha1::IpSolverProvider::JsonArray => {
+                        let url = config.url.clone().ok_or(IpSourceError::Malformed(
+                            "the jsonArray provider requires a url".to_string(),
+                        ))?;
+                        Box::new(JsonArray::new(
+                            url,
+                            headers.clone(),
+                            ratelimit_codes.clone(),
+                        ))
+                    }
+                };
+                Ok(ProviderState::new(inner))
+            })
+            .collect::<Result<Vec<_>, IpSourceError>>()?;
+        Ok(IpApiSolver {
+            client,
+            providers,
+            disk_cache: None,
+            cache_key,
+            metrics: None,
+            outbound_limit: OutboundLimit::default(),
+            min_cache_ttl: Duration::ZERO,
+        })
+    }
+
+    /// Persist successful responses in `disk_cache`, so that they can be reused after a restart
+    pub fn with_disk_cache(mut self, disk_cache: Option<Arc<DiskCache>>) -> IpApiSolver {
+        self.disk_cache = disk_cache;
+        self
+    }
+
+    /// Record cache hits, misses and rate limit backoff in `metrics`
+    pub fn with_metrics(mut self, metrics: Option<Arc<Metrics>>) -> IpApiSolver {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Wait for `outbound_limit` before querying the provider
+    pub fn with_outbound_limit(mut self, outbound_limit: OutboundLimit) -> IpApiSolver {
+        self.outbound_limit = outbound_limit;
+        self
+    }
+
+    /// Cache responses for at least `min_cache_ttl`, even if the provider would allow querying it again earlier
+    pub fn with_min_cache_ttl(mut self, min_cache_ttl: Duration) -> IpApiSolver {
+        self.min_cache_ttl = min_cache_ttl;
+        self
+    }
+
+    /// Create a solver whose only provider rate limits every request after `delay`, backing off for `backoff` at first
+    #[cfg(feature = "testing")]
+    pub fn rate_limited_mock(delay: Option<Duration>, backoff: Duration) -> IpApiSolver {
         IpApiSolver {
-            client: Client::new(),
-            inner,
-            cache: None,
+            client: HttpClientConfig::default().build(),
+            providers: vec![ProviderState::new(Box::new(
+                super::provider_mock::RateLimitedMock::new(delay, backoff),
+            ))],
+            disk_cache: None,
+            cache_key: "mock".to_string(),
+            metrics: None,
+            outbound_limit: OutboundLimit::default(),
+            min_cache_ttl: Duration::ZERO,
         }
     }
+
+    fn record_cache_hit(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .solver_cache_hits
+                .get_or_create(&METRICS_LABELS)
+                .inc();
+        }
+    }
+
     #[cfg(test)]
     fn with_test_provider(inner: Box<dyn IpProvider>) -> IpApiSolver {
+        IpApiSolver::with_test_providers(vec![inner])
+    }
+
+    #[cfg(test)]
+    fn with_test_providers(providers: Vec<Box<dyn IpProvider>>) -> IpApiSolver {
         IpApiSolver {
-            client: Client::new(),
-            inner,
-            cache: None,
+            client: HttpClientConfig::default().build(),
+            providers: providers.into_iter().map(ProviderState::new).collect(),
+            disk_cache: None,
+            cache_key: "test".to_string(),
+            metrics: None,
+            outbound_limit: OutboundLimit::default(),
+            min_cache_ttl: Duration::ZERO,
         }
     }
 }
 
-#[async_trait]
-impl Solver for IpApiSolver {
-    #[instrument(skip(self))]
-    async fn get_addresses(
+impl IpApiSolver {
+    /// Query the provider at `index`, reusing its cached response or backing off after it rate limited us
+    async fn query_provider(
         &mut self,
+        index: usize,
         kind: external_ip_source::AddressKind,
-        _: &Service,
-        _: &SolverRegistry,
     ) -> Result<Vec<std::net::IpAddr>, SolverError> {
-        if let Some(cached) = &self.cache
+        // Each provider has its own disk cache entry, so that answers of a fallback don't mask a failing primary after a restart
+        let disk_key = format!("{}/{kind}/{index}", self.cache_key);
+        if self.providers[index].cache.is_none()
+            && let Some(disk_cache) = &self.disk_cache
+            && let Some((addrs, remaining)) = disk_cache.get(&disk_key)
+        {
+            debug!(
+                msg = "restored IP API response from disk cache",
+                cache_remaining_secs = remaining.as_secs()
+            );
+            self.providers[index].cache = Some(IpProviderResponse::new(remaining, Ok(addrs)));
+        }
+        if let Some(cached) = &self.providers[index].cache
             && !cached.expired()
         {
             match &cached.response {
@@ -68,22 +238,42 @@ impl Solver for IpApiSolver {
                         msg = "reusing cached addresses for IP API",
                         cache_remaining_secs = cached.remaining().as_secs()
                     );
-                    return Ok(addrs.clone());
+                    let addrs = addrs.clone();
+                    self.record_cache_hit();
+                    return Ok(addrs);
                 }
                 Err(e) => {
                     if matches!(e, IpProviderError::RateLimited) {
                         debug!(msg = "respecting cached ratelimit response", resp = ?e);
-                        return Err(e.into());
+                        let err = e.into();
+                        self.record_cache_hit();
+                        return Err(err);
                     }
                 }
             };
         }
 
-        let resp = self.inner.get_addresses(kind, &self.client).await;
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .solver_cache_misses
+                .get_or_create(&METRICS_LABELS)
+                .inc();
+        }
+        let provider = &mut self.providers[index];
+        let resp = self
+            .outbound_limit
+            .run(provider.inner.get_addresses(kind, &self.client))
+            .await
+            .with_min_timeout(self.min_cache_ttl);
         let (res, cache) = match &resp.response {
-            Ok(addrs) => (Ok(addrs.clone()), Some(resp.clone())),
+            Ok(addrs) => {
+                if let Some(disk_cache) = &self.disk_cache {
+                    disk_cache.set(&disk_key, addrs.clone(), resp.timeout);
+                }
+                (Ok(addrs.clone()), Some(resp.clone()))
+            }
             Err(e) if matches!(e, IpProviderError::RateLimited) => {
-                if let Some(cached) = &self.cache
+                if let Some(cached) = &provider.cache
                     && matches!(&cached.response, Err(IpProviderError::RateLimited))
                 {
                     // Exponential backoff
@@ -102,15 +292,75 @@ impl Solver for IpApiSolver {
             }
             Err(e) => (Err(e.into()), None),
         };
-        self.cache = cache;
+        provider.cache = cache;
         res
     }
+
+    /// Longest remaining backoff of any provider that rate limited us, [Duration::ZERO] if none is backing off
+    fn remaining_backoff(&self) -> Duration {
+        self.providers
+            .iter()
+            .filter_map(|provider| provider.cache.as_ref())
+            .filter(|cached| {
+                !cached.expired() && matches!(cached.response, Err(IpProviderError::RateLimited))
+            })
+            .map(|cached| cached.remaining())
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl Solver for IpApiSolver {
+    #[instrument(skip(self))]
+    async fn get_addresses(
+        &mut self,
+        kind: external_ip_source::AddressKind,
+    ) -> Result<Vec<std::net::IpAddr>, SolverError> {
+        let mut result = None;
+        for index in 0..self.providers.len() {
+            match self.query_provider(index, kind).await {
+                Ok(addrs) => {
+                    result = Some(Ok(addrs));
+                    break;
+                }
+                Err(e) => {
+                    if index + 1 < self.providers.len() {
+                        info!(msg = "IP API provider failed, trying the next one", err = %e);
+                    }
+                    result = Some(Err(e));
+                }
+            }
+        }
+        // Reported once for all providers after every query, including ones answered from the cache,
+        // so that a fallback answering doesn't hide the backoff of the primary
+        if let Some(metrics) = &self.metrics {
+            metrics.set_solver_backoff(
+                &METRICS_LABELS,
+                &format!("{}/{kind}", self.cache_key),
+                self.remaining_backoff(),
+            );
+        }
+        result.expect("IP API solvers have at least one provider")
+    }
+
+    fn cache_state(&self) -> Option<SolverCacheState> {
+        // The first provider with a valid cache is the one answering queries
+        let cached = self
+            .providers
+            .iter()
+            .filter_map(|provider| provider.cache.as_ref())
+            .find(|cached| !cached.expired())?;
+        Some(SolverCacheState {
+            cached_addresses: cached.response.clone().unwrap_or_default(),
+            remaining_secs: Some(cached.remaining().as_secs()),
+            backing_off: matches!(cached.response, Err(IpProviderError::RateLimited)),
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
-
     use anyhow::Result;
 
     use crate::external_ip_source::AddressKind;
@@ -142,6 +392,90 @@ mod tests {
         }
     }
 
+    #[test]
+    fn rejects_invalid_headers() {
+        let res = IpApiSolver::new(
+            v1alpha1::IpAPIConfig {
+                fallback_providers: vec![],
+                provider: v1alpha1::IpSolverProvider::MyIp,
+                headers: [("X-Api-Key".to_string(), "line\nbreak".to_string())].into(),
+                url: None,
+                ratelimit_status_codes: vec![429],
+                client_certificate: None,
+                pinned_public_keys: vec![],
+            },
+            HttpClientConfig::default().build(),
+            &HttpClientConfig::default(),
+        );
+        assert!(matches!(res, Err(IpSourceError::Malformed(_))));
+    }
+
+    #[test]
+    fn cache_key_covers_secret_headers() {
+        let config = |key: &str| v1alpha1::IpAPIConfig {
+            fallback_providers: vec![],
+            provider: v1alpha1::IpSolverProvider::MyIp,
+            headers: [("Authorization".to_string(), key.to_string())].into(),
+            url: None,
+            ratelimit_status_codes: vec![429],
+            client_certificate: None,
+            pinned_public_keys: vec![],
+        };
+        assert_eq!(format!("{:?}", config("a")), format!("{:?}", config("b")));
+        assert_eq!(cache_key(&config("a")), cache_key(&config("a")));
+        assert_ne!(cache_key(&config("a")), cache_key(&config("b")));
+        assert!(!cache_key(&config("secret")).contains("secret"));
+    }
+
+    #[test]
+    fn json_array_requires_url() {
+        let res = IpApiSolver::new(
+            v1alpha1::IpAPIConfig {
+                fallback_providers: vec![],
+                provider: v1alpha1::IpSolverProvider::JsonArray,
+                headers: Default::default(),
+                url: None,
+                ratelimit_status_codes: vec![429],
+                client_certificate: None,
+                pinned_public_keys: vec![],
+            },
+            HttpClientConfig::default().build(),
+            &HttpClientConfig::default(),
+        );
+        assert!(matches!(res, Err(IpSourceError::Malformed(_))));
+    }
+
+    #[test]
+    fn rejects_invalid_client_certificate() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "externalip-manager-ipapi-mtls-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let cert_file = dir.join("tls.crt");
+        let key_file = dir.join("tls.key");
+        std::fs::write(&cert_file, "not a certificate")?;
+        std::fs::write(&key_file, "not a key")?;
+        let res = IpApiSolver::new(
+            v1alpha1::IpAPIConfig {
+                fallback_providers: vec![],
+                provider: v1alpha1::IpSolverProvider::MyIp,
+                headers: Default::default(),
+                url: None,
+                ratelimit_status_codes: vec![429],
+                client_certificate: Some(v1alpha1::ClientCertificateConfig {
+                    cert_file: cert_file.to_string_lossy().into_owned(),
+                    key_file: key_file.to_string_lossy().into_owned(),
+                }),
+                pinned_public_keys: vec![],
+            },
+            HttpClientConfig::default().build(),
+            &HttpClientConfig::default(),
+        );
+        assert!(matches!(res, Err(IpSourceError::Malformed(_))));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn uses_cache() -> Result<()> {
         let expected = vec!["0.0.0.0".parse().unwrap()];
@@ -149,18 +483,46 @@ mod tests {
             IpProviderResponse::new(CACHE_TIMEOUT, Ok(expected.clone())),
             IpProviderResponse::new(CACHE_TIMEOUT, Ok(vec!["1.1.1.1".parse().unwrap()])),
         ])));
-        let result = solv
-            .get_addresses(AddressKind::IPv4, &Service::default(), &HashMap::default())
-            .await?;
+        let result = solv.get_addresses(AddressKind::IPv4).await?;
         assert_eq!(result, expected);
         // Second call should reuse cached address
-        let result = solv
-            .get_addresses(AddressKind::IPv4, &Service::default(), &HashMap::default())
-            .await?;
+        let result = solv.get_addresses(AddressKind::IPv4).await?;
         assert_eq!(result, expected);
         Ok(())
     }
 
+    #[tokio::test]
+    async fn records_cache_metrics() -> Result<()> {
+        let metrics = Arc::new(Metrics::default());
+        let mut solv = IpApiSolver::with_test_provider(Box::new(MockSolver::new(vec![
+            IpProviderResponse::new(CACHE_TIMEOUT, Err(IpProviderError::RateLimited)),
+        ])))
+        .with_metrics(Some(metrics.clone()));
+        assert!(solv.get_addresses(AddressKind::IPv4).await.is_err());
+        // Backing off counts as a hit, as the provider isn't queried
+        assert!(solv.get_addresses(AddressKind::IPv4).await.is_err());
+        assert_eq!(
+            metrics
+                .solver_cache_misses
+                .get_or_create(&METRICS_LABELS)
+                .get(),
+            1
+        );
+        assert_eq!(
+            metrics
+                .solver_cache_hits
+                .get_or_create(&METRICS_LABELS)
+                .get(),
+            1
+        );
+        let backoff = metrics
+            .solver_backoff_seconds
+            .get_or_create(&METRICS_LABELS)
+            .get();
+        assert!(backoff > 0.0 && backoff <= CACHE_TIMEOUT.as_secs_f64());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn cache_invalidates() -> Result<()> {
         let expected = vec!["0.0.0.0".parse().unwrap()];
@@ -169,13 +531,71 @@ mod tests {
             IpProviderResponse::new(CACHE_TIMEOUT, Ok(expected.clone())),
         ])));
         // first call to fill cache
-        solv.get_addresses(AddressKind::IPv4, &Service::default(), &HashMap::default())
-            .await?;
+        solv.get_addresses(AddressKind::IPv4).await?;
         tokio::time::sleep(CACHE_TIMEOUT + SLEEP_EXTRA).await;
         // Call after sleep should be second address
-        let result = solv
-            .get_addresses(AddressKind::IPv4, &Service::default(), &HashMap::default())
-            .await?;
+        let result = solv.get_addresses(AddressKind::IPv4).await?;
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn floors_cache_duration() -> Result<()> {
+        let expected = vec!["0.0.0.0".parse().unwrap()];
+        let mut solv = IpApiSolver::with_test_provider(Box::new(MockSolver::new(vec![
+            IpProviderResponse::new(CACHE_TIMEOUT, Ok(expected.clone())),
+            IpProviderResponse::new(CACHE_TIMEOUT, Ok(vec!["1.1.1.1".parse().unwrap()])),
+        ])))
+        .with_min_cache_ttl(Duration::from_secs(60));
+        solv.get_addresses(AddressKind::IPv4).await?;
+        tokio::time::sleep(CACHE_TIMEOUT + SLEEP_EXTRA).await;
+        // The provider's cache duration has passed, but not the floor
+        let result = solv.get_addresses(AddressKind::IPv4).await?;
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_cache_state() -> Result<()> {
+        let expected = vec!["0.0.0.0".parse().unwrap()];
+        let mut solv = IpApiSolver::with_test_provider(Box::new(MockSolver::new(vec![
+            IpProviderResponse::new(Duration::from_secs(60), Err(IpProviderError::RateLimited)),
+            IpProviderResponse::new(Duration::from_secs(60), Ok(expected.clone())),
+        ])));
+        assert_eq!(solv.cache_state(), None);
+        assert!(solv.get_addresses(AddressKind::IPv4).await.is_err());
+        let state = solv.cache_state().unwrap();
+        assert!(state.backing_off);
+        assert!(state.cached_addresses.is_empty());
+
+        solv.providers[0].cache = None;
+        solv.get_addresses(AddressKind::IPv4).await?;
+        let state = solv.cache_state().unwrap();
+        assert!(!state.backing_off);
+        assert_eq!(state.cached_addresses, expected);
+        assert!(state.remaining_secs.is_some_and(|secs| secs > 50));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restores_from_disk_cache() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "externalip-manager-ipapi-cache-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let expected = vec!["0.0.0.0".parse().unwrap()];
+        let disk_cache = Arc::new(DiskCache::load(&dir));
+        let mut solv = IpApiSolver::with_test_provider(Box::new(MockSolver::new(vec![
+            IpProviderResponse::new(Duration::from_secs(60), Ok(expected.clone())),
+        ])))
+        .with_disk_cache(Some(disk_cache));
+        solv.get_addresses(AddressKind::IPv4).await?;
+
+        // A new solver, as after a restart, must not query the provider again
+        let mut restarted = IpApiSolver::with_test_provider(Box::new(MockSolver::new(vec![])))
+            .with_disk_cache(Some(Arc::new(DiskCache::load(&dir))));
+        let result = restarted.get_addresses(AddressKind::IPv4).await?;
         assert_eq!(result, expected);
         Ok(())
     }
@@ -185,9 +605,7 @@ mod tests {
         let mut solv = IpApiSolver::with_test_provider(Box::new(MockSolver::new(vec![
             IpProviderResponse::new(CACHE_TIMEOUT, Err(IpProviderError::RateLimited)),
         ])));
-        let result = solv
-            .get_addresses(AddressKind::IPv4, &Service::default(), &HashMap::default())
-            .await;
+        let result = solv.get_addresses(AddressKind::IPv4).await;
         assert!(result.is_err());
         Ok(())
     }
@@ -199,20 +617,14 @@ mod tests {
             IpProviderResponse::new(CACHE_TIMEOUT, Err(IpProviderError::RateLimited)),
             IpProviderResponse::new(CACHE_TIMEOUT, Ok(expected.clone())),
         ])));
-        let result = solv
-            .get_addresses(AddressKind::IPv4, &Service::default(), &HashMap::default())
-            .await;
+        let result = solv.get_addresses(AddressKind::IPv4).await;
         assert!(result.is_err());
         // Immediate second query, should still return a rate limit error
-        let result = solv
-            .get_addresses(AddressKind::IPv4, &Service::default(), &HashMap::default())
-            .await;
+        let result = solv.get_addresses(AddressKind::IPv4).await;
         assert!(result.is_err());
         tokio::time::sleep(CACHE_TIMEOUT + SLEEP_EXTRA).await;
         // After waitlimit timeout, the request succeeds
-        let result = solv
-            .get_addresses(AddressKind::IPv4, &Service::default(), &HashMap::default())
-            .await?;
+        let result = solv.get_addresses(AddressKind::IPv4).await?;
         assert_eq!(result, expected);
         Ok(())
     }
@@ -226,18 +638,87 @@ mod tests {
             IpProviderResponse::new(CACHE_TIMEOUT, Ok(expected.clone())),
         ])));
         // Trigger ratelimit
-        let result = solv
-            .get_addresses(AddressKind::IPv4, &Service::default(), &HashMap::default())
-            .await;
+        let result = solv.get_addresses(AddressKind::IPv4).await;
         assert!(result.is_err());
         tokio::time::sleep(CACHE_TIMEOUT + SLEEP_EXTRA).await;
         // Still ratelimited
-        let result = solv
-            .get_addresses(AddressKind::IPv4, &Service::default(), &HashMap::default())
-            .await;
+        let result = solv.get_addresses(AddressKind::IPv4).await;
         assert!(result.is_err());
         // Ratelimited with exponential backoff
-        assert!(solv.cache.expect("should have cached ratelimit").timeout == CACHE_TIMEOUT * 2);
+        assert!(
+            solv.providers[0]
+                .cache
+                .clone()
+                .expect("should have cached ratelimit")
+                .timeout
+                == CACHE_TIMEOUT * 2
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_next_provider() -> Result<()> {
+        let expected = vec!["0.0.0.0".parse().unwrap()];
+        let metrics = Arc::new(Metrics::default());
+        let mut solv = IpApiSolver::with_test_providers(vec![
+            Box::new(MockSolver::new(vec![IpProviderResponse::new(
+                CACHE_TIMEOUT,
+                Err(IpProviderError::RateLimited),
+            )])),
+            Box::new(MockSolver::new(vec![IpProviderResponse::new(
+                CACHE_TIMEOUT,
+                Ok(expected.clone()),
+            )])),
+        ])
+        .with_metrics(Some(metrics.clone()));
+        let result = solv.get_addresses(AddressKind::IPv4).await?;
+        assert_eq!(result, expected);
+        // The backoff of the first provider is still reported although the second one answered
+        assert!(
+            metrics
+                .solver_backoff_seconds
+                .get_or_create(&METRICS_LABELS)
+                .get()
+                > 0.0
+        );
+        // Both responses are cached, so neither provider is queried again
+        let result = solv.get_addresses(AddressKind::IPv4).await?;
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn restores_answers_of_each_provider_separately() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "externalip-manager-ipapi-fallback-cache-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let fallback = vec!["0.0.0.1".parse().unwrap()];
+        let mut solv = IpApiSolver::with_test_providers(vec![
+            Box::new(MockSolver::new(vec![IpProviderResponse::new(
+                CACHE_TIMEOUT,
+                Err(IpProviderError::RateLimited),
+            )])),
+            Box::new(MockSolver::new(vec![IpProviderResponse::new(
+                Duration::from_secs(60),
+                Ok(fallback.clone()),
+            )])),
+        ])
+        .with_disk_cache(Some(Arc::new(DiskCache::load(&dir))));
+        assert_eq!(solv.get_addresses(AddressKind::IPv4).await?, fallback);
+
+        // After a restart, the first provider is asked again instead of being masked by the answer of the fallback
+        let expected = vec!["0.0.0.0".parse().unwrap()];
+        let mut restarted = IpApiSolver::with_test_providers(vec![
+            Box::new(MockSolver::new(vec![IpProviderResponse::new(
+                Duration::from_secs(60),
+                Ok(expected.clone()),
+            )])),
+            Box::new(MockSolver::new(vec![])),
+        ])
+        .with_disk_cache(Some(Arc::new(DiskCache::load(&dir))));
+        assert_eq!(restarted.get_addresses(AddressKind::IPv4).await?, expected);
         Ok(())
     }
 }
@@ -0,0 +1,104 @@
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::Service;
+use tracing::instrument;
+
+use super::ServiceSolver;
+use crate::external_ip_source::{self, registry::SolverRegistry, solvers::SolverError};
+
+/// Returns the addresses currently in `spec.externalIPs` of the service.
+///
+/// Lets the manager take ownership of existing addresses without changing them, or use them as a baseline for other solvers.
+#[derive(Debug)]
+pub struct KeepCurrent {}
+
+impl KeepCurrent {
+    pub fn new() -> KeepCurrent {
+        KeepCurrent {}
+    }
+}
+
+/// All addresses in the `spec.externalIPs` field of `svc`
+fn current_addresses(svc: &Service) -> Result<Vec<IpAddr>, SolverError> {
+    svc.spec
+        .as_ref()
+        .and_then(|spec| spec.external_ips.as_ref())
+        .into_iter()
+        .flatten()
+        .map(|addr| {
+            addr.parse::<IpAddr>().map_err(|e| SolverError {
+                reason: format!("service has invalid externalIP `{addr}`: {e}"),
+            })
+        })
+        .collect()
+}
+
+#[async_trait]
+impl ServiceSolver for KeepCurrent {
+    #[instrument]
+    async fn get_addresses(
+        &self,
+        kind: external_ip_source::AddressKind,
+        svc: &Service,
+        _: &SolverRegistry,
+    ) -> Result<Vec<IpAddr>, SolverError> {
+        Ok(current_addresses(svc)?
+            .into_iter()
+            .filter(|addr| kind.matches(addr))
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_all_addresses(
+        &self,
+        svc: &Service,
+        _: &SolverRegistry,
+    ) -> Option<Result<Vec<IpAddr>, SolverError>> {
+        Some(current_addresses(svc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use external_ip_source::AddressKind;
+    use k8s_openapi::api::core::v1::ServiceSpec;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_current_addresses_of_family() {
+        let svc = Service {
+            spec: Some(ServiceSpec {
+                external_ips: Some(vec!["192.0.2.1".to_string(), "2001:db8::1".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let solver = KeepCurrent::new();
+        let registry = HashMap::new();
+        assert_eq!(
+            solver
+                .get_addresses(AddressKind::IPv4, &svc, &registry)
+                .await
+                .unwrap(),
+            vec!["192.0.2.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(
+            solver
+                .get_addresses(AddressKind::IPv6, &svc, &registry)
+                .await
+                .unwrap(),
+            vec!["2001:db8::1".parse::<IpAddr>().unwrap()]
+        );
+        assert!(
+            solver
+                .get_addresses(AddressKind::IPv4, &Service::default(), &registry)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -1,11 +1,11 @@
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::IpAddr;
 
 use async_trait::async_trait;
 use itertools::Itertools;
 use k8s_openapi::api::core::v1::Service;
 use tracing::instrument;
 
-use super::Solver;
+use super::ServiceSolver;
 use crate::external_ip_source::{self, registry::SolverRegistry, solvers::SolverError};
 
 #[derive(Debug)]
@@ -17,40 +17,50 @@ impl LoadBalancerIngress {
     }
 }
 
+/// All addresses in the `.status.loadBalancer.ingress` field of `svc`
+fn ingress_addresses(svc: &Service) -> Result<Vec<IpAddr>, SolverError> {
+    Ok(svc
+        .status
+        .as_ref()
+        .ok_or(SolverError {
+            reason: "no status field on service".to_string(),
+        })?
+        .load_balancer
+        .as_ref()
+        .ok_or(SolverError {
+            reason: "no status.loadBalancer field on service".to_string(),
+        })?
+        .ingress
+        .as_ref()
+        .ok_or(SolverError {
+            reason: "no status.loadBalancer.ingress field on service".to_string(),
+        })?
+        .iter()
+        .filter_map(|ingress| ingress.ip.as_ref()?.parse::<IpAddr>().ok())
+        .collect_vec())
+}
+
 #[async_trait]
-impl Solver for LoadBalancerIngress {
+impl ServiceSolver for LoadBalancerIngress {
     #[instrument]
     async fn get_addresses(
-        &mut self,
+        &self,
         kind: external_ip_source::AddressKind,
         svc: &Service,
         _: &SolverRegistry,
     ) -> Result<Vec<std::net::IpAddr>, SolverError> {
-        Ok(svc
-            .clone()
-            .status
-            .ok_or(SolverError {
-                reason: "no status field on service".to_string(),
-            })?
-            .load_balancer
-            .ok_or(SolverError {
-                reason: "no status.loadBalancer field on service".to_string(),
-            })?
-            .ingress
-            .ok_or(SolverError {
-                reason: "no status.loadBalancer.ingress field on service".to_string(),
-            })?
-            .iter()
-            .filter_map(|addr| {
-                addr.clone().ip.and_then(|addr_string| match kind {
-                    external_ip_source::AddressKind::IPv4 => {
-                        addr_string.parse::<Ipv4Addr>().ok().map(IpAddr::V4)
-                    }
-                    external_ip_source::AddressKind::IPv6 => {
-                        addr_string.parse::<Ipv6Addr>().ok().map(IpAddr::V6)
-                    }
-                })
-            })
+        Ok(ingress_addresses(svc)?
+            .into_iter()
+            .filter(|addr| kind.matches(addr))
             .collect_vec())
     }
+
+    #[instrument]
+    async fn get_all_addresses(
+        &self,
+        svc: &Service,
+        _: &SolverRegistry,
+    ) -> Option<Result<Vec<IpAddr>, SolverError>> {
+        Some(ingress_addresses(svc))
+    }
 }
@@ -0,0 +1,133 @@
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use tracing::{debug, instrument};
+
+use crate::{
+    crd::v1alpha1::{InterfaceAddressScope, LocalInterfaceConfig},
+    external_ip_source::{AddressKind, solvers::SolverError},
+};
+
+use super::Solver;
+
+/// Returns the addresses assigned to a single named interface on the host.
+///
+/// Unlike [super::interface::Interface], private addresses are included, so that this also works behind 1:1 NATs
+#[derive(Debug)]
+pub struct LocalInterface {
+    interface: String,
+    scope: InterfaceAddressScope,
+}
+
+impl LocalInterface {
+    pub fn new(config: LocalInterfaceConfig) -> LocalInterface {
+        LocalInterface {
+            interface: config.interface,
+            scope: config.scope.unwrap_or_default(),
+        }
+    }
+}
+
+/// Return the addresses of family `kind` that are within `scope`
+fn filter_addresses(
+    addrs: impl IntoIterator<Item = IpAddr>,
+    kind: AddressKind,
+    scope: InterfaceAddressScope,
+) -> Vec<IpAddr> {
+    addrs
+        .into_iter()
+        .filter(|addr| match kind {
+            AddressKind::IPv4 => addr.is_ipv4(),
+            AddressKind::IPv6 => addr.is_ipv6(),
+        })
+        .filter(|addr| match scope {
+            InterfaceAddressScope::All => true,
+            InterfaceAddressScope::Global => match addr {
+                IpAddr::V4(v4) => !v4.is_loopback() && !v4.is_link_local() && !v4.is_unspecified(),
+                IpAddr::V6(v6) => {
+                    !v6.is_loopback() && !v6.is_unicast_link_local() && !v6.is_unspecified()
+                }
+            },
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Solver for LocalInterface {
+    #[instrument]
+    async fn get_addresses(&mut self, kind: AddressKind) -> Result<Vec<IpAddr>, SolverError> {
+        let interface = getifs::interface_by_name(&self.interface)
+            .map_err(|e| SolverError {
+                reason: format!("unable to retrieve network interfaces: {e}"),
+            })?
+            .ok_or(SolverError {
+                reason: format!("interface {} does not exist", self.interface),
+            })?;
+        let addrs: Vec<IpAddr> = match kind {
+            AddressKind::IPv4 => interface
+                .ipv4_addrs()
+                .map(|addrs| addrs.iter().map(|n| IpAddr::V4(n.addr())).collect()),
+            AddressKind::IPv6 => interface
+                .ipv6_addrs()
+                .map(|addrs| addrs.iter().map(|n| IpAddr::V6(n.addr())).collect()),
+        }
+        .map_err(|e| SolverError {
+            reason: format!(
+                "unable to retrieve addresses of interface {}: {e}",
+                self.interface
+            ),
+        })?;
+        debug!(msg = "found addresses on interface", interface = self.interface, addresses = ?addrs);
+        Ok(filter_addresses(addrs, kind, self.scope))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface_addrs() -> Vec<IpAddr> {
+        [
+            "127.0.0.1",
+            "169.254.1.1",
+            "10.0.0.5",
+            "192.0.2.1",
+            "::1",
+            "fe80::1",
+            "fd00::5",
+            "2001:db8::1",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect()
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn excludes_loopback_and_link_local_by_default() {
+        let scope = InterfaceAddressScope::default();
+        assert_eq!(
+            filter_addresses(interface_addrs(), AddressKind::IPv4, scope),
+            addrs(&["10.0.0.5", "192.0.2.1"])
+        );
+        assert_eq!(
+            filter_addresses(interface_addrs(), AddressKind::IPv6, scope),
+            addrs(&["fd00::5", "2001:db8::1"])
+        );
+    }
+
+    #[test]
+    fn includes_all_addresses_of_family() {
+        assert_eq!(
+            filter_addresses(
+                interface_addrs(),
+                AddressKind::IPv4,
+                InterfaceAddressScope::All
+            ),
+            addrs(&["127.0.0.1", "169.254.1.1", "10.0.0.5", "192.0.2.1"])
+        );
+    }
+}
@@ -4,8 +4,8 @@ use std::{
 };
 
 use async_trait::async_trait;
+use itertools::Itertools;
 use k8s_openapi::api::core::v1::Service;
-use tokio::time::timeout;
 use tracing::info;
 
 use crate::{
@@ -13,36 +13,171 @@ use crate::{
     external_ip_source::{
         self, AddressKind, IpSourceError,
         registry::SolverRegistry,
-        solvers::{Solver, SolverError},
+        solvers::{ServiceSolver, SolverError, select_part},
     },
 };
 
+/// Upper limit of addresses returned by a merge in [v1alpha1::MergeResultMode::Product] mode
+const MAX_PRODUCT_ADDRESSES: usize = 256;
+
 #[derive(Debug)]
 pub struct Merge {
-    partial_solvers: Vec<v1alpha1::PartialSolver>,
+    parts: Vec<MergePart>,
+    mode: v1alpha1::MergeResultMode,
+}
+
+/// A partial solver with its mask already resolved to a bitmask of the merge's address family
+#[derive(Debug)]
+struct MergePart {
+    source: PartSource,
+    mask: u128,
+}
+
+/// Where the address of a [MergePart] comes from
+#[derive(Debug)]
+enum PartSource {
+    Solver {
+        solver: v1alpha1::PartialSolverKind,
+        selector: v1alpha1::PartSelector,
+    },
+    Value(IpAddr),
 }
 
 impl Merge {
+    /// Build a merge for addresses of type `kind`, validating its masks and values against that family
+    pub fn from_config(
+        config: v1alpha1::MergeConfig,
+        kind: AddressKind,
+    ) -> Result<Merge, external_ip_source::IpSourceError> {
+        let mut merge = Merge::new(config.partial_solvers, kind)?;
+        merge.mode = config.mode.unwrap_or_default();
+        Ok(merge)
+    }
+
     pub fn new(
         partial_solvers: Vec<v1alpha1::PartialSolver>,
+        kind: AddressKind,
     ) -> Result<Merge, external_ip_source::IpSourceError> {
         // nested merges do not make sense
-        // we cannot check if the ip address type is correct at this point, but we can make sure that is generically valid at least
-        // Ensure that the parts masks combine into a full address
-        let mask_sum = partial_solvers
-            .iter()
-            .map(|ps| match ps.mask {
-                IpAddr::V4(ipv4_addr) => u128::from(ipv4_addr.to_bits()),
-                IpAddr::V6(ipv6_addr) => ipv6_addr.to_bits(),
+        if partial_solvers.is_empty() {
+            return Err(IpSourceError::Malformed(
+                "merge needs at least one partial solver".to_string(),
+            ));
+        }
+        let parts = partial_solvers
+            .into_iter()
+            .map(|ps| {
+                Ok(MergePart {
+                    mask: part_mask(&ps, kind)?,
+                    source: part_source(ps, kind)?,
+                })
             })
-            .sum::<u128>();
-        if ![u128::from(u32::MAX), u128::MAX].contains(&mask_sum) {
+            .collect::<Result<Vec<_>, IpSourceError>>()?;
+        // Ensure that the parts masks don't overlap and combine into a full address
+        let mut mask_sum = 0u128;
+        for part in &parts {
+            if mask_sum & part.mask != 0 {
+                return Err(IpSourceError::Malformed(format!(
+                    "merge part netmasks must not overlap. Got overlapping bits {}",
+                    format_mask(mask_sum & part.mask, kind)
+                )));
+            }
+            mask_sum |= part.mask;
+        }
+        let full_mask = match kind {
+            AddressKind::IPv4 => u128::from(u32::MAX),
+            AddressKind::IPv6 => u128::MAX,
+        };
+        if mask_sum != full_mask {
             return Err(IpSourceError::Malformed(format!(
-                "merge part netmasks do not combine to full IPv4 address. Got {}",
-                Ipv6Addr::from(mask_sum)
+                "merge part netmasks do not combine to full {} address. Got {}",
+                kind,
+                format_mask(mask_sum, kind)
             )));
         }
-        Ok(Merge { partial_solvers })
+        Ok(Merge {
+            parts,
+            mode: v1alpha1::MergeResultMode::default(),
+        })
+    }
+}
+
+/// Display a bitmask as an address of type `kind`
+fn format_mask(mask: u128, kind: AddressKind) -> String {
+    match kind {
+        AddressKind::IPv4 => u32::try_from(mask).map_or_else(
+            |_| format!("{mask:#x}"),
+            |m| Ipv4Addr::from_bits(m).to_string(),
+        ),
+        AddressKind::IPv6 => Ipv6Addr::from_bits(mask).to_string(),
+    }
+}
+
+/// Resolve the mask of a partial solver from either its explicit netmask or its prefix length
+fn part_mask(ps: &v1alpha1::PartialSolver, kind: AddressKind) -> Result<u128, IpSourceError> {
+    match (ps.mask, ps.mask_prefix_len) {
+        (Some(mask), None) => {
+            if mask.is_ipv4() != (kind == AddressKind::IPv4) {
+                return Err(IpSourceError::Malformed(format!(
+                    "merge part netmasks mix address families. Expected {} masks, got {}",
+                    kind, mask
+                )));
+            }
+            Ok(ip_to_u128(&mask))
+        }
+        (None, Some(prefix_len)) => {
+            let bits = match kind {
+                AddressKind::IPv4 => 32,
+                AddressKind::IPv6 => 128,
+            };
+            if u32::from(prefix_len) > bits {
+                return Err(IpSourceError::Malformed(format!(
+                    "merge part prefix length {prefix_len} is out of range for {kind} addresses"
+                )));
+            }
+            let full_mask = match kind {
+                AddressKind::IPv4 => u128::from(u32::MAX),
+                AddressKind::IPv6 => u128::MAX,
+            };
+            // A shift by the full width would overflow, in which case the mask is empty
+            Ok(full_mask
+                .checked_shl(bits - u32::from(prefix_len))
+                .unwrap_or(0)
+                & full_mask)
+        }
+        (Some(_), Some(_)) => Err(IpSourceError::Malformed(
+            "merge part must not set both mask and maskPrefixLen".to_string(),
+        )),
+        (None, None) => Err(IpSourceError::Malformed(
+            "merge part must set either mask or maskPrefixLen".to_string(),
+        )),
+    }
+}
+
+/// Resolve whether a partial solver queries a solver or contributes a fixed value of the merge's address family
+fn part_source(
+    ps: v1alpha1::PartialSolver,
+    kind: AddressKind,
+) -> Result<PartSource, IpSourceError> {
+    match (ps.solver, ps.value) {
+        (Some(solver), None) => Ok(PartSource::Solver {
+            solver,
+            selector: ps.part_selector.unwrap_or_default(),
+        }),
+        (None, Some(value)) => {
+            if value.is_ipv4() != (kind == AddressKind::IPv4) {
+                return Err(IpSourceError::Malformed(format!(
+                    "merge part value {value} is not an {kind} address"
+                )));
+            }
+            Ok(PartSource::Value(value))
+        }
+        (Some(_), Some(_)) => Err(IpSourceError::Malformed(
+            "merge part must not set both solver and value".to_string(),
+        )),
+        (None, None) => Err(IpSourceError::Malformed(
+            "merge part must set either solver or value".to_string(),
+        )),
     }
 }
 
@@ -54,75 +189,411 @@ fn ip_to_u128(addr: &IpAddr) -> u128 {
 }
 
 #[async_trait]
-impl Solver for Merge {
+impl ServiceSolver for Merge {
     //#[instrument]
     async fn get_addresses(
-        &mut self,
+        &self,
         kind: external_ip_source::AddressKind,
         svc: &Service,
         solvers: &SolverRegistry,
     ) -> Result<Vec<std::net::IpAddr>, SolverError> {
-        // Ensure that the part masks are all of the correct ip type.
-        // We already know they build a valid IP address thanks to the check in new()
-        if kind == AddressKind::IPv4
-            && let Some(mismatch) = self
-                .partial_solvers
-                .iter()
-                .find(|ps| !matches!(ps.mask, IpAddr::V4(_)))
-        {
-            return Err(SolverError {
+        // The candidate addresses of each part, a single one unless in product mode
+        let mut candidates = vec![];
+        for part in &self.parts {
+            let addrs = match &part.source {
+                PartSource::Solver { solver, selector } => {
+                    let registered =
+                        solvers
+                            .get(&(SolverKind::from(solver), kind))
+                            .ok_or(SolverError {
+                                reason: format!("solver {solver:?} not found"),
+                            })?;
+                    let addrs_ret = registered
+                        .get_addresses(kind, svc, solvers, Duration::from_secs(10))
+                        .await??;
+                    match self.mode {
+                        v1alpha1::MergeResultMode::Single => {
+                            select_part(&addrs_ret, *selector).into_iter().collect_vec()
+                        }
+                        v1alpha1::MergeResultMode::Product => {
+                            addrs_ret.into_iter().sorted().dedup().collect_vec()
+                        }
+                    }
+                }
+                PartSource::Value(value) => vec![*value],
+            };
+            if addrs.is_empty() {
+                return Err(SolverError {
+                    reason: "merge partialSolver returned no addresses".to_string(),
+                });
+            }
+            candidates.push(addrs);
+        }
+        let combinations = candidates
+            .iter()
+            .try_fold(1usize, |acc, addrs| acc.checked_mul(addrs.len()))
+            .filter(|count| *count <= MAX_PRODUCT_ADDRESSES)
+            .ok_or_else(|| SolverError {
                 reason: format!(
-                    "mismatched merge mask IP type. Expected IPv4 masks, got {}",
-                    mismatch.mask
+                    "merge would combine the addresses of its parts into more than {MAX_PRODUCT_ADDRESSES} addresses"
                 ),
-            });
-        } else if kind == AddressKind::IPv6
-            && let Some(mismatch) = self
-                .partial_solvers
+            })?;
+
+        let mut merged = Vec::with_capacity(combinations);
+        for addrs in candidates.into_iter().multi_cartesian_product() {
+            let address: u128 = addrs
                 .iter()
-                .find(|ps| !matches!(ps.mask, IpAddr::V6(_)))
-        {
-            return Err(SolverError {
-                reason: format!(
-                    "mismatched merge mask IP type. Expected IPv6 masks, got {}",
-                    mismatch.mask
-                ),
-            });
+                .zip(&self.parts)
+                .fold(0, |acc, (addr, part)| acc | (ip_to_u128(addr) & part.mask));
+            let addr = match kind {
+                AddressKind::IPv4 => IpAddr::V4(Ipv4Addr::from_bits(
+                    u32::try_from(address).expect("ipv4 merge type should result in ipv4 address"),
+                )),
+                AddressKind::IPv6 => IpAddr::V6(Ipv6Addr::from_bits(address)),
+            };
+            info!(
+                msg = "merge: assembled address from parts",
+                address_parts = ?addrs,
+                ?addr
+            );
+            merged.push(addr);
+        }
+        // Different combinations may only differ in bits that are masked out
+        Ok(merged.into_iter().sorted().dedup().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::sync::RwLock;
+
+    use crate::{
+        crd::v1alpha1::{PartialSolver, PartialSolverKind, StaticConfig},
+        external_ip_source::solvers::{RegisteredSolver, Static},
+    };
+
+    use super::*;
+
+    fn static_part(addr: &str, mask: &str) -> PartialSolver {
+        PartialSolver {
+            solver: Some(PartialSolverKind::Static(StaticConfig {
+                addresses: vec![addr.parse().unwrap()],
+                ranges: vec![],
+            })),
+            value: None,
+            mask: Some(mask.parse().unwrap()),
+            mask_prefix_len: None,
+            part_selector: None,
         }
+    }
 
-        let mut addrs = vec![];
-        let mut parts = vec![];
-        for ps in &self.partial_solvers {
-            let solver = solvers
-                .get(&(SolverKind::from(&ps.solver), kind))
-                .ok_or(SolverError {
-                    reason: format!("solver {:?} not found", ps),
-                })?;
-            let mut guard = timeout(Duration::from_secs(10), solver.write())
-                .await
-                .map_err(|_| SolverError {
-                    reason: "timed out waiting for solver lock".to_string(),
-                })?;
-            let addrs_ret = guard.get_addresses(kind, svc, solvers).await?;
-            let addr = addrs_ret.last().ok_or(SolverError {
-                reason: "merge partialSolver returned no addresses".to_string(),
-            })?;
-            let part = ip_to_u128(addr) & ip_to_u128(&ps.mask);
-            addrs.push(*addr);
-            parts.push(part);
+    fn static_prefix_part(addr: &str, prefix_len: u8) -> PartialSolver {
+        PartialSolver {
+            solver: Some(PartialSolverKind::Static(StaticConfig {
+                addresses: vec![addr.parse().unwrap()],
+                ranges: vec![],
+            })),
+            value: None,
+            mask: None,
+            mask_prefix_len: Some(prefix_len),
+            part_selector: None,
+        }
+    }
+
+    #[test]
+    fn accepts_single_family_masks() {
+        assert!(
+            Merge::new(
+                vec![
+                    static_part("192.0.2.1", "255.255.255.0"),
+                    static_part("10.0.0.5", "0.0.0.255"),
+                ],
+                AddressKind::IPv4
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_mixed_family_masks() {
+        let res = Merge::new(
+            vec![
+                static_part("192.0.2.1", "255.255.255.0"),
+                static_part("2001:db8::1", "::ffff:ffff:ffff:ffff"),
+            ],
+            AddressKind::IPv4,
+        );
+        assert!(matches!(res, Err(IpSourceError::Malformed(_))));
+    }
+
+    #[test]
+    fn converts_prefix_len_to_mask() -> Result<(), IpSourceError> {
+        let merge = Merge::new(
+            vec![
+                static_prefix_part("2001:db8::1", 64),
+                static_part("fd00::5", "::ffff:ffff:ffff:ffff"),
+            ],
+            AddressKind::IPv6,
+        )?;
+        assert_eq!(
+            merge.parts[0].mask,
+            "ffff:ffff:ffff:ffff::"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+                .to_bits()
+        );
+        let merge = Merge::new(
+            vec![
+                static_prefix_part("192.0.2.1", 24),
+                static_part("10.0.0.5", "0.0.0.255"),
+            ],
+            AddressKind::IPv4,
+        )?;
+        assert_eq!(
+            merge.parts[0].mask,
+            u128::from("255.255.255.0".parse::<Ipv4Addr>().unwrap().to_bits())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_out_of_range_prefix_len() {
+        let res = Merge::new(
+            vec![
+                static_prefix_part("192.0.2.1", 33),
+                static_part("10.0.0.5", "0.0.0.255"),
+            ],
+            AddressKind::IPv4,
+        );
+        assert!(matches!(res, Err(IpSourceError::Malformed(_))));
+    }
+
+    #[test]
+    fn rejects_overlapping_prefix_lens() {
+        // Two full-width masks would overflow when added up
+        let res = Merge::new(
+            vec![
+                static_prefix_part("2001:db8::1", 128),
+                static_prefix_part("2001:db8::2", 128),
+            ],
+            AddressKind::IPv6,
+        );
+        assert!(matches!(res, Err(IpSourceError::Malformed(_))), "{res:?}");
+        let res = Merge::new(
+            vec![
+                static_prefix_part("192.0.2.1", 24),
+                static_prefix_part("10.0.0.5", 16),
+                static_part("10.0.0.5", "0.0.0.255"),
+            ],
+            AddressKind::IPv4,
+        );
+        assert!(matches!(res, Err(IpSourceError::Malformed(_))), "{res:?}");
+    }
+
+    #[test]
+    fn rejects_mask_and_prefix_len() {
+        let mut part = static_part("192.0.2.1", "255.255.255.255");
+        part.mask_prefix_len = Some(32);
+        let res = Merge::new(vec![part], AddressKind::IPv4);
+        assert!(matches!(res, Err(IpSourceError::Malformed(_))));
+    }
+
+    /// Merge a prefix from a solver returning `prefixes` in the given order with a fixed host part
+    async fn merge_with_prefixes(prefixes: &[&str]) -> Vec<IpAddr> {
+        let prefix_solver = StaticConfig {
+            addresses: prefixes.iter().map(|a| a.parse().unwrap()).collect(),
+            ranges: vec![],
+        };
+        let host = static_part("::5", "::ffff:ffff:ffff:ffff");
+        let mut registry: SolverRegistry = HashMap::new();
+        for config in [
+            prefix_solver.clone(),
+            StaticConfig {
+                addresses: vec!["::5".parse().unwrap()],
+                ranges: vec![],
+            },
+        ] {
+            registry.insert(
+                (SolverKind::Static(config.clone()), AddressKind::IPv6),
+                RegisteredSolver::Global(RwLock::new(Box::new(
+                    Static::from_config(config).unwrap(),
+                ))),
+            );
+        }
+        let merge = Merge::new(
+            vec![
+                PartialSolver {
+                    solver: Some(PartialSolverKind::Static(prefix_solver)),
+                    value: None,
+                    mask: None,
+                    mask_prefix_len: Some(64),
+                    part_selector: None,
+                },
+                host,
+            ],
+            AddressKind::IPv6,
+        )
+        .unwrap();
+        merge
+            .get_addresses(AddressKind::IPv6, &Service::default(), &registry)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn selects_part_independent_of_order() {
+        let expected = vec!["2001:db8:1::5".parse::<IpAddr>().unwrap()];
+        assert_eq!(
+            merge_with_prefixes(&["2001:db8:2::1", "2001:db8:1::1", "2001:db8:3::1"]).await,
+            expected
+        );
+        assert_eq!(
+            merge_with_prefixes(&["2001:db8:3::1", "2001:db8:2::1", "2001:db8:1::1"]).await,
+            expected
+        );
+    }
+
+    fn value_part(value: &str, mask: &str) -> PartialSolver {
+        PartialSolver {
+            solver: None,
+            value: Some(value.parse().unwrap()),
+            mask: Some(mask.parse().unwrap()),
+            mask_prefix_len: None,
+            part_selector: None,
         }
-        let address: u128 = parts.into_iter().sum();
-        let addr = match kind {
-            AddressKind::IPv4 => IpAddr::V4(Ipv4Addr::from_bits(
-                u32::try_from(address).expect("ipv4 merge type should result in ipv4 address"),
-            )),
-            AddressKind::IPv6 => IpAddr::V6(Ipv6Addr::from_bits(address)),
+    }
+
+    #[tokio::test]
+    async fn merges_dynamic_prefix_with_value() {
+        let prefix = StaticConfig {
+            addresses: vec!["2001:db8:1::42".parse().unwrap()],
+            ranges: vec![],
+        };
+        let registry: SolverRegistry = HashMap::from([(
+            (SolverKind::Static(prefix.clone()), AddressKind::IPv6),
+            RegisteredSolver::Global(RwLock::new(Box::new(
+                Static::from_config(prefix.clone()).unwrap(),
+            ))),
+        )]);
+        let merge = Merge::new(
+            vec![
+                PartialSolver {
+                    solver: Some(PartialSolverKind::Static(prefix)),
+                    value: None,
+                    mask: None,
+                    mask_prefix_len: Some(64),
+                    part_selector: None,
+                },
+                value_part("::1", "::ffff:ffff:ffff:ffff"),
+            ],
+            AddressKind::IPv6,
+        )
+        .unwrap();
+        assert_eq!(
+            merge
+                .get_addresses(AddressKind::IPv6, &Service::default(), &registry)
+                .await
+                .unwrap(),
+            vec!["2001:db8:1::1".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let res = Merge::new(
+            vec![
+                static_part("192.0.2.1", "255.255.255.0"),
+                value_part("::1", "0.0.0.255"),
+            ],
+            AddressKind::IPv4,
+        );
+        assert!(
+            matches!(&res, Err(IpSourceError::Malformed(msg)) if msg.contains("not an IPv4 address")),
+            "{res:?}"
+        );
+        let mut both = static_part("192.0.2.1", "255.255.255.255");
+        both.value = Some("192.0.2.2".parse().unwrap());
+        let res = Merge::new(vec![both], AddressKind::IPv4);
+        assert!(matches!(res, Err(IpSourceError::Malformed(_))));
+    }
+
+    #[test]
+    fn validates_masks_against_the_family_of_the_block() {
+        let v4_merge = v1alpha1::MergeConfig {
+            partial_solvers: vec![
+                static_part("192.0.2.1", "255.255.255.0"),
+                static_part("10.0.0.5", "0.0.0.255"),
+            ],
+            mode: None,
+        };
+        assert!(Merge::from_config(v4_merge.clone(), AddressKind::IPv4).is_ok());
+        let res = Merge::from_config(v4_merge, AddressKind::IPv6);
+        assert!(
+            matches!(&res, Err(IpSourceError::Malformed(msg)) if msg.contains("Expected IPv6 masks")),
+            "{res:?}"
+        );
+    }
+
+    /// Merge the addresses of a prefix solver with a fixed host part in product mode
+    async fn product_merge(prefixes: StaticConfig) -> Result<Vec<IpAddr>, SolverError> {
+        let registry: SolverRegistry = HashMap::from([(
+            (SolverKind::Static(prefixes.clone()), AddressKind::IPv6),
+            RegisteredSolver::Global(RwLock::new(Box::new(
+                Static::from_config(prefixes.clone()).unwrap(),
+            ))),
+        )]);
+        let merge = Merge::from_config(
+            v1alpha1::MergeConfig {
+                partial_solvers: vec![
+                    PartialSolver {
+                        solver: Some(PartialSolverKind::Static(prefixes)),
+                        value: None,
+                        mask: None,
+                        mask_prefix_len: Some(64),
+                        part_selector: None,
+                    },
+                    value_part("::1", "::ffff:ffff:ffff:ffff"),
+                ],
+                mode: Some(v1alpha1::MergeResultMode::Product),
+            },
+            AddressKind::IPv6,
+        )
+        .unwrap();
+        merge
+            .get_addresses(AddressKind::IPv6, &Service::default(), &registry)
+            .await
+    }
+
+    #[tokio::test]
+    async fn merges_every_address_in_product_mode() {
+        let prefixes = StaticConfig {
+            addresses: ["2001:db8:2::42", "2001:db8:1::42", "2001:db8:1::43"]
+                .iter()
+                .map(|a| a.parse().unwrap())
+                .collect(),
+            ranges: vec![],
         };
-        info!(
-            msg = "merge: assembled address from parts",
-            address_parts = ?addrs,
-            ?addr
+        assert_eq!(
+            product_merge(prefixes).await.unwrap(),
+            vec![
+                "2001:db8:1::1".parse::<IpAddr>().unwrap(),
+                "2001:db8:2::1".parse().unwrap()
+            ]
         );
-        Ok(vec![addr])
+    }
+
+    #[tokio::test]
+    async fn limits_addresses_in_product_mode() {
+        let prefixes = StaticConfig {
+            addresses: vec![],
+            ranges: vec![
+                "2001:db8::1-2001:db8::100".to_string(),
+                "2001:db8::101-2001:db8::101".to_string(),
+            ],
+        };
+        let err = product_merge(prefixes).await.unwrap_err();
+        assert!(err.reason.contains("more than 256 addresses"), "{err:?}");
     }
 }
@@ -0,0 +1,128 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tracing::instrument;
+
+use crate::{
+    crd::v1alpha1::{MockConfig, MockFailure},
+    external_ip_source::{
+        AddressKind,
+        solvers::{IpApiSolver, Solver, SolverCacheState, SolverError},
+    },
+    metrics::Metrics,
+};
+
+/// How long rate limited mock solvers back off for at first, doubling on every further rate limit
+const RATELIMIT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Returns configured addresses after an optional delay, or fails on purpose. Only available with the `testing` feature
+#[derive(Debug)]
+pub struct Mock {
+    config: MockConfig,
+    /// Answers queries of mocks configured to be rate limited, so that they back off like real IP APIs
+    rate_limited: Option<IpApiSolver>,
+}
+
+impl Mock {
+    pub fn new(config: MockConfig) -> Mock {
+        let rate_limited = (config.failure == Some(MockFailure::RateLimited)).then(|| {
+            IpApiSolver::rate_limited_mock(
+                config.delay_ms.map(Duration::from_millis),
+                RATELIMIT_BACKOFF,
+            )
+        });
+        Mock {
+            config,
+            rate_limited,
+        }
+    }
+
+    /// Record rate limit backoff in `metrics`
+    pub fn with_metrics(mut self, metrics: Option<Arc<Metrics>>) -> Mock {
+        self.rate_limited = self.rate_limited.map(|solver| solver.with_metrics(metrics));
+        self
+    }
+}
+
+#[async_trait]
+impl Solver for Mock {
+    #[instrument]
+    async fn get_addresses(&mut self, kind: AddressKind) -> Result<Vec<IpAddr>, SolverError> {
+        if let Some(rate_limited) = &mut self.rate_limited {
+            return rate_limited.get_addresses(kind).await;
+        }
+        if let Some(delay_ms) = self.config.delay_ms {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+        }
+        match self.config.failure {
+            Some(MockFailure::Error | MockFailure::RateLimited) => Err(SolverError {
+                reason: "mock solver configured to fail".to_string(),
+            }),
+            None => Ok(self
+                .config
+                .addresses
+                .iter()
+                .filter(|addr| match kind {
+                    AddressKind::IPv4 => addr.is_ipv4(),
+                    AddressKind::IPv6 => addr.is_ipv6(),
+                })
+                .copied()
+                .collect()),
+        }
+    }
+
+    fn cache_state(&self) -> Option<SolverCacheState> {
+        self.rate_limited.as_ref()?.cache_state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_addresses_after_delay() {
+        let mut mock = Mock::new(MockConfig {
+            addresses: vec!["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap()],
+            delay_ms: Some(50),
+            failure: None,
+        });
+        let start = std::time::Instant::now();
+        let addrs = mock.get_addresses(AddressKind::IPv4).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(addrs, vec!["192.0.2.1".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn fails_when_configured() {
+        let mut mock = Mock::new(MockConfig {
+            addresses: vec!["192.0.2.1".parse().unwrap()],
+            delay_ms: None,
+            failure: Some(MockFailure::Error),
+        });
+        assert!(mock.get_addresses(AddressKind::IPv4).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn backs_off_when_rate_limited() {
+        let metrics = Arc::new(Metrics::default());
+        let mut mock = Mock::new(MockConfig {
+            addresses: vec!["192.0.2.1".parse().unwrap()],
+            delay_ms: Some(50),
+            failure: Some(MockFailure::RateLimited),
+        })
+        .with_metrics(Some(metrics.clone()));
+        assert!(mock.get_addresses(AddressKind::IPv4).await.is_err());
+        assert!(mock.cache_state().unwrap().backing_off);
+
+        // The mock isn't queried again while backing off, so the delay doesn't apply either
+        let start = std::time::Instant::now();
+        assert!(mock.get_addresses(AddressKind::IPv4).await.is_err());
+        assert!(start.elapsed() < Duration::from_millis(50));
+        let backoff = metrics
+            .solver_backoff_seconds
+            .get_or_create(&crate::metrics::SolverLabels { solver: "ipAPI" })
+            .get();
+        assert!(backoff > 0.0 && backoff <= RATELIMIT_BACKOFF.as_secs_f64());
+    }
+}
@@ -1,12 +1,28 @@
-use std::{fmt::Debug, net::IpAddr};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use async_trait::async_trait;
 use k8s_openapi::api::core::v1::Service;
+use kube::Client;
+use serde::Serialize;
 use thiserror::Error;
+use tokio::{
+    sync::RwLock,
+    time::{error::Elapsed, timeout},
+};
 
 use crate::{
     crd::v1alpha1,
-    external_ip_source::{IpSourceError, registry::SolverRegistry, solvers::interface::Interface},
+    external_ip_source::{
+        IpSourceError, disk_cache::DiskCache, registry::SolverRegistry,
+        solvers::interface::Interface,
+    },
+    metrics::Metrics,
 };
 
 use super::AddressKind;
@@ -14,75 +30,331 @@ use super::AddressKind;
 mod dns_hostname;
 mod interface;
 mod ip_api;
+mod keep_current;
 mod load_balancer_ingress;
+mod local_interface;
 mod merge;
+#[cfg(feature = "testing")]
+mod mock;
+mod namespace_annotation;
+mod outbound;
+mod self_node;
 mod r#static;
+mod template;
+mod unix_socket;
 
-pub use dns_hostname::DnsHostname;
-pub use ip_api::IpApiSolver;
+pub use dns_hostname::{DnsHostname, ResolverProtocol};
+pub use ip_api::{HttpClientConfig, IpApiSolver, socks_proxy};
+pub use keep_current::KeepCurrent;
 pub use load_balancer_ingress::LoadBalancerIngress;
+pub use local_interface::LocalInterface;
 pub use merge::Merge;
+#[cfg(feature = "testing")]
+pub use mock::Mock;
+pub use namespace_annotation::NamespaceAnnotation;
+pub use outbound::OutboundLimit;
+pub use self_node::SelfNode;
 pub use r#static::Static;
+pub use template::Template;
+pub use unix_socket::UnixSocket;
 
-/// A Source provides a list of externalIP addresses to be processed and applied
+/// A Solver provides a list of externalIP addresses that does not depend on the service being resolved.
+///
+/// Solvers are shared across all services, so they may cache their results, such as when querying external APIs.
 #[async_trait]
 pub trait Solver: Debug + Send + Sync {
+    /// Query this solver for addresses of type `kind` and return the results.
+    async fn get_addresses(&mut self, kind: AddressKind) -> Result<Vec<IpAddr>, SolverError>;
+
+    /// Current state of the cache of this solver, for troubleshooting. [None] if the solver doesn't cache its results
+    fn cache_state(&self) -> Option<SolverCacheState> {
+        None
+    }
+}
+
+/// Cache state of a [Solver], see [Solver::cache_state]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SolverCacheState {
+    /// Addresses that are currently reused instead of querying the solver again
+    pub cached_addresses: Vec<IpAddr>,
+    /// Seconds until the cached addresses or backoff expire
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_secs: Option<u64>,
+    /// Whether the solver is backing off after being rate limited
+    pub backing_off: bool,
+}
+
+/// A ServiceSolver provides a list of externalIP addresses that depends on the service being resolved.
+///
+/// Like [Solver]s, these are shared across all services. To prevent results of one service from leaking into another,
+/// they only get shared access to themselves and thus must not cache any results.
+#[async_trait]
+pub trait ServiceSolver: Debug + Send + Sync {
     /// Query this solver for addresses of type `kind` and return the results.
     ///
-    /// `svc` refers to the [Service] being currently resolved for solvers that need to access it.
+    /// `svc` refers to the [Service] being currently resolved.
     /// `solvers` is the global map of solvers across all ExternalIP sources, useful for solvers with subsolvers, such as [Merge].
     async fn get_addresses(
-        &mut self,
+        &self,
         kind: AddressKind,
         svc: &Service,
         solvers: &SolverRegistry,
     ) -> Result<Vec<IpAddr>, SolverError>;
+
+    /// Query this solver for addresses of both families in a single pass.
+    ///
+    /// Returns [None] if the solver can only be queried per family, such as meta-solvers whose sub-solvers depend on the family.
+    async fn get_all_addresses(
+        &self,
+        _svc: &Service,
+        _solvers: &SolverRegistry,
+    ) -> Option<Result<Vec<IpAddr>, SolverError>> {
+        None
+    }
+}
+
+/// Results of [ServiceSolver::get_all_addresses] while resolving a single service.
+///
+/// This lets solvers that can answer both families at once be queried only once per service, instead of once per family.
+#[derive(Debug, Default)]
+pub struct CombinedResults(Mutex<HashMap<v1alpha1::SolverKind, Result<Vec<IpAddr>, SolverError>>>);
+
+impl CombinedResults {
+    /// Return the addresses of type `kind` from the combined result of `solver`, querying it if needed.
+    ///
+    /// Returns [None] if `solver` can't be queried for both families at once.
+    pub async fn get_addresses(
+        &self,
+        kind: AddressKind,
+        key: &v1alpha1::SolverKind,
+        solver: &RegisteredSolver,
+        svc: &Service,
+        solvers: &SolverRegistry,
+    ) -> Option<Result<Vec<IpAddr>, SolverError>> {
+        let RegisteredSolver::Service(solver) = solver else {
+            return None;
+        };
+        let cached = self
+            .0
+            .lock()
+            .expect("combined results lock poisoned")
+            .get(key)
+            .cloned();
+        let result = match cached {
+            Some(result) => result,
+            None => {
+                let result = solver.get_all_addresses(svc, solvers).await?;
+                self.0
+                    .lock()
+                    .expect("combined results lock poisoned")
+                    .insert(key.clone(), result.clone());
+                result
+            }
+        };
+        Some(result.map(|addrs| {
+            addrs
+                .into_iter()
+                .filter(|addr| kind.matches(addr))
+                .collect()
+        }))
+    }
+}
+
+/// Whether the results of `solver` depend on the service being resolved, either directly or through its sub-solvers
+pub fn is_service_dependent(solver: &v1alpha1::SolverKind) -> bool {
+    match solver {
+        v1alpha1::SolverKind::LoadBalancerIngress(_)
+        | v1alpha1::SolverKind::KeepCurrent(_)
+        | v1alpha1::SolverKind::NamespaceAnnotation(_) => true,
+        v1alpha1::SolverKind::SelfNode(self_node_config) => self_node_config
+            .respect_local_traffic_policy
+            .unwrap_or(false),
+        v1alpha1::SolverKind::Merge(merge_config) => merge_config
+            .partial_solvers
+            .iter()
+            .filter_map(|ps| ps.solver.as_ref())
+            .any(|solver| is_service_dependent(&solver.into())),
+        v1alpha1::SolverKind::Template(template_config) => template_config
+            .solvers
+            .values()
+            .any(|ps| is_service_dependent(&ps.into())),
+        v1alpha1::SolverKind::IpAPI(_)
+        | v1alpha1::SolverKind::Interface(_)
+        | v1alpha1::SolverKind::LocalInterface(_)
+        | v1alpha1::SolverKind::DnsHostname(_)
+        | v1alpha1::SolverKind::UnixSocket(_)
+        | v1alpha1::SolverKind::Static(_) => false,
+        #[cfg(feature = "testing")]
+        v1alpha1::SolverKind::Mock(_) => false,
+    }
+}
+
+/// A solver stored in the [SolverRegistry]
+#[derive(Debug)]
+pub enum RegisteredSolver {
+    // Global solvers may hold mutable state such as a cache, so we need to lock them for each query
+    Global(RwLock<Box<dyn Solver>>),
+    Service(Box<dyn ServiceSolver>),
+}
+
+impl RegisteredSolver {
+    /// Query the underlying solver, waiting at most `lock_timeout` for exclusive access to global solvers.
+    ///
+    /// Returns [Elapsed] if the solver could not be queried in time, so that callers can tell timeouts apart from failures
+    pub async fn get_addresses(
+        &self,
+        kind: AddressKind,
+        svc: &Service,
+        solvers: &SolverRegistry,
+        lock_timeout: Duration,
+    ) -> Result<Result<Vec<IpAddr>, SolverError>, Elapsed> {
+        match self {
+            RegisteredSolver::Global(solver) => {
+                let mut guard = timeout(lock_timeout, solver.write()).await?;
+                Ok(guard.get_addresses(kind).await)
+            }
+            RegisteredSolver::Service(solver) => Ok(solver.get_addresses(kind, svc, solvers).await),
+        }
+    }
+
+    /// Current cache state of the underlying solver. Service solvers never cache, so they have none
+    pub async fn cache_state(&self) -> Option<SolverCacheState> {
+        match self {
+            RegisteredSolver::Global(solver) => solver.read().await.cache_state(),
+            RegisteredSolver::Service(_) => None,
+        }
+    }
+}
+
+/// Pick the address used as a part by meta-solvers such as [Merge] from the results of a sub-solver
+fn select_part(addrs: &[IpAddr], selector: v1alpha1::PartSelector) -> Option<IpAddr> {
+    match selector {
+        v1alpha1::PartSelector::Min => addrs.iter().min(),
+        v1alpha1::PartSelector::Max => addrs.iter().max(),
+        v1alpha1::PartSelector::First => addrs.first(),
+        v1alpha1::PartSelector::Last => addrs.last(),
+    }
+    .copied()
 }
 
-#[derive(Debug, Error)]
+#[derive(Debug, Clone, Error)]
 #[error("failed to resolve addresses: {reason}")]
 pub struct SolverError {
     pub reason: String,
 }
 
-impl TryFrom<v1alpha1::SolverKind> for Box<dyn Solver> {
+/// Meta-solvers report sub-solvers that could not be queried in time as their own failure
+impl From<Elapsed> for SolverError {
+    fn from(_: Elapsed) -> Self {
+        SolverError {
+            reason: "timed out waiting for solver".to_string(),
+        }
+    }
+}
+
+/// Shared resources that solvers may need when being constructed
+#[derive(Clone)]
+pub struct SolverContext {
+    pub client: Client,
+    /// HTTP client shared by all solvers querying external services
+    pub http_client: reqwest::Client,
+    /// Settings for dedicated HTTP clients, such as those using mutual TLS
+    pub http_config: HttpClientConfig,
+    /// Protocol used to reach name servers for DNS lookups
+    pub resolver_protocol: ResolverProtocol,
+    /// Persistent cache for solvers querying external services, if enabled
+    pub disk_cache: Option<Arc<DiskCache>>,
+    /// Metrics for solvers to record their cache usage in
+    pub metrics: Option<Arc<Metrics>>,
+    /// Shared limit of queries to external services in flight, acquired by the ipAPI and dnsHostname solvers
+    pub outbound_limit: OutboundLimit,
+    /// Lower bound for how long solvers cache their results. Only raises shorter cache durations
+    pub min_cache_ttl: Duration,
+}
+
+impl TryFrom<(v1alpha1::SolverKind, AddressKind, SolverContext)> for RegisteredSolver {
     type Error = IpSourceError;
 
-    fn try_from(value: v1alpha1::SolverKind) -> Result<Self, Self::Error> {
-        match value {
+    fn try_from(
+        value: (v1alpha1::SolverKind, AddressKind, SolverContext),
+    ) -> Result<Self, Self::Error> {
+        let (solver, kind, ctx) = value;
+        match solver {
             v1alpha1::SolverKind::IpAPI(ip_solver) => {
-                let boxed: Box<dyn Solver> = Box::new(IpApiSolver::new(ip_solver.provider));
-                Ok(boxed)
+                let boxed: Box<dyn Solver> = Box::new(
+                    IpApiSolver::new(ip_solver, ctx.http_client, &ctx.http_config)?
+                        .with_disk_cache(ctx.disk_cache)
+                        .with_metrics(ctx.metrics)
+                        .with_outbound_limit(ctx.outbound_limit)
+                        .with_min_cache_ttl(ctx.min_cache_ttl),
+                );
+                Ok(RegisteredSolver::Global(RwLock::new(boxed)))
             }
             v1alpha1::SolverKind::Interface(interface_config) => {
                 let boxed: Box<dyn Solver> = Box::new(Interface::new(interface_config.name));
-                Ok(boxed)
+                Ok(RegisteredSolver::Global(RwLock::new(boxed)))
+            }
+            v1alpha1::SolverKind::LocalInterface(local_interface_config) => {
+                let boxed: Box<dyn Solver> = Box::new(LocalInterface::new(local_interface_config));
+                Ok(RegisteredSolver::Global(RwLock::new(boxed)))
             }
             v1alpha1::SolverKind::DnsHostname(dns_hostname) => {
-                let boxed: Box<dyn Solver> = Box::new(DnsHostname::new(dns_hostname.host.clone()));
-                Ok(boxed)
+                let boxed: Box<dyn Solver> = Box::new(
+                    DnsHostname::new(dns_hostname, &ctx.resolver_protocol, ctx.min_cache_ttl)?
+                        .with_outbound_limit(ctx.outbound_limit),
+                );
+                Ok(RegisteredSolver::Global(RwLock::new(boxed)))
             }
             v1alpha1::SolverKind::LoadBalancerIngress(_) => {
-                let boxed: Box<dyn Solver> = Box::new(LoadBalancerIngress::new());
-                Ok(boxed)
+                let boxed: Box<dyn ServiceSolver> = Box::new(LoadBalancerIngress::new());
+                Ok(RegisteredSolver::Service(boxed))
+            }
+            v1alpha1::SolverKind::KeepCurrent(_) => {
+                let boxed: Box<dyn ServiceSolver> = Box::new(KeepCurrent::new());
+                Ok(RegisteredSolver::Service(boxed))
+            }
+            v1alpha1::SolverKind::NamespaceAnnotation(namespace_config) => {
+                let boxed: Box<dyn ServiceSolver> =
+                    Box::new(NamespaceAnnotation::new(namespace_config, ctx.client));
+                Ok(RegisteredSolver::Service(boxed))
+            }
+            v1alpha1::SolverKind::SelfNode(self_node_config) => {
+                if self_node_config
+                    .respect_local_traffic_policy
+                    .unwrap_or(false)
+                {
+                    let boxed: Box<dyn ServiceSolver> = Box::new(SelfNode::new(ctx.client));
+                    Ok(RegisteredSolver::Service(boxed))
+                } else {
+                    let boxed: Box<dyn Solver> = Box::new(SelfNode::new(ctx.client));
+                    Ok(RegisteredSolver::Global(RwLock::new(boxed)))
+                }
+            }
+            v1alpha1::SolverKind::UnixSocket(unix_socket_config) => {
+                let boxed: Box<dyn Solver> = Box::new(
+                    UnixSocket::new(unix_socket_config).with_min_cache_ttl(ctx.min_cache_ttl),
+                );
+                Ok(RegisteredSolver::Global(RwLock::new(boxed)))
             }
             v1alpha1::SolverKind::Static(cfg) => {
-                let boxed: Box<dyn Solver> = Box::new(Static::new(cfg.addresses.clone()));
-                Ok(boxed)
+                let boxed: Box<dyn Solver> = Box::new(Static::from_config(cfg)?);
+                Ok(RegisteredSolver::Global(RwLock::new(boxed)))
             }
             v1alpha1::SolverKind::Merge(merge_config) => {
+                let boxed: Box<dyn ServiceSolver> =
+                    Box::new(Merge::from_config(merge_config, kind)?);
+                Ok(RegisteredSolver::Service(boxed))
+            }
+            v1alpha1::SolverKind::Template(template_config) => {
+                let boxed: Box<dyn ServiceSolver> = Box::new(Template::new(template_config, kind)?);
+                Ok(RegisteredSolver::Service(boxed))
+            }
+            #[cfg(feature = "testing")]
+            v1alpha1::SolverKind::Mock(mock_config) => {
                 let boxed: Box<dyn Solver> =
-                    Box::new(Merge::new(merge_config.partial_solvers.clone())?);
-                Ok(boxed)
+                    Box::new(Mock::new(mock_config).with_metrics(ctx.metrics));
+                Ok(RegisteredSolver::Global(RwLock::new(boxed)))
             }
         }
     }
 }
-
-impl TryFrom<&v1alpha1::SolverKind> for Box<dyn Solver> {
-    type Error = IpSourceError;
-
-    fn try_from(value: &v1alpha1::SolverKind) -> Result<Self, Self::Error> {
-        Box::try_from((*value).clone())
-    }
-}
@@ -0,0 +1,114 @@
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::{Namespace, Service};
+use kube::{Api, Client};
+use tracing::instrument;
+
+use super::ServiceSolver;
+use crate::{
+    crd::v1alpha1,
+    external_ip_source::{self, registry::SolverRegistry, solvers::SolverError},
+};
+
+#[derive(Debug)]
+pub struct NamespaceAnnotation {
+    namespaces_api: Api<Namespace>,
+    annotation_key: String,
+}
+
+impl NamespaceAnnotation {
+    pub fn new(config: v1alpha1::NamespaceAnnotationConfig, client: Client) -> NamespaceAnnotation {
+        NamespaceAnnotation {
+            namespaces_api: Api::all(client),
+            annotation_key: config.annotation_key,
+        }
+    }
+
+    /// All addresses in the annotation of the namespace `svc` lives in
+    async fn namespace_addresses(&self, svc: &Service) -> Result<Vec<IpAddr>, SolverError> {
+        let namespace = svc.metadata.namespace.as_deref().ok_or(SolverError {
+            reason: "service has no namespace".to_string(),
+        })?;
+        let ns = self
+            .namespaces_api
+            .get(namespace)
+            .await
+            .map_err(|e| SolverError {
+                reason: format!("unable to retrieve namespace {namespace}: {e}"),
+            })?;
+        let value = ns
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(&self.annotation_key))
+            .ok_or_else(|| SolverError {
+                reason: format!(
+                    "namespace {namespace} has no annotation {}",
+                    self.annotation_key
+                ),
+            })?;
+        parse_addresses(value)
+    }
+}
+
+/// Parse a comma- or whitespace-separated list of addresses
+fn parse_addresses(value: &str) -> Result<Vec<IpAddr>, SolverError> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .map(|token| {
+            token.parse::<IpAddr>().map_err(|e| SolverError {
+                reason: format!("invalid IP address `{token}` in namespace annotation: {e}"),
+            })
+        })
+        .collect()
+}
+
+#[async_trait]
+impl ServiceSolver for NamespaceAnnotation {
+    #[instrument]
+    async fn get_addresses(
+        &self,
+        kind: external_ip_source::AddressKind,
+        svc: &Service,
+        _: &SolverRegistry,
+    ) -> Result<Vec<IpAddr>, SolverError> {
+        Ok(self
+            .namespace_addresses(svc)
+            .await?
+            .into_iter()
+            .filter(|addr| kind.matches(addr))
+            .collect())
+    }
+
+    #[instrument]
+    async fn get_all_addresses(
+        &self,
+        svc: &Service,
+        _: &SolverRegistry,
+    ) -> Option<Result<Vec<IpAddr>, SolverError>> {
+        Some(self.namespace_addresses(svc).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_separated_addresses() {
+        assert_eq!(
+            parse_addresses("192.0.2.1, 2001:db8::1\n192.0.2.2").unwrap(),
+            ["192.0.2.1", "2001:db8::1", "192.0.2.2"]
+                .iter()
+                .map(|a| a.parse::<IpAddr>().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        assert!(parse_addresses("192.0.2.1,example.com").is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use std::{num::NonZeroUsize, sync::Arc};
+
+use tokio::sync::Semaphore;
+
+/// Bounds the number of queries to external services, such as IP APIs and name servers, that are in flight across all solvers.
+///
+/// This protects rate-limited upstream services from bursts of queries, regardless of how many services are reconciled at once.
+/// The default is unbounded.
+#[derive(Debug, Clone, Default)]
+pub struct OutboundLimit(Option<Arc<Semaphore>>);
+
+impl OutboundLimit {
+    pub fn new(max_queries: NonZeroUsize) -> OutboundLimit {
+        OutboundLimit(Some(Arc::new(Semaphore::new(max_queries.get()))))
+    }
+
+    /// Run `query` as soon as fewer than the maximum number of queries are in flight
+    pub async fn run<F: Future>(&self, query: F) -> F::Output {
+        let _permit = match &self.0 {
+            Some(semaphore) => Some(
+                semaphore
+                    .acquire()
+                    .await
+                    .expect("outbound query semaphore is never closed"),
+            ),
+            None => None,
+        };
+        query.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn bounds_concurrent_queries() {
+        let limit = OutboundLimit::new(NonZeroUsize::new(2).unwrap());
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let queries = (0..6).map(|_| {
+            let (limit, in_flight, max_in_flight) =
+                (limit.clone(), in_flight.clone(), max_in_flight.clone());
+            tokio::spawn(async move {
+                limit
+                    .run(async {
+                        let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_in_flight.fetch_max(current, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await
+            })
+        });
+        for query in queries.collect::<Vec<_>>() {
+            query.await.unwrap();
+        }
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,226 @@
+use std::{env, net::IpAddr};
+
+use async_trait::async_trait;
+use k8s_openapi::api::{
+    core::v1::{Node, Service},
+    discovery::v1::EndpointSlice,
+};
+use kube::{Api, Client, api::ListParams};
+use tracing::{debug, instrument};
+
+use super::{ServiceSolver, Solver};
+use crate::external_ip_source::{self, registry::SolverRegistry, solvers::SolverError};
+
+/// Environment variable containing the name of the node the manager runs on, usually set through the downward API
+const NODE_NAME_ENV: &str = "NODE_NAME";
+/// Label linking an EndpointSlice to its service
+const LABEL_SERVICE_NAME: &str = "kubernetes.io/service-name";
+
+/// Returns the addresses of the node the manager runs on.
+///
+/// Used as a [Solver] by default. As a [ServiceSolver], it only returns addresses for services with
+/// externalTrafficPolicy "Local" if the node hosts one of their endpoints.
+#[derive(Debug)]
+pub struct SelfNode {
+    nodes_api: Api<Node>,
+    endpoint_slices_api: Api<EndpointSlice>,
+}
+
+impl SelfNode {
+    pub fn new(client: Client) -> SelfNode {
+        SelfNode {
+            nodes_api: Api::all(client.clone()),
+            endpoint_slices_api: Api::all(client),
+        }
+    }
+
+    async fn node_addresses(
+        &self,
+        node_name: &str,
+        kind: external_ip_source::AddressKind,
+    ) -> Result<Vec<IpAddr>, SolverError> {
+        let node_addrs = self
+            .nodes_api
+            .get(node_name)
+            .await
+            .map_err(|e| SolverError {
+                reason: format!("unable to retrieve node {node_name}: {e}"),
+            })?
+            .status
+            .and_then(|status| status.addresses)
+            .unwrap_or_default();
+
+        let addrs_of_type = |addr_type: &str| -> Vec<IpAddr> {
+            node_addrs
+                .iter()
+                .filter(|addr| addr.type_ == addr_type)
+                .filter_map(|addr| addr.address.parse::<IpAddr>().ok())
+                .filter(|addr| kind.matches(addr))
+                .collect()
+        };
+        // Prefer ExternalIPs, only falling back to the InternalIP if the node has no external address
+        let external = addrs_of_type("ExternalIP");
+        if !external.is_empty() {
+            return Ok(external);
+        }
+        Ok(addrs_of_type("InternalIP"))
+    }
+
+    /// Whether `node_name` hosts a ready endpoint of `svc`
+    async fn hosts_endpoint(&self, svc: &Service, node_name: &str) -> Result<bool, SolverError> {
+        let namespace = svc.metadata.namespace.as_deref().unwrap_or_default();
+        let name = svc.metadata.name.as_deref().unwrap_or_default();
+        let list_params = ListParams::default()
+            .labels(&format!("{LABEL_SERVICE_NAME}={name}"))
+            .fields(&format!("metadata.namespace={namespace}"));
+        let slices = self
+            .endpoint_slices_api
+            .list(&list_params)
+            .await
+            .map_err(|e| SolverError {
+                reason: format!("unable to list endpoints of service {namespace}/{name}: {e}"),
+            })?;
+        Ok(hosts_ready_endpoint(&slices.items, node_name))
+    }
+}
+
+fn node_name() -> Result<String, SolverError> {
+    env::var(NODE_NAME_ENV).map_err(|_| SolverError {
+        reason: format!(
+            "{NODE_NAME_ENV} is not set, expose it to the manager through the downward API"
+        ),
+    })
+}
+
+/// Whether any endpoint in `slices` that isn't known to be unready runs on `node_name`
+fn hosts_ready_endpoint(slices: &[EndpointSlice], node_name: &str) -> bool {
+    slices
+        .iter()
+        .flat_map(|slice| &slice.endpoints)
+        .filter(|endpoint| {
+            endpoint
+                .conditions
+                .as_ref()
+                .and_then(|conditions| conditions.ready)
+                .unwrap_or(true)
+        })
+        .any(|endpoint| endpoint.node_name.as_deref() == Some(node_name))
+}
+
+#[async_trait]
+impl Solver for SelfNode {
+    #[instrument]
+    async fn get_addresses(
+        &mut self,
+        kind: external_ip_source::AddressKind,
+    ) -> Result<Vec<IpAddr>, SolverError> {
+        self.node_addresses(&node_name()?, kind).await
+    }
+}
+
+#[async_trait]
+impl ServiceSolver for SelfNode {
+    #[instrument(skip(svc, _solvers))]
+    async fn get_addresses(
+        &self,
+        kind: external_ip_source::AddressKind,
+        svc: &Service,
+        _solvers: &SolverRegistry,
+    ) -> Result<Vec<IpAddr>, SolverError> {
+        let node_name = node_name()?;
+        let local_policy = svc
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.external_traffic_policy.as_deref())
+            == Some("Local");
+        if local_policy && !self.hosts_endpoint(svc, &node_name).await? {
+            debug!(
+                msg = "node hosts no endpoint of service with local traffic policy, returning no addresses",
+                node = node_name
+            );
+            return Ok(vec![]);
+        }
+        self.node_addresses(&node_name, kind).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::discovery::v1::{Endpoint, EndpointConditions};
+
+    use super::*;
+
+    fn endpoint(node: &str, ready: Option<bool>) -> Endpoint {
+        Endpoint {
+            node_name: Some(node.to_string()),
+            conditions: Some(EndpointConditions {
+                ready,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// A client answering every request with `node`
+    fn node_client(node: serde_json::Value) -> Client {
+        let service = tower::service_fn(move |_: http::Request<kube::client::Body>| {
+            let node = node.clone();
+            async move {
+                Ok::<_, std::convert::Infallible>(
+                    http::Response::builder()
+                        .status(200)
+                        .body(kube::client::Body::from(node.to_string().into_bytes()))
+                        .unwrap(),
+                )
+            }
+        });
+        Client::new(service, "default")
+    }
+
+    #[tokio::test]
+    async fn prefers_external_node_addresses() {
+        let node = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Node",
+            "metadata": {"name": "worker-1"},
+            "status": {"addresses": [
+                {"type": "InternalIP", "address": "10.0.0.1"},
+                {"type": "InternalIP", "address": "fd00::1"},
+                {"type": "ExternalIP", "address": "2001:db8::1"},
+                {"type": "Hostname", "address": "worker-1"},
+            ]}
+        });
+        let solver = SelfNode::new(node_client(node));
+        assert_eq!(
+            solver
+                .node_addresses("worker-1", external_ip_source::AddressKind::IPv6)
+                .await
+                .unwrap(),
+            vec!["2001:db8::1".parse::<IpAddr>().unwrap()]
+        );
+        // Falls back to the internal address per family
+        assert_eq!(
+            solver
+                .node_addresses("worker-1", external_ip_source::AddressKind::IPv4)
+                .await
+                .unwrap(),
+            vec!["10.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn only_ready_endpoints_count() {
+        let slices = [EndpointSlice {
+            endpoints: vec![
+                endpoint("a", Some(true)),
+                endpoint("b", Some(false)),
+                endpoint("c", None),
+            ],
+            ..Default::default()
+        }];
+        assert!(hosts_ready_endpoint(&slices, "a"));
+        assert!(!hosts_ready_endpoint(&slices, "b"));
+        assert!(hosts_ready_endpoint(&slices, "c"));
+        assert!(!hosts_ready_endpoint(&slices, "d"));
+    }
+}
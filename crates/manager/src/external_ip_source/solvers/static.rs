@@ -2,15 +2,33 @@ use std::net::IpAddr;
 
 use async_trait::async_trait;
 use itertools::Itertools;
-use k8s_openapi::api::core::v1::Service;
-use tracing::{instrument, warn};
+use tracing::{debug, instrument, warn};
 
-use crate::external_ip_source::{
-    self, AddressKind,
-    registry::SolverRegistry,
-    solvers::{Solver, SolverError},
+use crate::{
+    crd::v1alpha1::StaticConfig,
+    external_ip_source::{
+        self, AddressKind, IpSourceError,
+        solvers::{Solver, SolverError},
+    },
 };
 
+/// Maximum number of addresses in a single range, to catch ranges that were mistyped
+const MAX_RANGE_SIZE: u128 = 256;
+
+/// Treat IPv4-mapped IPv6 addresses (`::ffff:192.0.2.1`) as the IPv4 address they represent
+fn normalize_mapped(addr: &IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => {
+                debug!(msg = "treating IPv4-mapped address in 'static' address source as IPv4", address = %v6, ipv4 = %v4);
+                IpAddr::V4(v4)
+            }
+            None => *addr,
+        },
+        IpAddr::V4(_) => *addr,
+    }
+}
+
 #[derive(Debug)]
 pub struct Static {
     addresses: Vec<IpAddr>,
@@ -20,6 +38,69 @@ impl Static {
     pub fn new(addresses: Vec<IpAddr>) -> Static {
         Static { addresses }
     }
+
+    /// Whether this solver yields any addresses of type `kind`, to catch static solvers placed in the block of the other family
+    pub fn yields_family(&self, kind: AddressKind) -> bool {
+        self.addresses.is_empty()
+            || self
+                .addresses
+                .iter()
+                .any(|addr| kind.matches(&addr.to_canonical()))
+    }
+
+    /// Build a solver returning the addresses of `config`, with all ranges expanded
+    pub fn from_config(config: StaticConfig) -> Result<Static, IpSourceError> {
+        let mut addresses = config.addresses;
+        for range in &config.ranges {
+            addresses.extend(expand_range(range)?);
+        }
+        Ok(Static::new(addresses))
+    }
+}
+
+/// Expand an inclusive range such as `192.0.2.1-192.0.2.4` into its individual addresses
+fn expand_range(range: &str) -> Result<Vec<IpAddr>, IpSourceError> {
+    let malformed =
+        |reason: &str| IpSourceError::Malformed(format!("invalid range `{range}`: {reason}"));
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| malformed("expected two addresses separated by -"))?;
+    let parse = |addr: &str| {
+        addr.trim()
+            .parse::<IpAddr>()
+            .map_err(|e| malformed(&e.to_string()))
+    };
+    let (start, end, kind) = match (parse(start)?, parse(end)?) {
+        (IpAddr::V4(start), IpAddr::V4(end)) => (
+            u128::from(u32::from(start)),
+            u128::from(u32::from(end)),
+            AddressKind::IPv4,
+        ),
+        (IpAddr::V6(start), IpAddr::V6(end)) => {
+            (u128::from(start), u128::from(end), AddressKind::IPv6)
+        }
+        _ => {
+            return Err(malformed(
+                "both ends must belong to the same address family",
+            ));
+        }
+    };
+    if start > end {
+        return Err(malformed(
+            "the start address is larger than the end address",
+        ));
+    }
+    if end - start >= MAX_RANGE_SIZE {
+        return Err(malformed(&format!(
+            "ranges may contain at most {MAX_RANGE_SIZE} addresses"
+        )));
+    }
+    Ok((start..=end)
+        .map(|addr| match kind {
+            AddressKind::IPv4 => IpAddr::V4((addr as u32).into()),
+            AddressKind::IPv6 => IpAddr::V6(addr.into()),
+        })
+        .collect())
 }
 
 #[async_trait]
@@ -28,24 +109,84 @@ impl Solver for Static {
     async fn get_addresses(
         &mut self,
         kind: external_ip_source::AddressKind,
-        _: &Service,
-        _: &SolverRegistry,
     ) -> Result<Vec<std::net::IpAddr>, SolverError> {
         Ok(self
             .addresses
-            .clone()
             .iter()
-            .filter_map(|addr| {
+            .map(normalize_mapped)
+            .filter(|addr| {
                 if kind == AddressKind::IPv4 && !addr.is_ipv4() {
                     warn!(msg = "ignoring non-IPv4 address in 'static' IPv4 address source");
-                    None
+                    false
                 } else if kind == AddressKind::IPv6 && !addr.is_ipv6() {
                     warn!(msg = "ignoring non-IPv6 address in 'static' IPv6 address source");
-                    None
+                    false
                 } else {
-                    Some(*addr)
+                    true
                 }
             })
             .collect_vec())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn static_solver() -> Static {
+        Static::new(vec![
+            "::ffff:192.0.2.1".parse().unwrap(),
+            "2001:db8::1".parse().unwrap(),
+        ])
+    }
+
+    #[tokio::test]
+    async fn expands_small_range() {
+        let mut solver = Static::from_config(StaticConfig {
+            addresses: vec!["2001:db8::1".parse().unwrap()],
+            ranges: vec!["10.0.0.1-10.0.0.4".to_string()],
+        })
+        .unwrap();
+        let addrs = solver.get_addresses(AddressKind::IPv4).await.unwrap();
+        assert_eq!(
+            addrs,
+            ["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.4"]
+                .map(|a| a.parse::<IpAddr>().unwrap())
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        for range in [
+            "10.0.0.0-10.0.255.255",
+            "2001:db8::-2001:db8::ffff",
+            "10.0.0.4-10.0.0.1",
+            "10.0.0.1-2001:db8::1",
+            "10.0.0.1",
+        ] {
+            assert!(
+                matches!(expand_range(range), Err(IpSourceError::Malformed(_))),
+                "{range} should be rejected"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn returns_mapped_address_as_ipv4() {
+        let addrs = static_solver()
+            .get_addresses(AddressKind::IPv4)
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec!["192.0.2.1".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn ignores_mapped_address_for_ipv6() {
+        let addrs = static_solver()
+            .get_addresses(AddressKind::IPv6)
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec!["2001:db8::1".parse::<IpAddr>().unwrap()]);
+    }
+}
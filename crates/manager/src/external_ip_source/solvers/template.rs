@@ -0,0 +1,297 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use itertools::Itertools;
+use k8s_openapi::api::core::v1::Service;
+use tracing::info;
+
+use crate::{
+    crd::v1alpha1::{self, SolverKind},
+    external_ip_source::{
+        self, AddressKind, IpSourceError,
+        registry::SolverRegistry,
+        solvers::{ServiceSolver, SolverError, select_part},
+    },
+};
+
+/// Builds an address by substituting the results of named sub-solvers into a textual template
+#[derive(Debug)]
+pub struct Template {
+    segments: Vec<Segment>,
+    solvers: BTreeMap<String, v1alpha1::PartialSolverKind>,
+    selector: v1alpha1::PartSelector,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    /// `{name}` or `{name/prefix_len}`, replaced with the (leading part of the) address returned by the solver `name`
+    Placeholder {
+        name: String,
+        prefix_len: Option<u8>,
+    },
+}
+
+impl Template {
+    pub fn new(
+        config: v1alpha1::TemplateConfig,
+        kind: AddressKind,
+    ) -> Result<Template, IpSourceError> {
+        let segments = parse(&config.template)?;
+        for segment in &segments {
+            if let Segment::Placeholder { name, prefix_len } = segment {
+                if !config.solvers.contains_key(name) {
+                    return Err(IpSourceError::Malformed(format!(
+                        "template references unknown solver {name}"
+                    )));
+                }
+                if let Some(prefix_len) = prefix_len {
+                    let (bits, group_bits) = match kind {
+                        AddressKind::IPv4 => (32, 8),
+                        AddressKind::IPv6 => (128, 16),
+                    };
+                    if *prefix_len > bits || prefix_len % group_bits != 0 {
+                        return Err(IpSourceError::Malformed(format!(
+                            "template prefix length {prefix_len} for {name} must be a multiple of {group_bits} and at most {bits} for {kind} addresses"
+                        )));
+                    }
+                }
+            }
+        }
+        if let Some(unused) = config.solvers.keys().find(|name| {
+            !segments
+                .iter()
+                .any(|s| matches!(s, Segment::Placeholder { name: n, .. } if n == *name))
+        }) {
+            return Err(IpSourceError::Malformed(format!(
+                "template solver {unused} is not used in the template"
+            )));
+        }
+
+        let template = Template {
+            segments,
+            solvers: config.solvers,
+            selector: config.part_selector.unwrap_or_default(),
+        };
+        // Render the template with placeholder addresses to catch templates that can never produce a valid address
+        let unspecified = match kind {
+            AddressKind::IPv4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            AddressKind::IPv6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let dummy_addrs = template
+            .solvers
+            .keys()
+            .map(|name| (name.clone(), unspecified))
+            .collect();
+        template
+            .render(&dummy_addrs, kind)
+            .map_err(|e| IpSourceError::Malformed(e.reason))?;
+        Ok(template)
+    }
+
+    fn render(
+        &self,
+        addrs: &BTreeMap<String, IpAddr>,
+        kind: AddressKind,
+    ) -> Result<IpAddr, SolverError> {
+        let rendered = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.clone(),
+                Segment::Placeholder { name, prefix_len } => {
+                    let addr = addrs[name];
+                    match prefix_len {
+                        None => addr.to_string(),
+                        Some(prefix_len) => leading_groups(&addr, *prefix_len),
+                    }
+                }
+            })
+            .join("");
+        let addr = rendered.parse::<IpAddr>().map_err(|e| SolverError {
+            reason: format!("template result {rendered} is not a valid address: {e}"),
+        })?;
+        if addr.is_ipv4() != (kind == AddressKind::IPv4) {
+            return Err(SolverError {
+                reason: format!("template result {rendered} is not an {kind} address"),
+            });
+        }
+        Ok(addr)
+    }
+}
+
+/// Format the first `prefix_len` bits of `addr` as dot- or colon-separated groups, without compression
+fn leading_groups(addr: &IpAddr, prefix_len: u8) -> String {
+    match addr {
+        IpAddr::V4(addr) => addr
+            .octets()
+            .iter()
+            .take(usize::from(prefix_len / 8))
+            .join("."),
+        IpAddr::V6(addr) => addr
+            .segments()
+            .iter()
+            .take(usize::from(prefix_len / 16))
+            .map(|s| format!("{s:x}"))
+            .join(":"),
+    }
+}
+
+fn parse(template: &str) -> Result<Vec<Segment>, IpSourceError> {
+    let mut segments = vec![];
+    let mut rest = template;
+    while !rest.is_empty() {
+        match rest.find('{') {
+            Some(0) => {
+                let end = rest.find('}').ok_or_else(|| {
+                    IpSourceError::Malformed(format!("unclosed placeholder in template {template}"))
+                })?;
+                let placeholder = &rest[1..end];
+                let (name, prefix_len) = match placeholder.split_once('/') {
+                    Some((name, prefix_len)) => (
+                        name,
+                        Some(prefix_len.parse::<u8>().map_err(|e| {
+                            IpSourceError::Malformed(format!(
+                                "invalid prefix length in template placeholder {placeholder}: {e}"
+                            ))
+                        })?),
+                    ),
+                    None => (placeholder, None),
+                };
+                if name.is_empty() || name.contains('{') {
+                    return Err(IpSourceError::Malformed(format!(
+                        "invalid placeholder {{{placeholder}}} in template {template}"
+                    )));
+                }
+                segments.push(Segment::Placeholder {
+                    name: name.to_string(),
+                    prefix_len,
+                });
+                rest = &rest[end + 1..];
+            }
+            Some(start) => {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+                rest = &rest[start..];
+            }
+            None => {
+                if rest.contains('}') {
+                    return Err(IpSourceError::Malformed(format!(
+                        "unopened placeholder in template {template}"
+                    )));
+                }
+                segments.push(Segment::Literal(rest.to_string()));
+                rest = "";
+            }
+        }
+    }
+    Ok(segments)
+}
+
+#[async_trait]
+impl ServiceSolver for Template {
+    async fn get_addresses(
+        &self,
+        kind: external_ip_source::AddressKind,
+        svc: &Service,
+        solvers: &SolverRegistry,
+    ) -> Result<Vec<IpAddr>, SolverError> {
+        let mut addrs = BTreeMap::new();
+        for (name, partial_solver) in &self.solvers {
+            let solver = solvers
+                .get(&(SolverKind::from(partial_solver), kind))
+                .ok_or(SolverError {
+                    reason: format!("solver {:?} not found", partial_solver),
+                })?;
+            let addrs_ret = solver
+                .get_addresses(kind, svc, solvers, Duration::from_secs(10))
+                .await??;
+            let addr = select_part(&addrs_ret, self.selector).ok_or(SolverError {
+                reason: format!("template solver {name} returned no addresses"),
+            })?;
+            addrs.insert(name.clone(), addr);
+        }
+        let addr = self.render(&addrs, kind)?;
+        info!(
+            msg = "template: rendered address from solvers",
+            solver_addresses = ?addrs,
+            ?addr
+        );
+        Ok(vec![addr])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crd::v1alpha1::{PartialSolverKind, StaticConfig, TemplateConfig};
+
+    use super::*;
+
+    fn config(template: &str, names: &[&str]) -> TemplateConfig {
+        TemplateConfig {
+            template: template.to_string(),
+            solvers: names
+                .iter()
+                .map(|name| {
+                    (
+                        name.to_string(),
+                        PartialSolverKind::Static(StaticConfig {
+                            addresses: vec![],
+                            ranges: vec![],
+                        }),
+                    )
+                })
+                .collect(),
+            part_selector: None,
+        }
+    }
+
+    #[test]
+    fn renders_prefix_with_suffix() -> Result<(), IpSourceError> {
+        let template = Template::new(config("{prefix/64}::1", &["prefix"]), AddressKind::IPv6)?;
+        let addrs = BTreeMap::from([("prefix".to_string(), "2001:db8:1:2::5".parse().unwrap())]);
+        assert_eq!(
+            template.render(&addrs, AddressKind::IPv6).unwrap(),
+            "2001:db8:1:2::1".parse::<IpAddr>().unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn renders_ipv4_octets() -> Result<(), IpSourceError> {
+        let template = Template::new(
+            config("{net/24}.{host}", &["net", "host"]),
+            AddressKind::IPv4,
+        );
+        // a full address cannot be used as a single octet
+        assert!(matches!(template, Err(IpSourceError::Malformed(_))));
+        let template = Template::new(config("{net/24}.7", &["net"]), AddressKind::IPv4)?;
+        let addrs = BTreeMap::from([("net".to_string(), "192.0.2.1".parse().unwrap())]);
+        assert_eq!(
+            template.render(&addrs, AddressKind::IPv4).unwrap(),
+            "192.0.2.7".parse::<IpAddr>().unwrap()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_unknown_and_unused_solvers() {
+        assert!(Template::new(config("{prefix/64}::1", &[]), AddressKind::IPv6).is_err());
+        assert!(Template::new(config("::1", &["prefix"]), AddressKind::IPv6).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_prefix_len() {
+        assert!(Template::new(config("{prefix/60}::1", &["prefix"]), AddressKind::IPv6).is_err());
+        assert!(Template::new(config("{prefix/8}.0.0.1", &["prefix"]), AddressKind::IPv6).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_placeholders() {
+        assert!(Template::new(config("{prefix/64::1", &["prefix"]), AddressKind::IPv6).is_err());
+        assert!(Template::new(config("prefix}::1", &["prefix"]), AddressKind::IPv6).is_err());
+    }
+}
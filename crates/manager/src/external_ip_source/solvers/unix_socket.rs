@@ -0,0 +1,191 @@
+use std::{
+    net::IpAddr,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use tokio::{io::AsyncReadExt, net::UnixStream, time::timeout};
+use tracing::{debug, instrument};
+
+use crate::{
+    crd::v1alpha1::{UnixSocketConfig, UnixSocketFormat},
+    external_ip_source::{
+        AddressKind,
+        solvers::{SolverCacheState, SolverError},
+    },
+};
+
+use super::Solver;
+
+/// How long addresses read from the socket are reused, so that the sidecar isn't queried for every service
+const CACHE_DURATION: Duration = Duration::from_secs(10);
+/// Maximum time to wait for the sidecar to send its response
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Returns the addresses a sidecar writes to a unix domain socket.
+///
+/// The sidecar is expected to write its response and close the connection for every client that connects.
+#[derive(Debug)]
+pub struct UnixSocket {
+    path: PathBuf,
+    format: UnixSocketFormat,
+    cached: Option<(Instant, Vec<IpAddr>)>,
+    cache_duration: Duration,
+}
+
+impl UnixSocket {
+    pub fn new(config: UnixSocketConfig) -> UnixSocket {
+        UnixSocket {
+            path: PathBuf::from(config.path),
+            format: config.format.unwrap_or_default(),
+            cached: None,
+            cache_duration: CACHE_DURATION,
+        }
+    }
+
+    /// Reuse responses for at least `min_cache_ttl` instead of [CACHE_DURATION], if it is longer
+    pub fn with_min_cache_ttl(mut self, min_cache_ttl: Duration) -> UnixSocket {
+        self.cache_duration = self.cache_duration.max(min_cache_ttl);
+        self
+    }
+
+    async fn read_response(&self) -> Result<String, SolverError> {
+        let path = self.path.display();
+        let mut stream = UnixStream::connect(&self.path)
+            .await
+            .map_err(|e| SolverError {
+                reason: format!("unable to connect to socket {path}: {e}"),
+            })?;
+        let mut response = String::new();
+        timeout(READ_TIMEOUT, stream.read_to_string(&mut response))
+            .await
+            .map_err(|_| SolverError {
+                reason: format!("timed out reading from socket {path}"),
+            })?
+            .map_err(|e| SolverError {
+                reason: format!("unable to read from socket {path}: {e}"),
+            })?;
+        Ok(response)
+    }
+}
+
+/// Parse the response of the sidecar in the given format
+fn parse_addresses(response: &str, format: UnixSocketFormat) -> Result<Vec<IpAddr>, SolverError> {
+    match format {
+        UnixSocketFormat::Text => response
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                token.parse::<IpAddr>().map_err(|e| SolverError {
+                    reason: format!("invalid IP address `{token}` in socket response: {e}"),
+                })
+            })
+            .collect(),
+        UnixSocketFormat::Json => serde_json::from_str(response).map_err(|e| SolverError {
+            reason: format!("socket response is not a JSON array of addresses: {e}"),
+        }),
+    }
+}
+
+#[async_trait]
+impl Solver for UnixSocket {
+    #[instrument]
+    async fn get_addresses(&mut self, kind: AddressKind) -> Result<Vec<IpAddr>, SolverError> {
+        let addrs = match &self.cached {
+            Some((read_at, addrs)) if read_at.elapsed() < self.cache_duration => {
+                debug!(msg = "using cached socket response", addresses = ?addrs);
+                addrs.clone()
+            }
+            _ => {
+                let addrs = parse_addresses(&self.read_response().await?, self.format)?;
+                self.cached = Some((Instant::now(), addrs.clone()));
+                addrs
+            }
+        };
+        Ok(addrs
+            .into_iter()
+            .filter(|addr| kind.matches(addr))
+            .collect())
+    }
+
+    fn cache_state(&self) -> Option<SolverCacheState> {
+        let (read_at, addrs) = self
+            .cached
+            .as_ref()
+            .filter(|(read_at, _)| read_at.elapsed() < self.cache_duration)?;
+        Some(SolverCacheState {
+            cached_addresses: addrs.clone(),
+            remaining_secs: Some(
+                self.cache_duration
+                    .saturating_sub(read_at.elapsed())
+                    .as_secs(),
+            ),
+            backing_off: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::AsyncWriteExt, net::UnixListener};
+
+    use super::*;
+
+    /// Serve `response` on a temporary socket for a single connection, returning a solver reading from it
+    fn solver_for(name: &str, response: &'static str, format: UnixSocketFormat) -> UnixSocket {
+        let path =
+            std::env::temp_dir().join(format!("externalip-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        UnixSocket::new(UnixSocketConfig {
+            path: path.to_string_lossy().to_string(),
+            format: Some(format),
+        })
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn reads_and_caches_text_response() {
+        let mut solver = solver_for("text", "192.0.2.1\n2001:db8::1\n", UnixSocketFormat::Text);
+        assert_eq!(
+            solver.get_addresses(AddressKind::IPv4).await.unwrap(),
+            addrs(&["192.0.2.1"])
+        );
+        // the server only accepts a single connection, so this is answered from the cache
+        assert_eq!(
+            solver.get_addresses(AddressKind::IPv6).await.unwrap(),
+            addrs(&["2001:db8::1"])
+        );
+    }
+
+    #[tokio::test]
+    async fn reads_json_response() {
+        let mut solver = solver_for(
+            "json",
+            r#"["192.0.2.1", "192.0.2.2"]"#,
+            UnixSocketFormat::Json,
+        );
+        assert_eq!(
+            solver.get_addresses(AddressKind::IPv4).await.unwrap(),
+            addrs(&["192.0.2.1", "192.0.2.2"])
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_for_missing_socket() {
+        let mut solver = UnixSocket::new(UnixSocketConfig {
+            path: "/nonexistent/externalip.sock".to_string(),
+            format: None,
+        });
+        let err = solver.get_addresses(AddressKind::IPv4).await.unwrap_err();
+        assert!(err.reason.contains("/nonexistent/externalip.sock"), "{err}");
+    }
+}
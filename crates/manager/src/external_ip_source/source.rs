@@ -1,16 +1,27 @@
 use std::{
+    collections::HashMap,
     fmt::{Debug, Display},
-    net::IpAddr,
-    time::Duration,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
+use itertools::Itertools;
 use k8s_openapi::api::core::v1::Service;
-use tokio::time::timeout;
+use rand::seq::SliceRandom;
 use tracing::{debug, error, info, instrument, warn};
 
 use crate::{
+    cidr::Cidr,
     crd::v1alpha1::{self, CLUSTER_EXTERNAL_IP_SOURCE_KIND, SolverKind},
-    external_ip_source::{self, IpSourceError, registry::SolverRegistry, solvers::SolverError},
+    external_ip_source::{
+        self, IpSourceError,
+        registry::SolverRegistry,
+        solvers::{
+            CombinedResults, IpApiSolver, Merge, SolverError, Static, Template,
+            is_service_dependent,
+        },
+    },
 };
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -25,7 +36,7 @@ impl ExternalIpSourceKind {
         }
     }
 
-    fn name(&self) -> String {
+    pub fn name(&self) -> String {
         match self {
             ExternalIpSourceKind::Cluster(name) => name.to_string(),
         }
@@ -37,28 +48,163 @@ pub struct ExternalIpSource {
     kind: ExternalIpSourceKind,
     v4: Option<SolverList>,
     v6: Option<SolverList>,
+    /// Addresses of both families added to the results of their family, see [v1alpha1::ClusterExternalIpSourceSpec::static_addresses]
+    static_addresses: Vec<IpAddr>,
+    dual_stack_policy: DualStackPolicy,
+    /// Minimum time between queries for the same service, see [v1alpha1::ClusterExternalIpSourceSpec::refresh_interval_seconds]
+    refresh_interval: Option<Duration>,
+    /// Only kept if `refresh_interval` is set
+    last_results: Mutex<LastResults>,
 }
+
+/// When each service, keyed by namespace and name, was last resolved, and the resulting addresses
+type LastResults = HashMap<(String, String), (Instant, Vec<IpAddr>)>;
 impl ExternalIpSource {
-    #[instrument]
+    #[instrument(skip_all, fields(
+        svc.namespace = svc.metadata.namespace.as_deref().unwrap_or_default(),
+        svc.name = svc.metadata.name.as_deref().unwrap_or_default(),
+        source = self.name(),
+    ))]
     pub async fn query(
         &self,
         svc: &Service,
         solvers: &SolverRegistry,
     ) -> Result<Vec<IpAddr>, external_ip_source::IpSourceError> {
-        let mut addrs = vec![];
-        if let Some(v4) = &self.v4 {
-            addrs.extend(v4.query(AddressKind::IPv4, svc, solvers).await?);
-        }
-        if let Some(v6) = &self.v6 {
-            addrs.extend(v6.query(AddressKind::IPv6, svc, solvers).await?);
+        let Some(refresh_interval) = self.refresh_interval else {
+            return self.resolve(svc, solvers).await;
+        };
+        let key = (
+            svc.metadata.namespace.clone().unwrap_or_default(),
+            svc.metadata.name.clone().unwrap_or_default(),
+        );
+        let last = self
+            .last_results
+            .lock()
+            .expect("last results lock poisoned")
+            .get(&key)
+            .filter(|(resolved_at, _)| resolved_at.elapsed() < refresh_interval)
+            .map(|(_, addrs)| addrs.clone());
+        if let Some(addrs) = last {
+            debug!(
+                msg = "refresh interval of source has not passed yet, reusing addresses",
+                addresses = ?addrs
+            );
+            return Ok(addrs);
         }
+        let addrs = self.resolve(svc, solvers).await?;
+        let mut last_results = self
+            .last_results
+            .lock()
+            .expect("last results lock poisoned");
+        // expired results are never reused, and would otherwise pile up for services that were deleted
+        last_results.retain(|_, (resolved_at, _)| resolved_at.elapsed() < refresh_interval);
+        last_results.insert(key, (Instant::now(), addrs.clone()));
         Ok(addrs)
     }
 
+    /// Reuse the results of `previous`, an older version of this source with the same configuration,
+    /// so that the refresh interval carries over when sources are reloaded
+    pub fn keep_results_of(&mut self, previous: ExternalIpSource) {
+        self.last_results = previous.last_results;
+    }
+
+    async fn resolve(
+        &self,
+        svc: &Service,
+        solvers: &SolverRegistry,
+    ) -> Result<Vec<IpAddr>, external_ip_source::IpSourceError> {
+        let combined = CombinedResults::default();
+        let (preferred, fallback) = match self.dual_stack_policy {
+            DualStackPolicy::Both => {
+                let mut addrs = vec![];
+                for kind in [AddressKind::IPv4, AddressKind::IPv6] {
+                    if let Some(res) = self.query_family(kind, svc, solvers, &combined).await {
+                        addrs.extend(res?);
+                    }
+                }
+                return Ok(addrs);
+            }
+            DualStackPolicy::PreferV4 => (AddressKind::IPv4, AddressKind::IPv6),
+            DualStackPolicy::PreferV6 => (AddressKind::IPv6, AddressKind::IPv4),
+        };
+
+        match self.query_family(preferred, svc, solvers, &combined).await {
+            Some(Ok(addrs)) if !addrs.is_empty() => return Ok(addrs),
+            Some(Ok(_)) => {
+                info!(msg = "preferred address family returned no addresses, falling back", preferred = %preferred);
+            }
+            Some(Err(e)) => {
+                info!(msg = "preferred address family failed, falling back", preferred = %preferred, err = e.to_string());
+            }
+            None => {}
+        }
+        match self.query_family(fallback, svc, solvers, &combined).await {
+            Some(res) => res,
+            None => Err(IpSourceError::Solver(SolverError {
+                reason: format!(
+                    "preferred address family returned no addresses and no {fallback} solvers are configured"
+                ),
+            })),
+        }
+    }
+
+    /// Query the solvers of the `kind` block and add the static addresses of this family to their results.
+    /// Returns [None] if neither are configured for this family
+    async fn query_family(
+        &self,
+        kind: AddressKind,
+        svc: &Service,
+        solvers: &SolverRegistry,
+        combined: &CombinedResults,
+    ) -> Option<Result<Vec<IpAddr>, IpSourceError>> {
+        let list = match kind {
+            AddressKind::IPv4 => &self.v4,
+            AddressKind::IPv6 => &self.v6,
+        };
+        let statics = self
+            .static_addresses
+            .iter()
+            .filter(|addr| kind.matches(addr))
+            .copied()
+            .collect_vec();
+        match list {
+            Some(list) => Some(
+                list.query(kind, svc, solvers, combined)
+                    .await
+                    .map(|mut addrs| {
+                        for addr in statics {
+                            if !addrs.contains(&addr) {
+                                addrs.push(addr);
+                            }
+                        }
+                        addrs
+                    }),
+            ),
+            None if !statics.is_empty() => Some(Ok(statics)),
+            None => None,
+        }
+    }
+
     pub fn name(&self) -> String {
         self.kind.name()
     }
 
+    /// Solvers of the `kind` block, empty if the block is not configured or invalid
+    pub fn solvers(&self, kind: AddressKind) -> &[SolverKind] {
+        let list = match kind {
+            AddressKind::IPv4 => &self.v4,
+            AddressKind::IPv6 => &self.v6,
+        };
+        list.as_ref()
+            .map(|list| list.solver_refs.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Addresses added to the results of their family, see [v1alpha1::ClusterExternalIpSourceSpec::static_addresses]
+    pub fn static_addresses(&self) -> &[IpAddr] {
+        &self.static_addresses
+    }
+
     #[allow(unused)]
     pub fn kind(&self) -> String {
         self.kind.kind()
@@ -69,11 +215,36 @@ impl TryFrom<v1alpha1::ClusterExternalIPSource> for ExternalIpSource {
     type Error = IpSourceError;
 
     fn try_from(value: v1alpha1::ClusterExternalIPSource) -> Result<Self, IpSourceError> {
-        if value.spec.ipv4.is_none() && value.spec.ipv6.is_none() {
+        if value.spec.ipv4.is_none()
+            && value.spec.ipv6.is_none()
+            && value.spec.static_addresses.is_empty()
+        {
+            return Err(IpSourceError::Malformed(
+                "ClusterExternalIpSource needs at least one source block or static address defined"
+                    .to_string(),
+            ));
+        }
+        if value
+            .spec
+            .ipv4
+            .as_ref()
+            .is_some_and(|ipv4| ipv4.embed_v4_in_v6_prefix.is_some())
+        {
             return Err(IpSourceError::Malformed(
-                "ClusterExternalIpSource needs at least one source block defined".to_string(),
+                "embedV4InV6Prefix can only be set for the ipv6 block".to_string(),
             ));
         }
+        if let Some(ipv4) = &value.spec.ipv4 {
+            check_solver_families(ipv4, AddressKind::IPv4)?;
+        }
+        if let Some(ipv6) = &value.spec.ipv6 {
+            // NAT64 blocks resolve IPv4 addresses and embed them into the IPv6 prefix afterwards
+            let kind = match ipv6.embed_v4_in_v6_prefix {
+                Some(_) => AddressKind::IPv4,
+                None => AddressKind::IPv6,
+            };
+            check_solver_families(ipv6, kind)?;
+        }
         Ok(ExternalIpSource {
             kind: ExternalIpSourceKind::Cluster(value.metadata.name.unwrap_or_default()),
             v4: value.spec.ipv4.and_then(|ipv4| {
@@ -86,6 +257,19 @@ impl TryFrom<v1alpha1::ClusterExternalIPSource> for ExternalIpSource {
                 error!(msg = "unable to create IPv6 solvers for ClusterExternalIpSource", err = ?e);
             }).ok()
             }),
+            static_addresses: value
+                .spec
+                .static_addresses
+                .iter()
+                .map(IpAddr::to_canonical)
+                .unique()
+                .collect(),
+            dual_stack_policy: value.spec.dual_stack_policy.unwrap_or_default().into(),
+            refresh_interval: value
+                .spec
+                .refresh_interval_seconds
+                .map(|secs| Duration::from_secs(secs.into())),
+            last_results: Mutex::default(),
         })
     }
 }
@@ -102,6 +286,15 @@ pub enum AddressKind {
     IPv4,
     IPv6,
 }
+impl AddressKind {
+    /// Whether `addr` belongs to this address family
+    pub fn matches(&self, addr: &IpAddr) -> bool {
+        match self {
+            AddressKind::IPv4 => addr.is_ipv4(),
+            AddressKind::IPv6 => addr.is_ipv6(),
+        }
+    }
+}
 impl Display for AddressKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -120,15 +313,87 @@ impl Display for AddressKind {
 struct SolverList {
     solver_refs: Vec<SolverKind>,
     query_mode: QueryMode,
+    min_addresses: usize,
+    max_addresses: Option<usize>,
+    selection: Selection,
+    on_error: SolverErrorPolicy,
+    exclude_addresses: Vec<IpAddr>,
+    exclude_cidrs: Vec<Cidr>,
+    /// If set, IPv4 addresses are resolved and embedded into this /96 prefix instead
+    nat64_prefix: Option<Ipv6Addr>,
+    /// Results of the last query, if none of the solvers depend on the service being resolved.
+    /// Sources are rebuilt on every registry refresh, so this only lives for a single reconciliation run
+    cached: Option<Mutex<Option<Vec<IpAddr>>>>,
 }
 
 impl SolverList {
-    #[instrument]
+    #[instrument(skip_all, fields(
+        svc.namespace = svc.metadata.namespace.as_deref().unwrap_or_default(),
+        svc.name = svc.metadata.name.as_deref().unwrap_or_default(),
+        %kind,
+    ))]
     async fn query(
         &self,
         kind: AddressKind,
         svc: &Service,
         solvers: &SolverRegistry,
+        combined: &CombinedResults,
+    ) -> Result<Vec<IpAddr>, IpSourceError> {
+        let solver_kind = match self.nat64_prefix {
+            Some(_) => AddressKind::IPv4,
+            None => kind,
+        };
+        let addrs = match &self.cached {
+            Some(cache) => {
+                let cached = cache.lock().expect("cache lock poisoned").clone();
+                match cached {
+                    Some(addrs) => {
+                        debug!(msg = "reusing addresses resolved for previous service", addresses = ?addrs);
+                        addrs
+                    }
+                    None => {
+                        let addrs = self
+                            .query_solvers(solver_kind, svc, solvers, combined)
+                            .await?;
+                        *cache.lock().expect("cache lock poisoned") = Some(addrs.clone());
+                        addrs
+                    }
+                }
+            }
+            None => {
+                self.query_solvers(solver_kind, svc, solvers, combined)
+                    .await?
+            }
+        };
+        let addrs = match self.nat64_prefix {
+            Some(prefix) => addrs
+                .into_iter()
+                .filter_map(|addr| match addr {
+                    IpAddr::V4(v4) => Some(IpAddr::V6(embed_v4_in_v6(prefix, v4))),
+                    IpAddr::V6(_) => None,
+                })
+                .collect(),
+            None => addrs,
+        };
+        if addrs.len() < self.min_addresses {
+            return Err(IpSourceError::TooFewAddresses {
+                kind,
+                found: addrs.len(),
+                min: self.min_addresses,
+            });
+        }
+        Ok(match self.max_addresses {
+            Some(max) => self.selection.select(addrs, max),
+            None => addrs,
+        })
+    }
+
+    async fn query_solvers(
+        &self,
+        kind: AddressKind,
+        svc: &Service,
+        solvers: &SolverRegistry,
+        combined: &CombinedResults,
     ) -> Result<Vec<IpAddr>, IpSourceError> {
         // should be guaranteed from our TryFrom impl
         assert!(
@@ -137,6 +402,10 @@ impl SolverList {
         );
 
         let mut collected_addrs: Vec<IpAddr> = vec![];
+        // Reported instead of the generic error if nothing was resolved, so that the service is retried sooner
+        let mut timeout_err = None;
+        // Solvers that succeeded without returning addresses, listed in the error if nothing was resolved
+        let mut empty_solvers = vec![];
         let svc_name = format!(
             "{}/{}",
             svc.metadata.namespace.clone().unwrap_or_default(),
@@ -148,21 +417,31 @@ impl SolverList {
                 .ok_or(IpSourceError::Solver(SolverError {
                     reason: format!("solver {:?} not found", (solv_ref, kind)),
                 }))?;
-            let mut guard = timeout(Duration::from_secs(5), solver.write())
+            let result = match combined
+                .get_addresses(kind, solv_ref, solver, svc, solvers)
                 .await
-                .map_err(|_| {
-                    IpSourceError::Solver(SolverError {
-                        reason: "timed out waiting for solver".to_string(),
-                    })
-                })?;
-            match guard.get_addresses(kind, svc, solvers).await {
+            {
+                Some(result) => result.map_err(IpSourceError::from),
+                None => match solver
+                    .get_addresses(kind, svc, solvers, Duration::from_secs(5))
+                    .await
+                {
+                    Ok(result) => result.map_err(IpSourceError::from),
+                    Err(_) => Err(IpSourceError::Timeout {
+                        solver: solv_ref.name().to_string(),
+                    }),
+                },
+            };
+            match result {
                 Ok(addrs) => {
+                    let addrs = self.without_excluded(addrs);
                     if addrs.is_empty() {
                         info!(
                             msg = "solver returned no addresses",
                             svc = svc_name,
                             solver = ?solv_ref
                         );
+                        empty_solvers.push(solv_ref.name());
                         continue;
                     }
                     debug!(msg = "retrieved externalIP addresses from solver", svc = svc_name, solver = ?solv_ref, addresses = ?addrs);
@@ -187,6 +466,12 @@ impl SolverList {
                         err = e.to_string(),
                         svc = svc_name
                     );
+                    if self.on_error == SolverErrorPolicy::Fail {
+                        return Err(e);
+                    }
+                    if e.is_timeout() {
+                        timeout_err = Some(e);
+                    }
                     continue;
                 }
             }
@@ -200,13 +485,73 @@ impl SolverList {
                 );
                 Ok(collected_addrs)
             }
-            QueryMode::FirstFound | QueryMode::All => Err(IpSourceError::Solver(SolverError {
-                reason: "no IP addresses were returned by any source".to_string(),
+            QueryMode::FirstFound | QueryMode::All => Err(timeout_err.unwrap_or_else(|| {
+                let mut reason = "no IP addresses were returned by any source".to_string();
+                if !empty_solvers.is_empty() {
+                    reason.push_str(&format!(
+                        ", solvers without addresses: {}",
+                        empty_solvers.join(", ")
+                    ));
+                }
+                IpSourceError::Solver(SolverError { reason })
             })),
         }
     }
 }
 
+impl SolverList {
+    fn without_excluded(&self, addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+        addrs
+            .into_iter()
+            .filter(|addr| {
+                let excluded = self.exclude_addresses.contains(addr)
+                    || self.exclude_cidrs.iter().any(|cidr| cidr.contains(addr));
+                if excluded {
+                    debug!(msg = "excluding address from solver result", address = %addr);
+                }
+                !excluded
+            })
+            .collect()
+    }
+}
+
+/// Enabled solvers that are listed more than once in `config`
+pub fn duplicate_solvers(config: &v1alpha1::IpSolversConfig) -> Vec<&SolverKind> {
+    config.enabled_solvers().duplicates().collect()
+}
+
+/// Reject solvers that are malformed or can never yield addresses of type `kind`, where this is known without querying them,
+/// such as static solvers with only IPv6 addresses in the ipv4 block, so that only this source is rejected
+fn check_solver_families(
+    config: &v1alpha1::IpSolversConfig,
+    kind: AddressKind,
+) -> Result<(), IpSourceError> {
+    for solver in config.enabled_solvers() {
+        match solver {
+            // Building the solver expands its ranges, rejecting malformed or oversized ones
+            SolverKind::Static(static_config) => {
+                let solver = Static::from_config(static_config.clone())?;
+                if !solver.yields_family(kind) {
+                    return Err(IpSourceError::Malformed(format!(
+                        "static solver only has addresses of the other family, but is used to resolve {kind} addresses"
+                    )));
+                }
+            }
+            // Building the merge checks its masks and values against the family
+            SolverKind::Merge(merge_config) => {
+                Merge::from_config(merge_config.clone(), kind)?;
+            }
+            SolverKind::IpAPI(ip_api_config) => IpApiSolver::validate(ip_api_config)?,
+            // Building the template parses it and checks its placeholders against the family
+            SolverKind::Template(template_config) => {
+                Template::new(template_config.clone(), kind)?;
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
 impl TryFrom<v1alpha1::IpSolversConfig> for SolverList {
     type Error = IpSourceError;
 
@@ -216,13 +561,51 @@ impl TryFrom<v1alpha1::IpSolversConfig> for SolverList {
                 "sources list is empty".to_string(),
             ));
         }
+        if let Some(prefix) = value.embed_v4_in_v6_prefix
+            && u128::from(prefix) as u32 != 0
+        {
+            return Err(IpSourceError::Malformed(format!(
+                "embedV4InV6Prefix {prefix} is not a /96 prefix, the last 32 bits must be zero"
+            )));
+        }
+        let exclude_cidrs = value
+            .exclude_cidrs
+            .iter()
+            .map(|cidr| cidr.parse::<Cidr>().map_err(IpSourceError::Malformed))
+            .collect::<Result<Vec<_>, _>>()?;
+        for duplicate in duplicate_solvers(&value) {
+            warn!(msg = "ignoring duplicate solver", solver = ?duplicate);
+        }
+        if let (Some(min), Some(max)) = (value.min_addresses, value.max_addresses)
+            && max < min
+        {
+            return Err(IpSourceError::Malformed(format!(
+                "maxAddresses {max} is lower than minAddresses {min}"
+            )));
+        }
+        // Disabled solvers are neither registered nor queried
+        let solver_refs = value.enabled_solvers().unique().cloned().collect_vec();
+        let cached = (!solver_refs.iter().any(is_service_dependent)).then(Mutex::default);
         Ok(SolverList {
-            solver_refs: value.solvers,
+            solver_refs,
             query_mode: value.query_mode.unwrap_or_default().into(),
+            min_addresses: value.min_addresses.unwrap_or_default() as usize,
+            max_addresses: value.max_addresses.map(|max| max as usize),
+            selection: value.selection.unwrap_or_default().into(),
+            on_error: value.on_error.unwrap_or_default().into(),
+            nat64_prefix: value.embed_v4_in_v6_prefix,
+            exclude_addresses: value.exclude_addresses,
+            exclude_cidrs,
+            cached,
         })
     }
 }
 
+/// Embed `v4` into the last 32 bits of the /96 `prefix`, as described in RFC 6052
+fn embed_v4_in_v6(prefix: Ipv6Addr, v4: Ipv4Addr) -> Ipv6Addr {
+    Ipv6Addr::from(u128::from(prefix) | u128::from(u32::from(v4)))
+}
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 enum QueryMode {
     FirstFound,
@@ -236,3 +619,888 @@ impl From<v1alpha1::QueryMode> for QueryMode {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+enum Selection {
+    Deterministic,
+    PreferSolverOrder,
+    Random,
+}
+impl From<v1alpha1::Selection> for Selection {
+    fn from(value: v1alpha1::Selection) -> Self {
+        match value {
+            v1alpha1::Selection::Deterministic => Selection::Deterministic,
+            v1alpha1::Selection::PreferSolverOrder => Selection::PreferSolverOrder,
+            v1alpha1::Selection::Random => Selection::Random,
+        }
+    }
+}
+impl Selection {
+    /// Keep at most `max` of the unique addresses in `addrs`, which are ordered by the solver that returned them
+    fn select(&self, addrs: Vec<IpAddr>, max: usize) -> Vec<IpAddr> {
+        let mut addrs = addrs.into_iter().unique().collect_vec();
+        if addrs.len() > max {
+            debug!(msg = "resolved more addresses than allowed, selecting a subset", found = addrs.len(), max, selection = ?self);
+        }
+        match self {
+            Selection::Deterministic => addrs.sort(),
+            Selection::PreferSolverOrder => (),
+            Selection::Random => addrs.shuffle(&mut rand::rng()),
+        }
+        addrs.truncate(max);
+        addrs
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+enum SolverErrorPolicy {
+    Skip,
+    Fail,
+}
+impl From<v1alpha1::SolverErrorPolicy> for SolverErrorPolicy {
+    fn from(value: v1alpha1::SolverErrorPolicy) -> Self {
+        match value {
+            v1alpha1::SolverErrorPolicy::Skip => SolverErrorPolicy::Skip,
+            v1alpha1::SolverErrorPolicy::Fail => SolverErrorPolicy::Fail,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+enum DualStackPolicy {
+    Both,
+    PreferV4,
+    PreferV6,
+}
+impl From<v1alpha1::DualStackPolicy> for DualStackPolicy {
+    fn from(value: v1alpha1::DualStackPolicy) -> Self {
+        match value {
+            v1alpha1::DualStackPolicy::Both => DualStackPolicy::Both,
+            v1alpha1::DualStackPolicy::PreferV4 => DualStackPolicy::PreferV4,
+            v1alpha1::DualStackPolicy::PreferV6 => DualStackPolicy::PreferV6,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use tokio::sync::RwLock;
+
+    use crate::{
+        crd::v1alpha1::{
+            ClientCertificateConfig, ClusterExternalIPSource, ClusterExternalIpSourceSpec,
+            DnsHostnameConfig, IpAPIConfig, IpSolverProvider, IpSolversConfig, PartialSolverKind,
+            SolverEntry, StaticConfig, TemplateConfig,
+        },
+        external_ip_source::solvers::{RegisteredSolver, ServiceSolver, Solver, Static},
+    };
+
+    use super::*;
+
+    const V4_ADDR: &str = "192.0.2.1";
+    const V6_ADDR: &str = "2001:db8::1";
+
+    fn static_solver(addrs: &[&str]) -> SolverKind {
+        SolverKind::Static(StaticConfig {
+            addresses: addrs.iter().map(|a| a.parse().unwrap()).collect(),
+            ranges: vec![],
+        })
+    }
+
+    /// Build a source with one static solver per family, along with a registry containing these solvers
+    fn source_with_policy(
+        v4: &[&str],
+        v6: &[&str],
+        policy: v1alpha1::DualStackPolicy,
+    ) -> (ExternalIpSource, SolverRegistry) {
+        let mut registry: SolverRegistry = HashMap::new();
+        for (addrs, kind) in [(v4, AddressKind::IPv4), (v6, AddressKind::IPv6)] {
+            let solver = Static::new(addrs.iter().map(|a| a.parse().unwrap()).collect());
+            registry.insert(
+                (static_solver(addrs), kind),
+                RegisteredSolver::Global(RwLock::new(Box::new(solver))),
+            );
+        }
+        let ceips = ClusterExternalIPSource::new(
+            "test",
+            ClusterExternalIpSourceSpec {
+                ipv4: Some(IpSolversConfig {
+                    solvers: vec![static_solver(v4).into()],
+                    ..Default::default()
+                }),
+                ipv6: Some(IpSolversConfig {
+                    solvers: vec![static_solver(v6).into()],
+                    ..Default::default()
+                }),
+                static_addresses: vec![],
+                dual_stack_policy: Some(policy),
+                refresh_interval_seconds: None,
+            },
+        );
+        (ExternalIpSource::try_from(ceips).unwrap(), registry)
+    }
+
+    #[derive(Debug)]
+    struct FailingSolver {}
+    #[async_trait]
+    impl Solver for FailingSolver {
+        async fn get_addresses(&mut self, _: AddressKind) -> Result<Vec<IpAddr>, SolverError> {
+            Err(SolverError {
+                reason: "failing solver".to_string(),
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_skipped_timeout_if_nothing_resolved() {
+        let solver = static_solver(&[V4_ADDR]);
+        let mut registry: SolverRegistry = HashMap::new();
+        registry.insert(
+            (solver.clone(), AddressKind::IPv4),
+            RegisteredSolver::Global(RwLock::new(Box::new(Static::new(addrs(&[V4_ADDR]))))),
+        );
+        let list = SolverList::try_from(IpSolversConfig {
+            solvers: vec![solver.clone().into()],
+            ..Default::default()
+        })
+        .unwrap();
+        // the solver is still busy with another query, so it can't be locked in time
+        let Some(RegisteredSolver::Global(busy)) = registry.get(&(solver, AddressKind::IPv4))
+        else {
+            unreachable!("static solvers are global");
+        };
+        let _guard = busy.write().await;
+        let result = list
+            .query(
+                AddressKind::IPv4,
+                &Service::default(),
+                &registry,
+                &CombinedResults::default(),
+            )
+            .await;
+        assert!(matches!(result, Err(IpSourceError::Timeout { solver }) if solver == "static"));
+    }
+
+    /// Build an IPv4 solver list where a failing solver is queried before a working one
+    fn failing_first_list(policy: v1alpha1::SolverErrorPolicy) -> (SolverList, SolverRegistry) {
+        let failing = SolverKind::DnsHostname(DnsHostnameConfig {
+            host: "failing.invalid".to_string(),
+            record_type: None,
+            txt_pattern: None,
+            selection: None,
+        });
+        let working = static_solver(&[V4_ADDR]);
+        let mut registry: SolverRegistry = HashMap::new();
+        registry.insert(
+            (failing.clone(), AddressKind::IPv4),
+            RegisteredSolver::Global(RwLock::new(Box::new(FailingSolver {}))),
+        );
+        registry.insert(
+            (working.clone(), AddressKind::IPv4),
+            RegisteredSolver::Global(RwLock::new(Box::new(Static::new(addrs(&[V4_ADDR]))))),
+        );
+        let list = SolverList::try_from(IpSolversConfig {
+            solvers: vec![failing.into(), working.into()],
+            on_error: Some(policy),
+            ..Default::default()
+        })
+        .unwrap();
+        (list, registry)
+    }
+
+    #[tokio::test]
+    async fn skips_failing_solver() -> Result<()> {
+        let (list, registry) = failing_first_list(v1alpha1::SolverErrorPolicy::Skip);
+        let result = list
+            .query(
+                AddressKind::IPv4,
+                &Service::default(),
+                &registry,
+                &CombinedResults::default(),
+            )
+            .await?;
+        assert_eq!(result, addrs(&[V4_ADDR]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fails_on_failing_solver() {
+        let (list, registry) = failing_first_list(v1alpha1::SolverErrorPolicy::Fail);
+        let result = list
+            .query(
+                AddressKind::IPv4,
+                &Service::default(),
+                &registry,
+                &CombinedResults::default(),
+            )
+            .await;
+        assert!(matches!(result, Err(IpSourceError::Solver(_))));
+    }
+
+    #[tokio::test]
+    async fn lists_empty_solvers_in_error() {
+        let dns = SolverKind::DnsHostname(DnsHostnameConfig {
+            host: "empty.invalid".to_string(),
+            record_type: None,
+            txt_pattern: None,
+            selection: None,
+        });
+        let empty_static = static_solver(&[]);
+        let mut registry: SolverRegistry = HashMap::new();
+        for solver in [&dns, &empty_static] {
+            registry.insert(
+                (solver.clone(), AddressKind::IPv4),
+                RegisteredSolver::Global(RwLock::new(Box::new(Static::new(vec![])))),
+            );
+        }
+        let list = SolverList::try_from(IpSolversConfig {
+            solvers: vec![dns.into(), empty_static.into()],
+            ..Default::default()
+        })
+        .unwrap();
+        let err = list
+            .query(
+                AddressKind::IPv4,
+                &Service::default(),
+                &registry,
+                &CombinedResults::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("solvers without addresses: dnsHostname, static"),
+            "{err}"
+        );
+    }
+
+    /// Query a list of static IPv4 solvers, all registered in the returned registry
+    async fn query_static_solvers(config: IpSolversConfig) -> Result<Vec<IpAddr>, IpSourceError> {
+        let mut registry: SolverRegistry = HashMap::new();
+        for solver in config.enabled_solvers() {
+            if let SolverKind::Static(static_config) = solver {
+                registry.insert(
+                    (solver.clone(), AddressKind::IPv4),
+                    RegisteredSolver::Global(RwLock::new(Box::new(Static::new(
+                        static_config.addresses.clone(),
+                    )))),
+                );
+            }
+        }
+        SolverList::try_from(config)?
+            .query(
+                AddressKind::IPv4,
+                &Service::default(),
+                &registry,
+                &CombinedResults::default(),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn skips_disabled_solvers() -> Result<()> {
+        let disabled = static_solver(&["192.0.2.1"]);
+        let config = IpSolversConfig {
+            query_mode: Some(v1alpha1::QueryMode::All),
+            solvers: vec![
+                SolverEntry {
+                    kind: disabled.clone(),
+                    enabled: Some(false),
+                },
+                static_solver(&["198.51.100.1"]).into(),
+            ],
+            ..Default::default()
+        };
+        let list = SolverList::try_from(config.clone())?;
+        assert!(!list.solver_refs.contains(&disabled));
+        let result = query_static_solvers(config).await?;
+        assert_eq!(result, addrs(&["198.51.100.1"]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn excludes_exact_addresses() -> Result<()> {
+        let result = query_static_solvers(IpSolversConfig {
+            solvers: vec![static_solver(&["192.0.2.1", "192.0.2.2"]).into()],
+            exclude_addresses: vec!["192.0.2.1".parse()?],
+            ..Default::default()
+        })
+        .await?;
+        assert_eq!(result, addrs(&["192.0.2.2"]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn excludes_cidrs_across_solvers() -> Result<()> {
+        let result = query_static_solvers(IpSolversConfig {
+            query_mode: Some(v1alpha1::QueryMode::All),
+            solvers: vec![
+                static_solver(&["192.0.2.1", "198.51.100.1"]).into(),
+                static_solver(&["192.0.2.200", "203.0.113.1"]).into(),
+            ],
+            exclude_cidrs: vec!["192.0.2.0/24".to_string()],
+            ..Default::default()
+        })
+        .await?;
+        assert_eq!(result, addrs(&["198.51.100.1", "203.0.113.1"]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn falls_through_when_all_addresses_are_excluded() -> Result<()> {
+        let result = query_static_solvers(IpSolversConfig {
+            solvers: vec![
+                static_solver(&["192.0.2.1"]).into(),
+                static_solver(&["198.51.100.1"]).into(),
+            ],
+            exclude_cidrs: vec!["192.0.2.0/24".to_string()],
+            ..Default::default()
+        })
+        .await?;
+        assert_eq!(result, addrs(&["198.51.100.1"]));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_exclude_cidr() {
+        let result = SolverList::try_from(IpSolversConfig {
+            solvers: vec![static_solver(&[V4_ADDR]).into()],
+            exclude_cidrs: vec!["192.0.2.0/40".to_string()],
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(IpSourceError::Malformed(_))));
+    }
+
+    #[tokio::test]
+    async fn embeds_v4_into_nat64_prefix() -> Result<()> {
+        let solver = static_solver(&["192.0.2.33", "198.51.100.1"]);
+        let mut registry: SolverRegistry = HashMap::new();
+        registry.insert(
+            (solver.clone(), AddressKind::IPv4),
+            RegisteredSolver::Global(RwLock::new(Box::new(Static::new(addrs(&[
+                "192.0.2.33",
+                "198.51.100.1",
+            ]))))),
+        );
+        let list = SolverList::try_from(IpSolversConfig {
+            solvers: vec![solver.into()],
+            query_mode: Some(v1alpha1::QueryMode::All),
+            embed_v4_in_v6_prefix: Some("64:ff9b::".parse()?),
+            ..Default::default()
+        })?;
+        let result = list
+            .query(
+                AddressKind::IPv6,
+                &Service::default(),
+                &registry,
+                &CombinedResults::default(),
+            )
+            .await?;
+        assert_eq!(result, addrs(&["64:ff9b::c000:221", "64:ff9b::c633:6401"]));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_nat64_prefix_longer_than_96() {
+        let result = SolverList::try_from(IpSolversConfig {
+            solvers: vec![static_solver(&[V4_ADDR]).into()],
+            embed_v4_in_v6_prefix: Some("64:ff9b::1".parse().unwrap()),
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(IpSourceError::Malformed(_))));
+    }
+
+    #[test]
+    fn rejects_nat64_prefix_in_ipv4_block() {
+        let ceips = ClusterExternalIPSource::new(
+            "test",
+            ClusterExternalIpSourceSpec {
+                ipv4: Some(IpSolversConfig {
+                    solvers: vec![static_solver(&[V4_ADDR]).into()],
+                    embed_v4_in_v6_prefix: Some("64:ff9b::".parse().unwrap()),
+                    ..Default::default()
+                }),
+                ipv6: None,
+                static_addresses: vec![],
+                dual_stack_policy: None,
+                refresh_interval_seconds: None,
+            },
+        );
+        assert!(matches!(
+            ExternalIpSource::try_from(ceips),
+            Err(IpSourceError::Malformed(_))
+        ));
+    }
+
+    fn source_with_solvers(
+        ipv4: Vec<SolverKind>,
+        ipv6: Vec<SolverKind>,
+        embed_v4_in_v6_prefix: Option<Ipv6Addr>,
+    ) -> Result<ExternalIpSource, IpSourceError> {
+        let block = |solvers: Vec<SolverKind>| {
+            (!solvers.is_empty()).then(|| IpSolversConfig {
+                solvers: solvers.into_iter().map(SolverEntry::from).collect(),
+                ..Default::default()
+            })
+        };
+        let mut ipv6 = block(ipv6);
+        if let Some(ipv6) = &mut ipv6 {
+            ipv6.embed_v4_in_v6_prefix = embed_v4_in_v6_prefix;
+        }
+        ExternalIpSource::try_from(ClusterExternalIPSource::new(
+            "test",
+            ClusterExternalIpSourceSpec {
+                ipv4: block(ipv4),
+                ipv6,
+                static_addresses: vec![],
+                dual_stack_policy: None,
+                refresh_interval_seconds: None,
+            },
+        ))
+    }
+
+    #[test]
+    fn rejects_static_solvers_of_other_family() {
+        for (ipv4, ipv6) in [
+            (vec![static_solver(&[V6_ADDR])], vec![]),
+            (vec![], vec![static_solver(&[V4_ADDR])]),
+        ] {
+            assert!(matches!(
+                source_with_solvers(ipv4, ipv6, None),
+                Err(IpSourceError::Malformed(msg)) if msg.contains("other family")
+            ));
+        }
+        // Mixed solvers, IPv4-mapped addresses and NAT64 blocks are fine
+        assert!(
+            source_with_solvers(vec![static_solver(&[V4_ADDR, V6_ADDR])], vec![], None).is_ok()
+        );
+        assert!(
+            source_with_solvers(vec![static_solver(&["::ffff:192.0.2.1"])], vec![], None).is_ok()
+        );
+        assert!(
+            source_with_solvers(
+                vec![],
+                vec![static_solver(&[V4_ADDR])],
+                Some("64:ff9b::".parse().unwrap())
+            )
+            .is_ok()
+        );
+        let ranges = SolverKind::Static(StaticConfig {
+            addresses: vec![],
+            ranges: vec!["2001:db8::1-2001:db8::4".to_string()],
+        });
+        assert!(source_with_solvers(vec![ranges], vec![], None).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_ip_api_solvers() {
+        let ip_api = |edit: fn(&mut IpAPIConfig)| {
+            let mut config = IpAPIConfig {
+                provider: IpSolverProvider::MyIp,
+                fallback_providers: vec![],
+                headers: Default::default(),
+                url: None,
+                ratelimit_status_codes: vec![429],
+                client_certificate: None,
+                pinned_public_keys: vec![],
+            };
+            edit(&mut config);
+            SolverKind::IpAPI(config)
+        };
+        assert!(source_with_solvers(vec![ip_api(|_| ())], vec![], None).is_ok());
+        for invalid in [
+            ip_api(|c| c.pinned_public_keys = vec!["sha256/AAAA".to_string()]),
+            ip_api(|c| {
+                c.client_certificate = Some(ClientCertificateConfig {
+                    cert_file: "/nonexistent/tls.crt".to_string(),
+                    key_file: "/nonexistent/tls.key".to_string(),
+                })
+            }),
+            ip_api(|c| c.ratelimit_status_codes = vec![1000]),
+            ip_api(|c| c.fallback_providers = vec![IpSolverProvider::JsonArray]),
+        ] {
+            assert!(
+                matches!(
+                    source_with_solvers(vec![invalid.clone()], vec![], None),
+                    Err(IpSourceError::Malformed(_))
+                ),
+                "{invalid:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        let template = |template: &str| {
+            SolverKind::Template(TemplateConfig {
+                template: template.to_string(),
+                solvers: [(
+                    "prefix".to_string(),
+                    PartialSolverKind::Static(StaticConfig {
+                        addresses: vec![V6_ADDR.parse().unwrap()],
+                        ranges: vec![],
+                    }),
+                )]
+                .into(),
+                part_selector: None,
+            })
+        };
+        assert!(source_with_solvers(vec![], vec![template("{prefix/64}::1")], None).is_ok());
+        for invalid in ["{prefix/64::1", "{other/64}::1", "{prefix/65}::1"] {
+            assert!(
+                matches!(
+                    source_with_solvers(vec![], vec![template(invalid)], None),
+                    Err(IpSourceError::Malformed(_))
+                ),
+                "{invalid} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_static_ranges() {
+        for range in ["192.0.2.1-192.0.3.1", "192.0.2.4-192.0.2.1", "192.0.2.1"] {
+            let solver = SolverKind::Static(StaticConfig {
+                addresses: vec![V4_ADDR.parse().unwrap()],
+                ranges: vec![range.to_string()],
+            });
+            assert!(
+                matches!(
+                    source_with_solvers(vec![solver], vec![], None),
+                    Err(IpSourceError::Malformed(msg)) if msg.contains("invalid range")
+                ),
+                "{range} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_merges_of_other_family() {
+        let merge = SolverKind::Merge(v1alpha1::MergeConfig {
+            partial_solvers: vec![v1alpha1::PartialSolver {
+                solver: Some(v1alpha1::PartialSolverKind::Static(StaticConfig {
+                    addresses: vec![V6_ADDR.parse().unwrap()],
+                    ranges: vec![],
+                })),
+                value: None,
+                mask: Some("ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff".parse().unwrap()),
+                mask_prefix_len: None,
+                part_selector: None,
+            }],
+            mode: None,
+        });
+        assert!(matches!(
+            source_with_solvers(vec![merge.clone()], vec![], None),
+            Err(IpSourceError::Malformed(_))
+        ));
+        assert!(source_with_solvers(vec![], vec![merge], None).is_ok());
+    }
+
+    #[test]
+    fn skips_solvers_of_unknown_family() {
+        let dns = SolverKind::DnsHostname(DnsHostnameConfig {
+            host: "example.com".to_string(),
+            record_type: None,
+            txt_pattern: None,
+            selection: None,
+        });
+        assert!(source_with_solvers(vec![dns.clone()], vec![dns], None).is_ok());
+    }
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn caches_service_independent_results() -> Result<()> {
+        let (source, registry) =
+            source_with_policy(&[V4_ADDR], &[V6_ADDR], v1alpha1::DualStackPolicy::PreferV4);
+        source.query(&Service::default(), &registry).await?;
+        // A different registry would yield different results, unless the previous ones are reused
+        let (_, other_registry) = source_with_policy(
+            &["198.51.100.1"],
+            &[V6_ADDR],
+            v1alpha1::DualStackPolicy::PreferV4,
+        );
+        let result = source.query(&Service::default(), &other_registry).await;
+        assert_eq!(result?, addrs(&[V4_ADDR]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reuses_results_within_refresh_interval() -> Result<()> {
+        let source = |refresh_interval_seconds| {
+            ExternalIpSource::try_from(ClusterExternalIPSource::new(
+                "test",
+                ClusterExternalIpSourceSpec {
+                    ipv4: Some(IpSolversConfig {
+                        solvers: vec![static_solver(&[V4_ADDR]).into()],
+                        ..Default::default()
+                    }),
+                    ipv6: None,
+                    static_addresses: vec![],
+                    dual_stack_policy: None,
+                    refresh_interval_seconds,
+                },
+            ))
+            .unwrap()
+        };
+        let (_, registry) = source_with_policy(&[V4_ADDR], &[], v1alpha1::DualStackPolicy::Both);
+        // The same solver returning different addresses, so that a new query is noticed
+        let other_registry: SolverRegistry = HashMap::from([(
+            (static_solver(&[V4_ADDR]), AddressKind::IPv4),
+            RegisteredSolver::Global(RwLock::new(Box::new(Static::new(addrs(&["198.51.100.1"]))))),
+        )]);
+
+        let first = source(Some(3600));
+        assert_eq!(
+            first.query(&Service::default(), &registry).await?,
+            addrs(&[V4_ADDR])
+        );
+        // Reloading an unchanged source keeps its results until the interval has passed
+        let mut reloaded = source(Some(3600));
+        reloaded.keep_results_of(first);
+        assert_eq!(
+            reloaded.query(&Service::default(), &other_registry).await?,
+            addrs(&[V4_ADDR])
+        );
+        let mut expired = source(Some(0));
+        expired.keep_results_of(reloaded);
+        assert_eq!(
+            expired.query(&Service::default(), &other_registry).await?,
+            addrs(&["198.51.100.1"])
+        );
+
+        // Expired results of other services are dropped
+        let mut other_svc = Service::default();
+        other_svc.metadata.name = Some("other".to_string());
+        expired.query(&other_svc, &other_registry).await?;
+        let last_results = expired.last_results.lock().unwrap();
+        assert_eq!(
+            last_results.keys().collect::<Vec<_>>(),
+            vec![&(String::new(), "other".to_string())]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn both_returns_all_families() -> Result<()> {
+        let (source, registry) =
+            source_with_policy(&[V4_ADDR], &[V6_ADDR], v1alpha1::DualStackPolicy::Both);
+        let result = source.query(&Service::default(), &registry).await?;
+        assert_eq!(result, addrs(&[V4_ADDR, V6_ADDR]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefer_v4_skips_v6() -> Result<()> {
+        let (source, registry) =
+            source_with_policy(&[V4_ADDR], &[V6_ADDR], v1alpha1::DualStackPolicy::PreferV4);
+        let result = source.query(&Service::default(), &registry).await?;
+        assert_eq!(result, addrs(&[V4_ADDR]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefer_v4_falls_back_to_v6() -> Result<()> {
+        let (source, registry) =
+            source_with_policy(&[], &[V6_ADDR], v1alpha1::DualStackPolicy::PreferV4);
+        let result = source.query(&Service::default(), &registry).await?;
+        assert_eq!(result, addrs(&[V6_ADDR]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefer_v6_skips_v4() -> Result<()> {
+        let (source, registry) =
+            source_with_policy(&[V4_ADDR], &[V6_ADDR], v1alpha1::DualStackPolicy::PreferV6);
+        let result = source.query(&Service::default(), &registry).await?;
+        assert_eq!(result, addrs(&[V6_ADDR]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prefer_v6_falls_back_to_v4() -> Result<()> {
+        let (source, registry) =
+            source_with_policy(&[V4_ADDR], &[], v1alpha1::DualStackPolicy::PreferV6);
+        let result = source.query(&Service::default(), &registry).await?;
+        assert_eq!(result, addrs(&[V4_ADDR]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn routes_static_addresses_by_family() -> Result<()> {
+        let (_, registry) = source_with_policy(&[V4_ADDR], &[], v1alpha1::DualStackPolicy::Both);
+        let source = |policy| {
+            ExternalIpSource::try_from(ClusterExternalIPSource::new(
+                "test",
+                ClusterExternalIpSourceSpec {
+                    ipv4: Some(IpSolversConfig {
+                        solvers: vec![static_solver(&[V4_ADDR]).into()],
+                        ..Default::default()
+                    }),
+                    ipv6: None,
+                    static_addresses: addrs(&["2001:db8::2", "::ffff:198.51.100.7", V4_ADDR]),
+                    dual_stack_policy: Some(policy),
+                    refresh_interval_seconds: None,
+                },
+            ))
+            .unwrap()
+        };
+
+        let result = source(v1alpha1::DualStackPolicy::Both)
+            .query(&Service::default(), &registry)
+            .await?;
+        assert_eq!(result, addrs(&[V4_ADDR, "198.51.100.7", "2001:db8::2"]));
+        // Static addresses count as results of their family, even without a block for it
+        let result = source(v1alpha1::DualStackPolicy::PreferV6)
+            .query(&Service::default(), &registry)
+            .await?;
+        assert_eq!(result, addrs(&["2001:db8::2"]));
+        Ok(())
+    }
+
+    /// Addresses in the order solvers returned them, including a duplicate
+    fn over_cap() -> Vec<IpAddr> {
+        addrs(&["198.51.100.2", "192.0.2.9", "198.51.100.2", "192.0.2.1"])
+    }
+
+    #[test]
+    fn deterministic_selection_keeps_lowest_addresses() {
+        assert_eq!(
+            Selection::Deterministic.select(over_cap(), 2),
+            addrs(&["192.0.2.1", "192.0.2.9"])
+        );
+    }
+
+    #[test]
+    fn solver_order_selection_keeps_first_addresses() {
+        assert_eq!(
+            Selection::PreferSolverOrder.select(over_cap(), 2),
+            addrs(&["198.51.100.2", "192.0.2.9"])
+        );
+    }
+
+    #[test]
+    fn random_selection_keeps_unique_subset() {
+        let selected = Selection::Random.select(over_cap(), 2);
+        assert_eq!(selected.len(), 2);
+        assert!(selected.iter().all_unique());
+        assert!(selected.iter().all(|addr| over_cap().contains(addr)));
+    }
+
+    #[test]
+    fn rejects_max_below_min_addresses() {
+        let config = IpSolversConfig {
+            solvers: vec![static_solver(&[V4_ADDR]).into()],
+            min_addresses: Some(2),
+            max_addresses: Some(1),
+            ..Default::default()
+        };
+        assert!(matches!(
+            SolverList::try_from(config),
+            Err(IpSourceError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn dedupes_identical_solvers() {
+        let ip_api = SolverKind::IpAPI(v1alpha1::IpAPIConfig {
+            fallback_providers: vec![],
+            provider: v1alpha1::IpSolverProvider::Ipify,
+            headers: Default::default(),
+            url: None,
+            ratelimit_status_codes: vec![429],
+            client_certificate: None,
+            pinned_public_keys: vec![],
+        });
+        let config = IpSolversConfig {
+            solvers: vec![
+                ip_api.clone().into(),
+                static_solver(&[V4_ADDR]).into(),
+                ip_api.clone().into(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(duplicate_solvers(&config), vec![&ip_api]);
+
+        let list = SolverList::try_from(config).unwrap();
+        assert_eq!(list.solver_refs, vec![ip_api, static_solver(&[V4_ADDR])]);
+    }
+
+    /// Service solver answering both families at once, counting how often it was queried
+    #[derive(Debug)]
+    struct CountingSolver {
+        queries: Arc<AtomicUsize>,
+    }
+    #[async_trait]
+    impl ServiceSolver for CountingSolver {
+        async fn get_addresses(
+            &self,
+            kind: AddressKind,
+            svc: &Service,
+            solvers: &SolverRegistry,
+        ) -> Result<Vec<IpAddr>, SolverError> {
+            Ok(self
+                .get_all_addresses(svc, solvers)
+                .await
+                .unwrap()?
+                .into_iter()
+                .filter(|addr| kind.matches(addr))
+                .collect())
+        }
+
+        async fn get_all_addresses(
+            &self,
+            _: &Service,
+            _: &SolverRegistry,
+        ) -> Option<Result<Vec<IpAddr>, SolverError>> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            Some(Ok(addrs(&[V4_ADDR, V6_ADDR])))
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_combined_solver_once_for_both_families() -> Result<()> {
+        let solver = SolverKind::LoadBalancerIngress(v1alpha1::LoadBalancerIngressConfig {});
+        let queries = Arc::new(AtomicUsize::new(0));
+        let mut registry: SolverRegistry = HashMap::new();
+        for kind in [AddressKind::IPv4, AddressKind::IPv6] {
+            registry.insert(
+                (solver.clone(), kind),
+                RegisteredSolver::Service(Box::new(CountingSolver {
+                    queries: queries.clone(),
+                })),
+            );
+        }
+        let block = IpSolversConfig {
+            solvers: vec![solver.into()],
+            ..Default::default()
+        };
+        let source = ExternalIpSource::try_from(ClusterExternalIPSource::new(
+            "test",
+            ClusterExternalIpSourceSpec {
+                ipv4: Some(block.clone()),
+                ipv6: Some(block),
+                static_addresses: vec![],
+                dual_stack_policy: None,
+                refresh_interval_seconds: None,
+            },
+        ))?;
+
+        let result = source.query(&Service::default(), &registry).await?;
+        assert_eq!(result, addrs(&[V4_ADDR, V6_ADDR]));
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+}
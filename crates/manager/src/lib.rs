@@ -1,65 +1,385 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::hash::RandomState;
 use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
 use error::Error;
 use external_ip_source::ExternalIpSourceKind;
 use itertools::Itertools;
-use k8s_openapi::api::core::v1::{Service, ServiceSpec};
+use k8s_openapi::api::core::v1::{ObjectReference, Pod, Service, ServiceSpec};
 use kube::api::{ObjectMeta, Patch, PatchParams};
 use kube::runtime::events::EventType;
 use kube::{Api, Client, Resource};
-use svc::{ExternalIpSvc, ServiceFinder};
-use tracing::error;
+use svc::{AnnotationKeys, ExternalIpSvc, ServiceFinder};
+use tracing::{debug, error, warn};
 use tracing::{info, instrument};
 
 use crate::events::EventRecorder;
-use crate::external_ip_source::IPSourceRegistry;
+use crate::external_ip_source::{
+    DiskCache, HttpClientConfig, IPSourceRegistry, IpSourceError, OutboundLimit, SolverContext,
+    socks_proxy,
+};
 use crate::svc::FinderError;
 
+pub use crate::debug_state::{
+    DebugState, SharedDebugState, SolverDebugState, SourceDebugState, serve_debug_state,
+};
+pub use crate::external_ip_source::{LastSourceError, ResolverProtocol, SolverCacheState};
+pub use crate::metrics::{Metrics, serve_metrics};
+pub use crate::publish_self::{PublishSelfConfig, SelfTarget, parse_self_target};
+pub use crate::reachability::{ReachabilityProbe, TcpProbe};
+pub use crate::report::ServiceReport;
+pub use crate::svc::{DEFAULT_ANNOTATION_PREFIX, parse_annotation_prefix};
+pub use crate::watch::watch_ip_sources;
+
+mod cidr;
 pub mod crd;
+mod debug_state;
 mod error;
 mod events;
 mod external_ip_source;
+mod metrics;
+mod publish_self;
+mod reachability;
+mod report;
+mod retry;
 mod svc;
+mod watch;
 
 const ACTION_UPDATE_EIPS: &str = "UpdatingExternlIPs";
+const ACTION_RECONCILE: &str = "Reconciling";
 const MANAGER_ID: &str = "externalip-manager";
+// Identical summaries are only re-published after this interval to avoid flooding the event log
+const SUMMARY_EVENT_MIN_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 pub struct Manager {
     config: ManagerConfig,
     svc_finder: ServiceFinder,
     ip_sources: IPSourceRegistry,
+    metrics: Arc<Metrics>,
     client: Client,
     events: EventRecorder,
+    annotations: AnnotationKeys,
+    summary_ref: Option<ObjectReference>,
+    last_summary: Option<(ReconcileSummary, Instant)>,
+    /// Namespace and name of services whose IP source did not exist yet or whose solvers timed out,
+    /// to be retried after the given delay before the next full run
+    pending_svcs: BTreeMap<(String, String), Duration>,
+    /// When the IP source of a service was first found to be missing, see [ManagerConfig::source_missing_grace]
+    source_missing_since: HashMap<(String, String), Instant>,
+    /// Number of managed addresses last applied to each service, see [ManagerConfig::address_drop_threshold]
+    applied_address_counts: HashMap<(String, String), usize>,
+    /// Service at which the last run was aborted, see [ManagerConfig::reconcile_run_timeout]
+    resume_from: Option<(String, String)>,
+    /// Probe for resolved addresses, see [ManagerConfig::verify_reachable]
+    reachability_probe: Option<Arc<dyn ReachabilityProbe>>,
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone)]
 pub struct ManagerConfig {
     pub dry_run: bool,
+    /// Run as a canary next to a primary instance: never modify services, but count every service whose
+    /// resolved addresses differ from the live ones in `externalip_canary_diff_total`. Implies `dry_run`
+    pub compare_with_live: bool,
+    /// Allow clearing the externalIPs of a service if no addresses could be resolved
+    pub allow_empty: bool,
+    /// Keep externalIPs that were not added by the manager instead of replacing the entire list
+    pub merge_with_existing: bool,
+    /// Delay after which services referencing a not-yet-existing IP source are retried, see [Manager::reconcile_pending_svcs]
+    pub unknown_source_retry: Duration,
+    /// Delay after which services whose solvers timed out are retried, see [Manager::reconcile_pending_svcs]
+    pub solver_timeout_retry: Duration,
+    /// Time for which the addresses of a service are kept after its IP source disappeared, after which the
+    /// managed addresses are removed. If unset, addresses are kept until the source exists again
+    pub source_missing_grace: Option<Duration>,
+    /// Maximum time to spend reconciling a single service during a full run.
+    /// Should be larger than the solver timeout, so that it only catches services that are stuck otherwise
+    pub service_reconcile_timeout: Option<Duration>,
+    /// Maximum time for an entire run of [Manager::reconcile_svcs], so that runs can't stack up in pathological cases.
+    /// Runs stop once it has passed and report [Error::RunTimeout] along with the errors of the services reconciled before.
+    /// Services that were already updated keep their new addresses, and the next run starts with the remaining ones
+    pub reconcile_run_timeout: Option<Duration>,
+    /// Only manage services in these namespaces, listing each one separately so that the manager only needs
+    /// RBAC permissions in them. Namespaces the manager may not list services in are skipped with a warning.
+    /// All namespaces are managed if empty
+    pub namespaces: Vec<String>,
+    /// Skip services of type LoadBalancer unless they opt in through an annotation, as their cloud provider may already manage their addresses
+    pub skip_loadbalancer_services: bool,
+    /// Drop resolved addresses that are also ClusterIPs of the service, instead of only warning about them
+    pub reject_self_collision: bool,
+    /// Only write resolved addresses that pass this probe, so that a misbehaving solver can't advertise unreachable addresses
+    pub verify_reachable: Option<TcpProbe>,
+    /// Publish an `AddressCountDropped` event if the resolved addresses of a service shrink by at least this many
+    /// compared to the last update, as this often means that an upstream is partially failing. The addresses are still applied
+    pub address_drop_threshold: Option<NonZeroUsize>,
+    /// Log every annotated service found at info level. Otherwise, only the total count is logged at info level
+    pub verbose_svc_logging: bool,
+    /// Annotate each ClusterExternalIPSource with the services referencing it after every full run
+    pub annotate_source_references: bool,
+    /// Fail [Manager::new] if no ClusterExternalIPSources exist or they can't be listed
+    pub require_sources: bool,
+    /// Publish addresses as a comma-separated list in this service annotation instead of `spec.externalIPs`
+    pub target_annotation: Option<String>,
+    /// Prefix of the annotations read and written by the manager, [DEFAULT_ANNOTATION_PREFIX] if unset.
+    /// Must be a valid DNS subdomain, see [parse_annotation_prefix]
+    pub annotation_prefix: Option<String>,
+    /// Maximum time to establish connections to external services such as IP APIs, in addition to their total request timeout
+    pub http_connect_timeout: Option<Duration>,
+    /// How long idle connections to external services are kept open for reuse
+    pub http_pool_idle_timeout: Option<Duration>,
+    /// Maximum number of redirects external services such as IP APIs may issue
+    pub http_max_redirects: usize,
+    /// Refuse redirects of external services to private, loopback or link-local addresses
+    pub http_deny_private_redirects: bool,
+    /// URL of a SOCKS5 proxy to send all HTTP requests through, such as `socks5h://proxy:1080`.
+    /// DNS queries of dnsHostname solvers are still sent to the name servers directly
+    pub socks_proxy: Option<String>,
+    /// Maximum number of queries to IP APIs and name servers in flight at once, across all services. Unbounded if unset
+    pub max_outbound_queries: Option<NonZeroUsize>,
+    /// Protocol used by dnsHostname solvers to reach name servers
+    pub resolver_protocol: ResolverProtocol,
+    /// Directory to persist solver results in, so that they survive restarts
+    pub cache_dir: Option<PathBuf>,
+    /// Cache solver results for at least this long, regardless of the cache duration of the individual solvers.
+    /// Only raises shorter cache durations, longer ones such as rate limit backoffs are kept
+    pub min_cache_ttl: Option<Duration>,
+    /// Publish the addresses of a source on a node or lease after every full run, in addition to or instead of services
+    pub publish_self: Option<PublishSelfConfig>,
+    /// Name of this manager instance reported in events, such as its pod name, so that events of multiple replicas can be told apart
+    pub reporter_instance: Option<String>,
+    /// Name and namespace of the Pod the manager runs in. If set, reconcile summaries are published as events on this Pod
+    pub pod: Option<(String, String)>,
+}
+
+impl ManagerConfig {
+    /// Whether services must not be modified
+    fn is_read_only(&self) -> bool {
+        self.dry_run || self.compare_with_live
+    }
+}
+
+fn svc_ref(svc: &ExternalIpSvc) -> (String, String) {
+    (
+        svc.svc().metadata.namespace.clone().unwrap_or_default(),
+        svc.svc().metadata.name.clone().unwrap_or_default(),
+    )
+}
+
+/// Change to apply to the externalIPs of a service
+#[derive(Debug, PartialEq, Eq)]
+enum AddressUpdate {
+    UpToDate,
+    /// The resolved set is empty and would clear the existing addresses
+    RefuseEmpty,
+    Update(HashSet<IpAddr>),
+}
+
+/// Determine the new externalIPs of a service from the `resolved` addresses and any `foreign` ones that must be kept
+fn plan_address_update(
+    current: &HashSet<IpAddr>,
+    resolved: &HashSet<IpAddr>,
+    foreign: &HashSet<IpAddr>,
+    allow_empty: bool,
+) -> AddressUpdate {
+    let desired: HashSet<IpAddr> = resolved.union(foreign).copied().collect();
+    if *current == desired {
+        AddressUpdate::UpToDate
+    } else if resolved.is_empty() && !allow_empty {
+        AddressUpdate::RefuseEmpty
+    } else {
+        AddressUpdate::Update(desired)
+    }
+}
+
+/// Whether the `current` addresses of a service differ from the `managed` ones the manager last wrote.
+///
+/// When merging with existing addresses, other actors may add addresses, so only missing managed addresses count as drift.
+fn has_drifted(
+    current: &HashSet<IpAddr>,
+    managed: &HashSet<IpAddr>,
+    merge_with_existing: bool,
+) -> bool {
+    if merge_with_existing {
+        !managed.is_subset(current)
+    } else {
+        current != managed
+    }
+}
+
+/// Probe all `addrs` concurrently and return the ones that are unreachable, sorted
+async fn unreachable_addresses(
+    probe: &dyn ReachabilityProbe,
+    addrs: &HashSet<IpAddr>,
+) -> Vec<IpAddr> {
+    let addrs = addrs.iter().copied().sorted().collect_vec();
+    let reachable =
+        futures::future::join_all(addrs.iter().map(|addr| probe.is_reachable(*addr))).await;
+    addrs
+        .into_iter()
+        .zip(reachable)
+        .filter(|(_, reachable)| !reachable)
+        .map(|(addr, _)| addr)
+        .collect()
+}
+
+/// Collapse IPv4-mapped IPv6 addresses (`::ffff:192.0.2.1`) into the IPv4 address they represent,
+/// so that the same host is not written twice when both address families are resolved
+fn normalize_addresses(addrs: impl IntoIterator<Item = IpAddr>) -> HashSet<IpAddr> {
+    addrs.into_iter().map(|addr| addr.to_canonical()).collect()
+}
+
+/// Query `sources` in order and return the first one that yields any addresses, along with these addresses.
+///
+/// Failing sources are skipped. If no source yields addresses, the first error is returned, or [None] if all sources succeeded.
+async fn first_non_empty<'a, E>(
+    sources: impl IntoIterator<Item = &'a ExternalIpSourceKind>,
+    mut query: impl AsyncFnMut(&ExternalIpSourceKind) -> Result<Vec<IpAddr>, E>,
+) -> Result<Option<(&'a ExternalIpSourceKind, Vec<IpAddr>)>, E> {
+    let mut first_err = None;
+    for source in sources {
+        match query(source).await {
+            Ok(addrs) if !addrs.is_empty() => return Ok(Some((source, addrs))),
+            Ok(_) => debug!(
+                msg = "IP source yielded no addresses",
+                source = source.name()
+            ),
+            Err(e) => {
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+    match first_err {
+        Some(e) => Err(e),
+        None => Ok(None),
+    }
+}
+
+/// Outcome of a single [Manager::reconcile_svcs] run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct ReconcileSummary {
+    scanned: usize,
+    updated: usize,
+    errored: usize,
 }
 
 impl Manager {
-    pub async fn new(config: ManagerConfig) -> Result<Manager, Error> {
-        let client = Client::try_default().await?;
-        let events = EventRecorder::new(client.clone(), MANAGER_ID.to_string());
+    pub async fn new(config: ManagerConfig, client: Client) -> Result<Manager, Error> {
+        let events = EventRecorder::new(
+            client.clone(),
+            MANAGER_ID.to_string(),
+            config.reporter_instance.clone(),
+        );
+        let summary_ref = match &config.pod {
+            Some((name, namespace)) => {
+                match Api::<Pod>::namespaced(client.clone(), namespace)
+                    .get(name)
+                    .await
+                {
+                    Ok(pod) => Some(pod.object_ref(&())),
+                    Err(e) => {
+                        warn!(msg = "unable to retrieve manager pod, reconcile summaries will not be published", pod = name, namespace, err = ?e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let disk_cache = config
+            .cache_dir
+            .as_deref()
+            .map(|dir| Arc::new(DiskCache::load(dir)));
+        let metrics = Arc::new(Metrics::default());
+        let annotations = AnnotationKeys::new(
+            config
+                .annotation_prefix
+                .as_deref()
+                .unwrap_or(DEFAULT_ANNOTATION_PREFIX),
+        );
+        let socks_proxy = config
+            .socks_proxy
+            .as_deref()
+            .map(socks_proxy)
+            .transpose()
+            .map_err(Error::InvalidProxy)?;
+        if socks_proxy.is_some() {
+            info!(
+                msg = "sending HTTP requests through SOCKS proxy, DNS queries of dnsHostname solvers still bypass it"
+            );
+        }
+        let http_config = HttpClientConfig {
+            connect_timeout: config.http_connect_timeout,
+            pool_idle_timeout: config.http_pool_idle_timeout,
+            max_redirects: config.http_max_redirects,
+            deny_private_redirects: config.http_deny_private_redirects,
+            socks_proxy,
+        };
+        let ip_sources = IPSourceRegistry::new(
+            SolverContext {
+                client: client.clone(),
+                http_client: http_config.build(),
+                http_config,
+                resolver_protocol: config.resolver_protocol.clone(),
+                disk_cache,
+                metrics: Some(metrics.clone()),
+                outbound_limit: config
+                    .max_outbound_queries
+                    .map(OutboundLimit::new)
+                    .unwrap_or_default(),
+                min_cache_ttl: config.min_cache_ttl.unwrap_or_default(),
+            },
+            events.clone(),
+            annotations.referenced_by.clone(),
+        )
+        .await
+        .map_err(|e| match e {
+            IpSourceError::Kube(e) if config.require_sources => Error::ListIPSources(e),
+            e => Error::IPSource {
+                name: "registry".to_string(),
+                err: e,
+            },
+        })?;
+        if config.require_sources && ip_sources.source_count() == 0 {
+            return Err(Error::NoIPSources);
+        }
         Ok(Manager {
-            config,
-            svc_finder: ServiceFinder::new(events.clone()).await?,
+            svc_finder: ServiceFinder::new(
+                client.clone(),
+                events.clone(),
+                annotations.clone(),
+                config.namespaces.clone(),
+                config.verbose_svc_logging,
+            ),
             client: client.clone(),
             events: events.clone(),
-            ip_sources: IPSourceRegistry::new(client.clone(), events.clone())
-                .await
-                .map_err(|e| Error::IPSource {
-                    name: "registry".to_string(),
-                    err: e,
-                })?,
+            annotations,
+            ip_sources,
+            metrics,
+            summary_ref,
+            last_summary: None,
+            pending_svcs: BTreeMap::new(),
+            source_missing_since: HashMap::new(),
+            applied_address_counts: HashMap::new(),
+            resume_from: None,
+            reachability_probe: config
+                .verify_reachable
+                .clone()
+                .map(|probe| Arc::new(probe) as Arc<dyn ReachabilityProbe>),
+            config,
         })
     }
 
     #[instrument(skip(self))]
     pub async fn reconcile_svcs(&mut self) -> Result<Vec<Error>, Error> {
+        let deadline = self
+            .config
+            .reconcile_run_timeout
+            .map(|timeout| tokio::time::Instant::now() + timeout);
         let mut errors = vec![];
+        let mut updated = 0;
+        self.pending_svcs.clear();
         self.ip_sources
             .refresh()
             .await
@@ -67,6 +387,15 @@ impl Manager {
                 name: "registry".to_string(),
                 err: e,
             })?;
+        if let Some(publish) = &self.config.publish_self {
+            if let Err(e) = self.publish_self(publish).await {
+                error!(msg = "failed to publish addresses", target = %publish.target, err = ?e);
+                errors.push(e);
+            }
+            if publish.only {
+                return Ok(errors);
+            }
+        }
         let svcs = match self.svc_finder.find_annotated_svcs().await {
             Ok(svc) => svc,
             Err(e) => {
@@ -82,7 +411,43 @@ impl Manager {
             )
         );
 
-        for svc in svcs.iter().filter_map(|svc| svc.as_ref().ok()) {
+        // services with invalid annotations were already reported by the finder and are skipped
+        errors.extend(
+            svcs.iter()
+                .filter_map(|svc| svc.as_ref().err())
+                .map(|e| Error::from(e.clone())),
+        );
+        // forget services that were deleted or unannotated since they were last seen
+        let found: HashSet<(String, String)> = svcs
+            .iter()
+            .filter_map(|svc| svc.as_ref().ok())
+            .map(svc_ref)
+            .collect();
+        self.source_missing_since
+            .retain(|svc, _| found.contains(svc));
+        self.applied_address_counts
+            .retain(|svc, _| found.contains(svc));
+        // start with the service at which the last run was aborted, so that services at the end are not starved
+        let mut queue = svcs
+            .iter()
+            .filter_map(|svc| svc.as_ref().ok())
+            .sorted_by_key(|svc| svc_ref(svc))
+            .collect_vec();
+        if let Some(resume_from) = self.resume_from.take() {
+            let start = queue.partition_point(|svc| svc_ref(svc) < resume_from);
+            queue.rotate_left(start);
+        }
+        self.metrics.reconcile_queue_depth.set(queue.len() as i64);
+        let mut timed_out = false;
+        for svc in queue {
+            if deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline) {
+                self.resume_from = Some(svc_ref(svc));
+                self.metrics.reconcile_queue_depth.set(0);
+                timed_out = true;
+                break;
+            }
+            self.metrics.reconcile_queue_depth.dec();
+            self.metrics.reconcile_in_flight.inc();
             let svc_name = format!(
                 "{}/{}",
                 svc.svc()
@@ -96,29 +461,341 @@ impl Manager {
                     .as_ref()
                     .expect("service should have name")
             );
-            if let Err(e) = self.reconcile_svc(svc).await {
-                error!(msg = "failed to reconcile service", svc = svc_name, err = ?e);
-                errors.push(e);
+            let service_timeout = self.config.service_reconcile_timeout;
+            let reconcile = async {
+                match service_timeout {
+                    Some(timeout) => {
+                        match tokio::time::timeout(timeout, self.reconcile_svc(svc)).await {
+                            Ok(result) => result,
+                            Err(_) => Err(Error::ReconcileTimeout {
+                                svc: svc_name.clone(),
+                                timeout,
+                            }),
+                        }
+                    }
+                    None => self.reconcile_svc(svc).await,
+                }
+            };
+            // a single service may not exceed the remaining time of the run either. Services cut off by the end of
+            // the run didn't fail themselves, so they are neither reported nor retried early, but come first in the next run
+            let result = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, reconcile).await.ok(),
+                None => Some(reconcile.await),
+            };
+            let Some(result) = result else {
+                info!(
+                    msg = "reconciliation run ended while reconciling service, continuing with it in the next run",
+                    svc = svc_name
+                );
+                self.metrics.reconcile_in_flight.dec();
+                self.metrics.reconcile_queue_depth.set(0);
+                self.resume_from = Some(svc_ref(svc));
+                timed_out = true;
+                break;
+            };
+            if !matches!(result, Err(Error::UnknownIPSource(_))) {
+                self.source_missing_since.remove(&svc_ref(svc));
+            }
+            self.metrics.reconcile_in_flight.dec();
+            self.metrics.reconcile_processed.inc();
+            match result {
+                Ok(true) => updated += 1,
+                Ok(false) => (),
+                Err(Error::UnknownIPSource(source)) => {
+                    info!(
+                        msg = "IP source of service does not exist yet, retrying soon",
+                        svc = svc_name,
+                        source,
+                        retry_secs = self.config.unknown_source_retry.as_secs()
+                    );
+                    self.pending_svcs
+                        .insert(svc_ref(svc), self.config.unknown_source_retry);
+                    if let Err(e) = self.handle_missing_source(svc).await {
+                        errors.push(e);
+                    }
+                }
+                Err(e) if e.is_timeout() => {
+                    self.handle_timeout(svc, &e).await;
+                    errors.push(e);
+                }
+                Err(e) => {
+                    error!(msg = "failed to reconcile service", svc = svc_name, err = ?e);
+                    errors.push(e);
+                }
+            }
+        }
+
+        if self.config.annotate_source_references && !self.config.is_read_only() {
+            let mut references: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+            for svc in svcs.iter().filter_map(|svc| svc.as_ref().ok()) {
+                let (namespace, name) = svc_ref(svc);
+                for source in svc.ip_sources() {
+                    references
+                        .entry(source.name())
+                        .or_default()
+                        .insert(format!("{namespace}/{name}"));
+                }
+            }
+            for (name, err) in self.ip_sources.annotate_references(&references).await {
+                error!(msg = "failed to annotate ClusterExternalIPSource with its services", source = name, err = ?err);
+                errors.push(Error::IPSource { name, err });
             }
         }
 
+        self.publish_summary(ReconcileSummary {
+            scanned: svcs.len(),
+            updated,
+            errored: errors.len(),
+        })
+        .await;
+        if let Some(timeout) = self.config.reconcile_run_timeout.filter(|_| timed_out) {
+            warn!(
+                msg = "reconciliation run timed out, the remaining services are reconciled first in the next run",
+                timeout_secs = timeout.as_secs()
+            );
+            self.metrics.reconcile_run_timeouts.inc();
+            errors.push(Error::RunTimeout { timeout });
+        }
         Ok(errors)
     }
 
+    /// The most recent error of each ClusterExternalIPSource, keyed by source name
+    pub fn last_source_errors(&self) -> &HashMap<String, LastSourceError> {
+        self.ip_sources.last_errors()
+    }
+
+    /// Metrics of this manager, to be served with [serve_metrics]
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// When the ClusterExternalIPSources were last refreshed successfully, such as for readiness checks.
+    /// If this falls behind, the manager is resolving addresses with stale sources
+    pub fn registry_last_refresh(&self) -> Option<SystemTime> {
+        self.ip_sources.last_refresh()
+    }
+
+    /// Whether any services are waiting for their IP source to be created or for their solvers to respond
+    pub fn has_pending_svcs(&self) -> bool {
+        !self.pending_svcs.is_empty()
+    }
+
+    /// Delay after which pending services should be retried, if there are any
+    pub fn pending_retry_delay(&self) -> Option<Duration> {
+        self.pending_svcs.values().min().copied()
+    }
+
+    /// Keep the addresses of a service whose IP source is missing during the grace window, and remove the managed ones after it
+    async fn handle_missing_source(&mut self, svc: &ExternalIpSvc) -> Result<(), Error> {
+        let Some(grace) = self.config.source_missing_grace else {
+            return Ok(());
+        };
+        let (namespace, name) = svc_ref(svc);
+        let svc_id = format!("{namespace}/{name}");
+        let missing_for = self
+            .source_missing_since
+            .entry((namespace, name))
+            .or_insert_with(Instant::now)
+            .elapsed();
+        if missing_for < grace {
+            warn!(
+                msg = "IP source of service is missing, keeping its addresses during the grace window",
+                svc = svc_id,
+                remaining_secs = (grace - missing_for).as_secs()
+            );
+            return Ok(());
+        }
+
+        let current: HashSet<IpAddr> = svc
+            .current_addresses(self.config.target_annotation.as_deref())
+            .iter()
+            .filter_map(|addr| addr.parse().ok())
+            .collect();
+        let kept = if self.config.merge_with_existing {
+            svc.foreign_ips(&current, MANAGER_ID)
+        } else {
+            HashSet::new()
+        };
+        if kept == current {
+            return Ok(());
+        }
+        warn!(
+            msg = "IP source of service has been missing for longer than the grace window, removing its addresses",
+            svc = svc_id,
+            missing_secs = missing_for.as_secs()
+        );
+        if self.config.is_read_only() {
+            return Ok(());
+        }
+        self.events
+            .publish(
+                "SourceMissing".to_string(),
+                ACTION_UPDATE_EIPS.to_string(),
+                EventType::Warning,
+                Some(format!(
+                    "IP source has been missing for more than {}s, removing managed externalIPs",
+                    grace.as_secs()
+                )),
+                &svc.svc().object_ref(&()),
+            )
+            .await;
+        self.update_svc_addresses(svc, kept.into_iter(), std::iter::empty())
+            .await
+    }
+
+    /// Schedule a service whose reconciliation timed out for a retry and notify about it
+    async fn handle_timeout(&mut self, svc: &ExternalIpSvc, err: &Error) {
+        let retry = self.config.solver_timeout_retry;
+        let (namespace, name) = svc_ref(svc);
+        warn!(
+            msg = "timed out reconciling service, retrying soon",
+            svc = format!("{namespace}/{name}"),
+            retry_secs = retry.as_secs(),
+            err = ?err
+        );
+        self.events
+            .publish(
+                "SolverTimeout".to_string(),
+                ACTION_UPDATE_EIPS.to_string(),
+                EventType::Warning,
+                Some(format!(
+                    "Timed out resolving addresses, retrying in {}s: {err}",
+                    retry.as_secs()
+                )),
+                &svc.svc().object_ref(&()),
+            )
+            .await;
+        self.pending_svcs.insert((namespace, name), retry);
+    }
+
+    /// Retry only the services whose IP source could not be found or whose solvers timed out during the last run.
+    ///
+    /// This allows services to pick up newly created sources or recover from transient timeouts
+    /// without waiting for a full reconciliation.
+    #[instrument(skip(self))]
+    pub async fn reconcile_pending_svcs(&mut self) -> Result<Vec<Error>, Error> {
+        let mut errors = vec![];
+        if self.pending_svcs.is_empty() {
+            return Ok(errors);
+        }
+        self.ip_sources
+            .refresh()
+            .await
+            .map_err(|e| Error::IPSource {
+                name: "registry".to_string(),
+                err: e,
+            })?;
+        for (namespace, name) in std::mem::take(&mut self.pending_svcs).into_keys() {
+            // the service may have been deleted or unannotated in the meantime, in which case we drop it
+            let svc = match self.svc_finder.find_annotated_svc(&namespace, &name).await {
+                Ok(Some(svc)) => svc,
+                Ok(None) | Err(kube::Error::Api(_)) => continue,
+                Err(e) => {
+                    errors.push(e.into());
+                    continue;
+                }
+            };
+            let result = self.reconcile_svc(&svc).await;
+            if !matches!(result, Err(Error::UnknownIPSource(_))) {
+                self.source_missing_since.remove(&svc_ref(&svc));
+            }
+            match result {
+                Ok(_) => (),
+                Err(Error::UnknownIPSource(_)) => {
+                    self.pending_svcs
+                        .insert((namespace, name), self.config.unknown_source_retry);
+                    if let Err(e) = self.handle_missing_source(&svc).await {
+                        errors.push(e);
+                    }
+                }
+                Err(e) if e.is_timeout() => {
+                    self.handle_timeout(&svc, &e).await;
+                    errors.push(e);
+                }
+                Err(e) => {
+                    error!(msg = "failed to reconcile service", svc = format!("{namespace}/{name}"), err = ?e);
+                    errors.push(e);
+                }
+            }
+        }
+        Ok(errors)
+    }
+
+    /// Reconcile only the service `name` in `namespace`, failing if it has no externalip-manager annotations
     #[instrument(skip(self))]
-    async fn reconcile_svc(&mut self, svc: &ExternalIpSvc) -> Result<(), Error> {
+    pub async fn reconcile_named_svc(&mut self, namespace: &str, name: &str) -> Result<(), Error> {
+        self.ip_sources
+            .refresh()
+            .await
+            .map_err(|e| Error::IPSource {
+                name: "registry".to_string(),
+                err: e,
+            })?;
+        let svc = self
+            .svc_finder
+            .find_annotated_svc(namespace, name)
+            .await?
+            .ok_or_else(|| FinderError {
+                msg: format!(
+                    "service {namespace}/{name} has no valid externalip-manager annotation"
+                ),
+            })?;
+        self.reconcile_svc(&svc).await?;
+        Ok(())
+    }
+
+    /// Publish a summary event on the manager's own Pod, if it changed or enough time has passed since the last one
+    async fn publish_summary(&mut self, summary: ReconcileSummary) {
+        let Some(summary_ref) = &self.summary_ref else {
+            return;
+        };
+        if let Some((last, published_at)) = &self.last_summary
+            && *last == summary
+            && published_at.elapsed() < SUMMARY_EVENT_MIN_INTERVAL
+        {
+            return;
+        }
+        self.events
+            .publish(
+                "ReconcileCompleted".to_string(),
+                ACTION_RECONCILE.to_string(),
+                if summary.errored == 0 {
+                    EventType::Normal
+                } else {
+                    EventType::Warning
+                },
+                Some(format!(
+                    "Reconciled {} services: {} updated, {} failed",
+                    summary.scanned, summary.updated, summary.errored
+                )),
+                summary_ref,
+            )
+            .await;
+        self.last_summary = Some((summary, Instant::now()));
+    }
+
+    /// Reconcile a single service, returning whether its addresses were updated
+    #[instrument(skip_all, fields(
+        svc.namespace = svc.svc().metadata.namespace.as_deref().unwrap_or_default(),
+        svc.name = svc.svc().metadata.name.as_deref().unwrap_or_default(),
+    ))]
+    async fn reconcile_svc(&mut self, svc: &ExternalIpSvc) -> Result<bool, Error> {
         let svc_name = svc.svc().metadata.name.clone().unwrap_or_default();
         let svc_namespace = svc.svc().metadata.namespace.clone().unwrap_or_default();
         let svc_id = format!("{}/{}", svc_namespace, svc_name);
+        if self.config.skip_loadbalancer_services && svc.is_unmanaged_load_balancer() {
+            debug!(
+                msg = "skipping LoadBalancer service, annotate it to manage it anyway",
+                service = svc_id,
+                annotation = self.annotations.manage_load_balancer
+            );
+            return Ok(false);
+        }
         info!(msg = "processing service", service = svc_id);
 
         #[allow(clippy::result_large_err)]
         let current_ips: Vec<IpAddr> = match svc
-            .svc()
-            .spec
-            .as_ref()
-            .and_then(|spec| spec.external_ips.clone())
-            .unwrap_or_default()
+            .current_addresses(self.config.target_annotation.as_deref())
             .iter()
             .map(|addr_string| addr_string.parse::<IpAddr>().map_err(Error::from))
             .collect()
@@ -141,30 +818,220 @@ impl Manager {
 
         let resolved_ips = self.resolve_svc_extipsource_addresses(svc).await?;
         let current_ip_set: HashSet<IpAddr, RandomState> = HashSet::from_iter(current_ips);
-        let new_ip_set: HashSet<IpAddr, RandomState> = HashSet::from_iter(resolved_ips);
-        if current_ip_set == new_ip_set {
-            info!(msg = "service externalIP field already up to date", svc = svc_id, addresses = ?current_ip_set);
-            return Ok(());
+        let mut new_ip_set: HashSet<IpAddr, RandomState> = normalize_addresses(resolved_ips);
+        let collisions: HashSet<IpAddr> = new_ip_set
+            .intersection(&svc.cluster_ips())
+            .copied()
+            .collect();
+        if !collisions.is_empty() {
+            warn!(msg = "resolved addresses collide with the ClusterIPs of the service", svc = svc_id, addresses = ?collisions, rejected = self.config.reject_self_collision);
+            self.events
+                .publish(
+                    "SelfCollision".to_string(),
+                    ACTION_UPDATE_EIPS.to_string(),
+                    EventType::Warning,
+                    Some(format!(
+                        "Resolved addresses {} are also ClusterIPs of the service{}",
+                        collisions.iter().sorted().join(", "),
+                        if self.config.reject_self_collision {
+                            ", not using them"
+                        } else {
+                            ""
+                        }
+                    )),
+                    &svc.svc().object_ref(&()),
+                )
+                .await;
+            if self.config.reject_self_collision {
+                new_ip_set.retain(|addr| !collisions.contains(addr));
+            }
         }
+        if let Some(probe) = &self.reachability_probe {
+            let unreachable = unreachable_addresses(probe.as_ref(), &new_ip_set).await;
+            if !unreachable.is_empty() {
+                warn!(msg = "resolved addresses are unreachable, not using them", svc = svc_id, addresses = ?unreachable);
+                self.events
+                    .publish(
+                        "UnreachableExternalIPs".to_string(),
+                        ACTION_UPDATE_EIPS.to_string(),
+                        EventType::Warning,
+                        Some(format!(
+                            "Resolved addresses {} are unreachable, not using them",
+                            unreachable.iter().join(", ")
+                        )),
+                        &svc.svc().object_ref(&()),
+                    )
+                    .await;
+                new_ip_set.retain(|addr| !unreachable.contains(addr));
+            }
+        }
+        let foreign_ip_set = if self.config.merge_with_existing {
+            normalize_addresses(svc.foreign_ips(&current_ip_set, MANAGER_ID))
+        } else {
+            HashSet::new()
+        };
+        let managed_ip_set = new_ip_set;
+        let new_ip_set = match plan_address_update(
+            &current_ip_set,
+            &managed_ip_set,
+            &foreign_ip_set,
+            self.config.allow_empty,
+        ) {
+            AddressUpdate::UpToDate => {
+                info!(msg = "service externalIP field already up to date", svc = svc_id, addresses = ?current_ip_set);
+                self.applied_address_counts
+                    .insert(svc_ref(svc), managed_ip_set.len());
+                return Ok(false);
+            }
+            AddressUpdate::RefuseEmpty => {
+                warn!(msg = "resolved no addresses for service, keeping current externalIPs", svc = svc_id, current_addresses = ?current_ip_set);
+                self.events
+                    .publish(
+                        "EmptyExternalIPs".to_string(),
+                        ACTION_UPDATE_EIPS.to_string(),
+                        EventType::Warning,
+                        Some("no addresses were resolved, keeping current externalIPs".to_string()),
+                        &svc.svc().object_ref(&()),
+                    )
+                    .await;
+                return Ok(false);
+            }
+            AddressUpdate::Update(new_ip_set) => new_ip_set,
+        };
+        let drifted = svc.managed_ips().is_some_and(|managed| {
+            has_drifted(&current_ip_set, &managed, self.config.merge_with_existing)
+        });
 
+        if self.config.compare_with_live {
+            let added = new_ip_set
+                .difference(&current_ip_set)
+                .sorted()
+                .collect_vec();
+            let removed = current_ip_set
+                .difference(&new_ip_set)
+                .sorted()
+                .collect_vec();
+            warn!(msg = "resolved addresses differ from the live externalIPs of service", svc = svc_id, added = ?added, removed = ?removed);
+            self.metrics.canary_diff.inc();
+            return Ok(false);
+        }
         if self.config.dry_run {
             info!(msg = "externalIP mismatch for service, not applying changes in dry-run mode", svc = svc_id, current_addresses = ?current_ip_set, new_addresses = ?new_ip_set);
-            return Ok(());
+            return Ok(false);
         } else {
             info!(msg = "externalIP mismatch for service, updating", svc = svc_id, current_addresses = ?current_ip_set, new_addresses = ?new_ip_set);
         }
 
-        self.update_svc_addresses(svc, new_ip_set.into_iter())
+        if drifted {
+            warn!(msg = "externalIPs of service were changed outside of externalip-manager, restoring them", svc = svc_id, current_addresses = ?current_ip_set);
+            self.metrics.drift_detected.inc();
+            self.events
+                .publish(
+                    "DriftDetected".to_string(),
+                    ACTION_UPDATE_EIPS.to_string(),
+                    EventType::Warning,
+                    Some(format!(
+                        "externalIPs were changed outside of externalip-manager to {}, restoring them",
+                        current_ip_set.iter().sorted().join(", ")
+                    )),
+                    &svc.svc().object_ref(&()),
+                )
+                .await;
+        }
+        let managed_count = managed_ip_set.len();
+        self.check_address_count_drop(svc, managed_count).await;
+        self.update_svc_addresses(svc, new_ip_set.into_iter(), managed_ip_set.into_iter())
             .await?;
+        self.applied_address_counts
+            .insert(svc_ref(svc), managed_count);
 
-        Ok(())
+        Ok(true)
     }
 
+    /// Warn if `svc` is about to get at least [ManagerConfig::address_drop_threshold] fewer managed addresses than on its last update.
+    ///
+    /// Before the first update since the manager started, the addresses in the managed annotation are compared against.
+    async fn check_address_count_drop(&self, svc: &ExternalIpSvc, count: usize) {
+        let Some(threshold) = self.config.address_drop_threshold else {
+            return;
+        };
+        let Some(previous) = self
+            .applied_address_counts
+            .get(&svc_ref(svc))
+            .copied()
+            .or_else(|| svc.managed_ips().map(|managed| managed.len()))
+        else {
+            return;
+        };
+        if previous < count + threshold.get() {
+            return;
+        }
+        let (namespace, name) = svc_ref(svc);
+        warn!(
+            msg = "number of resolved addresses of service dropped",
+            svc = format!("{namespace}/{name}"),
+            previous,
+            current = count
+        );
+        self.metrics.address_count_dropped.inc();
+        self.events
+            .publish(
+                "AddressCountDropped".to_string(),
+                ACTION_UPDATE_EIPS.to_string(),
+                EventType::Warning,
+                Some(format!(
+                    "Number of resolved addresses dropped from {previous} to {count}, the upstream may be partially failing"
+                )),
+                &svc.svc().object_ref(&()),
+            )
+            .await;
+    }
+
+    /// Resolve the addresses of `svc` from the first of its IP sources that yields any
     async fn resolve_svc_extipsource_addresses(
         &mut self,
         svc: &ExternalIpSvc,
     ) -> Result<Vec<IpAddr>, Error> {
-        let ip_source = match svc.ip_source() {
+        let resolved = first_non_empty(svc.ip_sources(), async |source| {
+            self.resolve_source_addresses(svc, source).await
+        })
+        .await?;
+        let Some((source, addrs)) = resolved else {
+            return Ok(vec![]);
+        };
+        if svc.ip_sources().count() > 1 {
+            info!(
+                msg = "using addresses of IP source",
+                svc = format!("{}/{}", svc_ref(svc).0, svc_ref(svc).1),
+                source = source.name()
+            );
+            self.events
+                .publish(
+                    "ExternalIPSourceSelected".to_string(),
+                    ACTION_UPDATE_EIPS.to_string(),
+                    EventType::Normal,
+                    Some(format!(
+                        "Using addresses of ClusterExternalIPSource {}",
+                        source.name()
+                    )),
+                    &svc.svc().object_ref(&()),
+                )
+                .await;
+        }
+        Ok(addrs)
+    }
+
+    #[instrument(skip_all, fields(
+        svc.namespace = svc.svc().metadata.namespace.as_deref().unwrap_or_default(),
+        svc.name = svc.svc().metadata.name.as_deref().unwrap_or_default(),
+        source = source.name(),
+    ))]
+    async fn resolve_source_addresses(
+        &mut self,
+        svc: &ExternalIpSvc,
+        source: &ExternalIpSourceKind,
+    ) -> Result<Vec<IpAddr>, Error> {
+        let ip_source = match source {
             ExternalIpSourceKind::Cluster(ceips) => self.ip_sources.get_cluster(ceips).ok_or(ceips),
         };
         let ip_source = match ip_source {
@@ -175,18 +1042,40 @@ impl Manager {
                         "UnknownExternalIPSource".to_string(),
                         ACTION_UPDATE_EIPS.to_string(),
                         EventType::Warning,
-                        Some(format!("Could not find ExternalIPSource {eips}")),
+                        Some(format!(
+                            "Could not find ExternalIPSource {eips}, retrying in {}s",
+                            self.config.unknown_source_retry.as_secs()
+                        )),
                         &svc.svc().object_ref(&()),
                     )
                     .await;
-                return Err(Error::Service(FinderError {
-                    msg: format!("could not find ExternalIPSource {eips}"),
-                }));
+                return Err(Error::UnknownIPSource(eips.to_string()));
             }
         };
 
-        match ip_source.query(svc.svc(), self.ip_sources.solvers()).await {
+        let source_name = ip_source.name();
+        let result = ip_source.query(svc.svc(), self.ip_sources.solvers()).await;
+        if let Err(e) = &result {
+            self.ip_sources.record_error(&source_name, e);
+            self.ip_sources.publish_query_error(&source_name, e).await;
+        }
+        match result {
             Ok(ips) => Ok(ips),
+            Err(e @ IpSourceError::TooFewAddresses { .. }) => {
+                self.events
+                    .publish(
+                        "InsufficientExternalIPs".to_string(),
+                        ACTION_UPDATE_EIPS.to_string(),
+                        EventType::Warning,
+                        Some(format!("Keeping current externalIPs: {}", e)),
+                        &svc.svc().object_ref(&()),
+                    )
+                    .await;
+                Err(Error::IPSource {
+                    name: source_name,
+                    err: e,
+                })
+            }
             Err(e) => {
                 self.events
                     .publish(
@@ -198,7 +1087,7 @@ impl Manager {
                     )
                     .await;
                 Err(Error::IPSource {
-                    name: ip_source.name(),
+                    name: source_name,
                     err: e,
                 })
             }
@@ -209,23 +1098,48 @@ impl Manager {
         &self,
         svc: &ExternalIpSvc,
         addresses: impl Iterator<Item = IpAddr>,
+        managed_addresses: impl Iterator<Item = IpAddr>,
     ) -> Result<(), Error> {
-        let address_strings = addresses.map(|addr| addr.to_string()).collect_vec();
+        let order = svc.address_order();
+        let address_strings = order
+            .sort(addresses)
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect_vec();
+        let managed_addresses = order
+            .sort(managed_addresses)
+            .iter()
+            .map(|addr| addr.to_string())
+            .join(",");
         let svc_name = svc.svc().metadata.name.clone().unwrap_or_default();
         let svc_namespace = svc.svc().metadata.namespace.clone().unwrap_or_default();
         let svc_id = format!("{}/{}", svc_name, svc_namespace);
 
+        let mut annotations = BTreeMap::from([(
+            self.annotations.managed_external_ips.clone(),
+            managed_addresses,
+        )]);
+        let spec = match &self.config.target_annotation {
+            Some(key) => {
+                annotations.insert(key.clone(), address_strings.join(","));
+                None
+            }
+            None => Some(ServiceSpec {
+                external_ips: Some(address_strings.clone()),
+                ..Default::default()
+            }),
+        };
         let api: Api<Service> = Api::namespaced(self.client.clone(), &svc_namespace);
         match api
             .patch(
                 &svc_name,
                 &PatchParams::apply(MANAGER_ID),
                 &Patch::Merge(Service {
-                    metadata: ObjectMeta::default(),
-                    spec: Some(ServiceSpec {
-                        external_ips: Some(address_strings.clone()),
+                    metadata: ObjectMeta {
+                        annotations: Some(annotations),
                         ..Default::default()
-                    }),
+                    },
+                    spec,
                     status: None,
                 }),
             )
@@ -251,3 +1165,888 @@ impl Manager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr_set(addrs: &[&str]) -> HashSet<IpAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn refuses_to_clear_existing_addresses() {
+        let current = addr_set(&["192.0.2.1"]);
+        assert_eq!(
+            plan_address_update(&current, &HashSet::new(), &HashSet::new(), false),
+            AddressUpdate::RefuseEmpty
+        );
+    }
+
+    #[test]
+    fn clears_existing_addresses_if_allowed() {
+        let current = addr_set(&["192.0.2.1"]);
+        assert_eq!(
+            plan_address_update(&current, &HashSet::new(), &HashSet::new(), true),
+            AddressUpdate::Update(HashSet::new())
+        );
+    }
+
+    #[test]
+    fn updates_changed_addresses() {
+        let current = addr_set(&["192.0.2.1"]);
+        let resolved = addr_set(&["192.0.2.2"]);
+        assert_eq!(
+            plan_address_update(&current, &resolved, &HashSet::new(), false),
+            AddressUpdate::Update(resolved)
+        );
+        assert_eq!(
+            plan_address_update(&current, &current, &HashSet::new(), false),
+            AddressUpdate::UpToDate
+        );
+    }
+
+    #[test]
+    fn collapses_mapped_addresses() {
+        let resolved = addr_set(&["::ffff:192.0.2.1", "2001:db8::1"])
+            .into_iter()
+            .chain(addr_set(&["192.0.2.1"]));
+        assert_eq!(
+            normalize_addresses(resolved),
+            addr_set(&["192.0.2.1", "2001:db8::1"])
+        );
+    }
+
+    #[test]
+    fn detects_drift() {
+        let managed = addr_set(&["192.0.2.1"]);
+        assert!(!has_drifted(&addr_set(&["192.0.2.1"]), &managed, false));
+        assert!(has_drifted(&addr_set(&["198.51.100.7"]), &managed, false));
+        assert!(has_drifted(
+            &addr_set(&["192.0.2.1", "198.51.100.7"]),
+            &managed,
+            false
+        ));
+        // Added addresses are kept when merging, only removed ones are drift
+        assert!(!has_drifted(
+            &addr_set(&["192.0.2.1", "198.51.100.7"]),
+            &managed,
+            true
+        ));
+        assert!(has_drifted(&addr_set(&["198.51.100.7"]), &managed, true));
+    }
+
+    #[test]
+    fn keeps_foreign_addresses() {
+        let current = addr_set(&["192.0.2.1", "198.51.100.1"]);
+        let foreign = addr_set(&["198.51.100.1"]);
+        assert_eq!(
+            plan_address_update(&current, &addr_set(&["192.0.2.2"]), &foreign, false),
+            AddressUpdate::Update(addr_set(&["192.0.2.2", "198.51.100.1"]))
+        );
+    }
+
+    fn cluster_sources(names: &[&str]) -> Vec<ExternalIpSourceKind> {
+        names
+            .iter()
+            .map(|name| ExternalIpSourceKind::Cluster(name.to_string()))
+            .collect()
+    }
+
+    /// Resolves the addresses given for each source name, recording the queried sources
+    async fn query_sources(
+        sources: &[ExternalIpSourceKind],
+        addrs: &HashMap<&str, Vec<IpAddr>>,
+    ) -> (Result<Option<(String, Vec<IpAddr>)>, String>, Vec<String>) {
+        let mut queried = vec![];
+        let result = first_non_empty(sources, async |source| {
+            queried.push(source.name());
+            addrs
+                .get(source.name().as_str())
+                .cloned()
+                .ok_or(format!("{} failed", source.name()))
+        })
+        .await
+        .map(|found| found.map(|(source, addrs)| (source.name(), addrs)));
+        (result, queried)
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_next_source_if_empty() {
+        let addrs = HashMap::from([
+            ("a", vec![]),
+            ("b", vec!["192.0.2.1".parse().unwrap()]),
+            ("c", vec!["192.0.2.2".parse().unwrap()]),
+        ]);
+        let (result, queried) = query_sources(&cluster_sources(&["a", "b", "c"]), &addrs).await;
+        assert_eq!(
+            result,
+            Ok(Some(("b".to_string(), vec!["192.0.2.1".parse().unwrap()])))
+        );
+        assert_eq!(queried, vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn all_sources_empty() {
+        let addrs = HashMap::from([("a", vec![]), ("b", vec![])]);
+        let (result, queried) = query_sources(&cluster_sources(&["a", "b"]), &addrs).await;
+        assert_eq!(result, Ok(None));
+        assert_eq!(queried, vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn failing_source_is_skipped() {
+        let addrs = HashMap::from([("b", vec!["192.0.2.1".parse().unwrap()])]);
+        let (result, _) = query_sources(&cluster_sources(&["a", "b"]), &addrs).await;
+        assert_eq!(
+            result,
+            Ok(Some(("b".to_string(), vec!["192.0.2.1".parse().unwrap()])))
+        );
+
+        let (result, _) = query_sources(&cluster_sources(&["a", "c"]), &HashMap::new()).await;
+        assert_eq!(result, Err("a failed".to_string()));
+    }
+
+    type Recorder<T> = Arc<std::sync::Mutex<Vec<T>>>;
+
+    /// Fake API server with the given ClusterExternalIPSources and services.
+    ///
+    /// Services can only be listed in the `default` namespace when listing them per namespace, any other namespace
+    /// is forbidden. Patches of services, nodes and sources are recorded by name, such as `web`, `node/worker-1`
+    /// or `source/public`, and published events by the kind of the object they regard.
+    #[derive(Clone, Default)]
+    struct FakeApi {
+        sources: Vec<serde_json::Value>,
+        services: Vec<serde_json::Value>,
+        /// Forbid listing ClusterExternalIPSources
+        forbidden: bool,
+        /// Names of services whose patches fail with an internal error
+        failing_patches: Vec<&'static str>,
+        /// Time every service patch takes
+        patch_latency: Duration,
+        patches: Recorder<(String, serde_json::Value)>,
+        events: Recorder<String>,
+    }
+
+    impl FakeApi {
+        /// A static "public" source resolving 192.0.2.1 and the given `services`
+        fn new(services: Vec<serde_json::Value>) -> FakeApi {
+            FakeApi {
+                services,
+                ..FakeApi::default()
+            }
+            .with_source_spec(serde_json::json!({
+                "ipv4": {"solvers": [{"static": {"addresses": ["192.0.2.1"]}}]}
+            }))
+        }
+
+        /// Replace the spec of the "public" source
+        fn with_source_spec(mut self, spec: serde_json::Value) -> FakeApi {
+            self.sources = vec![serde_json::json!({
+                "apiVersion": "externalip.spacebird.dev/v1alpha1",
+                "kind": "ClusterExternalIPSource",
+                "metadata": {"name": "public"},
+                "spec": spec
+            })];
+            self
+        }
+
+        fn with_patches(mut self, patches: Recorder<(String, serde_json::Value)>) -> FakeApi {
+            self.patches = patches;
+            self
+        }
+
+        fn with_events(mut self, events: Recorder<String>) -> FakeApi {
+            self.events = events;
+            self
+        }
+
+        fn client(self) -> Client {
+            let sources = Arc::new(std::sync::Mutex::new(self.sources.clone()));
+            let service = tower::service_fn(move |req: http::Request<kube::client::Body>| {
+                let api = self.clone();
+                let sources = sources.clone();
+                async move { Ok::<_, std::convert::Infallible>(api.respond(req, &sources).await) }
+            });
+            Client::new(service, "default")
+        }
+
+        async fn respond(
+            &self,
+            req: http::Request<kube::client::Body>,
+            sources: &std::sync::Mutex<Vec<serde_json::Value>>,
+        ) -> http::Response<kube::client::Body> {
+            let method = req.method().to_string();
+            let path = req.uri().path().to_string();
+            let name = path.rsplit('/').next().unwrap().to_string();
+            let status = |code: u16, reason: &str| {
+                (
+                    code,
+                    serde_json::json!({
+                        "apiVersion": "v1",
+                        "kind": "Status",
+                        "status": "Failure",
+                        "message": reason.to_lowercase(),
+                        "reason": reason,
+                        "code": code
+                    }),
+                )
+            };
+            let (code, body) = match (method.as_str(), path.as_str()) {
+                ("GET", "/apis/externalip.spacebird.dev/v1alpha1/clusterexternalipsources") => {
+                    if self.forbidden {
+                        status(403, "Forbidden")
+                    } else {
+                        (
+                            200,
+                            serde_json::json!({
+                                "apiVersion": "externalip.spacebird.dev/v1alpha1",
+                                "kind": "ClusterExternalIPSourceList",
+                                "metadata": {},
+                                "items": *sources.lock().unwrap()
+                            }),
+                        )
+                    }
+                }
+                ("GET", "/api/v1/services" | "/api/v1/namespaces/default/services") => (
+                    200,
+                    serde_json::json!({
+                        "apiVersion": "v1",
+                        "kind": "ServiceList",
+                        "metadata": {},
+                        "items": self.services
+                    }),
+                ),
+                ("GET", path) if path.ends_with("/services") => status(403, "Forbidden"),
+                ("PATCH", path) if path.starts_with("/api/v1/namespaces/default/services/") => {
+                    tokio::time::sleep(self.patch_latency).await;
+                    if self.failing_patches.contains(&name.as_str()) {
+                        status(500, "InternalError")
+                    } else {
+                        let body = req.into_body().collect_bytes().await.unwrap();
+                        let patch = serde_json::from_slice(&body).unwrap();
+                        self.patches.lock().unwrap().push((name.clone(), patch));
+                        let svc = self
+                            .services
+                            .iter()
+                            .find(|svc| svc["metadata"]["name"] == name.as_str())
+                            .cloned()
+                            .unwrap();
+                        (200, svc)
+                    }
+                }
+                ("PATCH", path) if path.starts_with("/api/v1/nodes/") => {
+                    let body = req.into_body().collect_bytes().await.unwrap();
+                    let patch = serde_json::from_slice(&body).unwrap();
+                    self.patches
+                        .lock()
+                        .unwrap()
+                        .push((format!("node/{name}"), patch));
+                    (200, serde_json::json!({"metadata": {"name": name}}))
+                }
+                ("PATCH", path)
+                    if path.starts_with(
+                        "/apis/externalip.spacebird.dev/v1alpha1/clusterexternalipsources/",
+                    ) =>
+                {
+                    let body = req.into_body().collect_bytes().await.unwrap();
+                    let patch: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                    let mut sources = sources.lock().unwrap();
+                    let source = sources
+                        .iter_mut()
+                        .find(|source| source["metadata"]["name"] == name.as_str())
+                        .unwrap();
+                    source["metadata"]["annotations"] = patch["metadata"]["annotations"].clone();
+                    self.patches
+                        .lock()
+                        .unwrap()
+                        .push((format!("source/{name}"), patch));
+                    (200, source.clone())
+                }
+                ("POST", path) if path.ends_with("/events") => {
+                    let body = req.into_body().collect_bytes().await.unwrap();
+                    let event: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                    let kind = event["regarding"]["kind"].as_str().unwrap().to_string();
+                    self.events.lock().unwrap().push(kind);
+                    (200, event)
+                }
+                _ => status(404, "NotFound"),
+            };
+            http::Response::builder()
+                .status(code)
+                .body(kube::client::Body::from(body.to_string().into_bytes()))
+                .unwrap()
+        }
+    }
+
+    /// Three annotated services using the "public" source, where patching `b` fails
+    fn partially_failing_api() -> FakeApi {
+        FakeApi {
+            failing_patches: vec!["b"],
+            ..FakeApi::new(vec![
+                fake_svc("a", serde_json::json!({}), &[]),
+                fake_svc("b", serde_json::json!({}), &[]),
+                fake_svc("c", serde_json::json!({}), &[]),
+            ])
+        }
+    }
+
+    fn patched_names(patches: &Recorder<(String, serde_json::Value)>) -> Vec<String> {
+        patches
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn requires_sources_if_configured() {
+        let required = ManagerConfig {
+            require_sources: true,
+            ..Default::default()
+        };
+        assert!(
+            Manager::new(ManagerConfig::default(), FakeApi::default().client())
+                .await
+                .is_ok()
+        );
+        assert!(matches!(
+            Manager::new(required.clone(), FakeApi::default().client()).await,
+            Err(Error::NoIPSources)
+        ));
+        assert!(matches!(
+            Manager::new(
+                required,
+                FakeApi {
+                    forbidden: true,
+                    ..FakeApi::default()
+                }
+                .client()
+            )
+            .await,
+            Err(Error::ListIPSources(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn failing_service_does_not_abort_reconcile() {
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig::default(),
+            partially_failing_api()
+                .with_patches(patches.clone())
+                .client(),
+        )
+        .await
+        .unwrap();
+
+        let errors = manager.reconcile_svcs().await.unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], Error::Kube(_)));
+        assert_eq!(patched_names(&patches), vec!["a", "c"]);
+    }
+
+    #[tokio::test]
+    async fn annotates_sources_with_referencing_services() {
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig {
+                annotate_source_references: true,
+                ..Default::default()
+            },
+            partially_failing_api()
+                .with_patches(patches.clone())
+                .client(),
+        )
+        .await
+        .unwrap();
+
+        manager.reconcile_svcs().await.unwrap();
+        assert_eq!(patched_names(&patches), vec!["a", "c", "source/public"]);
+        assert_eq!(
+            patches.lock().unwrap()[2].1["metadata"]["annotations"]["externalip.spacebird.dev/referenced-by"],
+            "default/a,default/b,default/c"
+        );
+
+        // Unchanged references are not patched again
+        patches.lock().unwrap().clear();
+        manager.reconcile_svcs().await.unwrap();
+        assert_eq!(patched_names(&patches), vec!["a", "c"]);
+    }
+
+    const TARGET_ANNOTATION: &str = "example.com/addresses";
+
+    /// An annotated service using the "public" source, with additional `annotations` and `external_ips`
+    fn fake_svc(
+        name: &str,
+        annotations: serde_json::Value,
+        external_ips: &[&str],
+    ) -> serde_json::Value {
+        let mut svc = serde_json::json!({
+            "metadata": {
+                "name": name,
+                "namespace": "default",
+                "annotations": {"externalip.spacebird.dev/cluster-external-ip-source": "public"}
+            },
+            "spec": {"externalIPs": external_ips}
+        });
+        for (key, value) in annotations.as_object().unwrap() {
+            svc["metadata"]["annotations"][key] = value.clone();
+        }
+        svc
+    }
+
+    #[tokio::test]
+    async fn publishes_addresses_in_target_annotation() {
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig {
+                target_annotation: Some(TARGET_ANNOTATION.to_string()),
+                ..Default::default()
+            },
+            FakeApi::new(vec![
+                fake_svc(
+                    "current",
+                    serde_json::json!({TARGET_ANNOTATION: "192.0.2.1"}),
+                    &[],
+                ),
+                fake_svc(
+                    "stale",
+                    serde_json::json!({TARGET_ANNOTATION: "192.0.2.9"}),
+                    &[],
+                ),
+            ])
+            .with_patches(patches.clone())
+            .client(),
+        )
+        .await
+        .unwrap();
+
+        let errors = manager.reconcile_svcs().await.unwrap();
+        assert!(errors.is_empty());
+        let patches = patches.lock().unwrap();
+        // The up-to-date service is compared by its annotation and left alone
+        assert_eq!(patches.len(), 1);
+        let (name, patch) = &patches[0];
+        assert_eq!(name, "stale");
+        assert_eq!(
+            patch["metadata"]["annotations"][TARGET_ANNOTATION],
+            "192.0.2.1"
+        );
+        assert!(patch.get("spec").is_none());
+    }
+
+    #[tokio::test]
+    async fn tracks_reconcile_progress() {
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig::default(),
+            FakeApi::new(vec![
+                fake_svc("a", serde_json::json!({}), &[]),
+                fake_svc("b", serde_json::json!({}), &["192.0.2.1"]),
+            ])
+            .with_patches(patches)
+            .client(),
+        )
+        .await
+        .unwrap();
+
+        manager.reconcile_svcs().await.unwrap();
+        let metrics = manager.metrics();
+        assert_eq!(metrics.reconcile_processed.get(), 2);
+        assert_eq!(metrics.reconcile_queue_depth.get(), 0);
+        assert_eq!(metrics.reconcile_in_flight.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn skips_loadbalancer_services() {
+        let load_balancer = |name: &str, annotations: serde_json::Value| {
+            let mut svc = fake_svc(name, annotations, &[]);
+            svc["spec"]["type"] = "LoadBalancer".into();
+            svc
+        };
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig {
+                skip_loadbalancer_services: true,
+                ..Default::default()
+            },
+            FakeApi::new(vec![
+                load_balancer("skipped", serde_json::json!({})),
+                load_balancer(
+                    "opted-in",
+                    serde_json::json!({"externalip.spacebird.dev/manage-load-balancer": "true"}),
+                ),
+                fake_svc("cluster-ip", serde_json::json!({}), &[]),
+            ])
+            .with_patches(patches.clone())
+            .client(),
+        )
+        .await
+        .unwrap();
+
+        manager.reconcile_svcs().await.unwrap();
+        let patched = patches
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .sorted()
+            .collect_vec();
+        assert_eq!(patched, vec!["cluster-ip", "opted-in"]);
+    }
+
+    #[tokio::test]
+    async fn skips_forbidden_namespaces() {
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig {
+                namespaces: vec!["restricted".to_string(), "default".to_string()],
+                ..Default::default()
+            },
+            FakeApi::new(vec![fake_svc("web", serde_json::json!({}), &[])])
+                .with_patches(patches.clone())
+                .client(),
+        )
+        .await
+        .unwrap();
+
+        manager.reconcile_svcs().await.unwrap();
+        let patched = patches
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect_vec();
+        assert_eq!(patched, vec!["web"]);
+    }
+
+    #[tokio::test]
+    async fn publishes_own_addresses() {
+        let publish = |only| PublishSelfConfig {
+            target: SelfTarget::Node("worker-1".to_string()),
+            source: "public".to_string(),
+            only,
+        };
+        for (only, expected) in [
+            (false, vec!["node/worker-1", "web"]),
+            (true, vec!["node/worker-1"]),
+        ] {
+            let patches = Arc::new(std::sync::Mutex::new(vec![]));
+            let mut manager = Manager::new(
+                ManagerConfig {
+                    publish_self: Some(publish(only)),
+                    ..Default::default()
+                },
+                FakeApi::new(vec![fake_svc("web", serde_json::json!({}), &[])])
+                    .with_patches(patches.clone())
+                    .client(),
+            )
+            .await
+            .unwrap();
+
+            assert!(manager.reconcile_svcs().await.unwrap().is_empty());
+            let patches = patches.lock().unwrap();
+            assert_eq!(
+                patches.iter().map(|(name, _)| name.as_str()).collect_vec(),
+                expected
+            );
+            assert_eq!(
+                patches[0].1["metadata"]["annotations"]["externalip.spacebird.dev/external-ips"],
+                "192.0.2.1"
+            );
+        }
+    }
+
+    #[derive(Debug)]
+    struct MockProbe {
+        reachable: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl ReachabilityProbe for MockProbe {
+        async fn is_reachable(&self, _: IpAddr) -> bool {
+            self.reachable
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_unreachable_addresses() {
+        for (reachable, expected) in [(true, vec!["web"]), (false, vec![])] {
+            let patches = Arc::new(std::sync::Mutex::new(vec![]));
+            let mut manager = Manager::new(
+                ManagerConfig::default(),
+                FakeApi::new(vec![fake_svc("web", serde_json::json!({}), &[])])
+                    .with_patches(patches.clone())
+                    .client(),
+            )
+            .await
+            .unwrap();
+            manager.reachability_probe = Some(Arc::new(MockProbe { reachable }));
+
+            assert!(manager.reconcile_svcs().await.unwrap().is_empty());
+            assert_eq!(
+                patches
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect_vec(),
+                expected
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn collects_debug_state() {
+        let mut manager = Manager::new(ManagerConfig::default(), FakeApi::new(vec![]).client())
+            .await
+            .unwrap();
+        manager.reconcile_svcs().await.unwrap();
+
+        let state = manager.debug_state().await;
+        assert!(state.registry_last_refresh.is_some());
+        assert_eq!(state.sources["public"].ipv4, vec!["static"]);
+        assert!(state.sources["public"].ipv6.is_empty());
+        assert_eq!(state.solvers.len(), 1);
+        assert_eq!(state.solvers[0].kind, "static");
+        assert_eq!(state.solvers[0].family, "IPv4");
+        assert_eq!(state.solvers[0].cache, None);
+    }
+
+    #[tokio::test]
+    async fn records_registry_refresh() {
+        let before = SystemTime::now();
+        let manager = Manager::new(ManagerConfig::default(), FakeApi::new(vec![]).client())
+            .await
+            .unwrap();
+        let last_refresh = manager.registry_last_refresh().unwrap();
+        assert!(last_refresh >= before);
+        let timestamp = last_refresh.duration_since(std::time::UNIX_EPOCH).unwrap();
+        assert_eq!(
+            manager.metrics().registry_last_refresh_timestamp.get(),
+            timestamp.as_secs_f64()
+        );
+    }
+
+    #[tokio::test]
+    async fn counts_differences_to_live_addresses() {
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig {
+                compare_with_live: true,
+                ..Default::default()
+            },
+            FakeApi::new(vec![
+                fake_svc("current", serde_json::json!({}), &["192.0.2.1"]),
+                fake_svc("stale", serde_json::json!({}), &["192.0.2.9"]),
+            ])
+            .with_patches(patches.clone())
+            .client(),
+        )
+        .await
+        .unwrap();
+
+        let errors = manager.reconcile_svcs().await.unwrap();
+        assert!(errors.is_empty());
+        assert!(patches.lock().unwrap().is_empty());
+        assert_eq!(manager.metrics().canary_diff.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn clears_addresses_after_source_missing_grace() {
+        let missing_source_svc = || {
+            vec![fake_svc(
+                "orphaned",
+                serde_json::json!({"externalip.spacebird.dev/cluster-external-ip-source": "missing"}),
+                &["192.0.2.1"],
+            )]
+        };
+        let run = |grace: Duration| async move {
+            let patches = Arc::new(std::sync::Mutex::new(vec![]));
+            let mut manager = Manager::new(
+                ManagerConfig {
+                    source_missing_grace: Some(grace),
+                    ..Default::default()
+                },
+                FakeApi::new(missing_source_svc())
+                    .with_patches(patches.clone())
+                    .client(),
+            )
+            .await
+            .unwrap();
+            manager.reconcile_svcs().await.unwrap();
+            assert!(manager.has_pending_svcs());
+            patches.lock().unwrap().clone()
+        };
+
+        assert!(run(Duration::from_secs(600)).await.is_empty());
+        let patches = run(Duration::ZERO).await;
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].1["spec"]["externalIPs"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn publishes_query_errors_on_source_once() {
+        let events = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig::default(),
+            FakeApi::new(vec![
+                fake_svc("a", serde_json::json!({}), &[]),
+                fake_svc("b", serde_json::json!({}), &[]),
+            ])
+            // The only source fails for IPv4
+            .with_source_spec(serde_json::json!({"ipv4": {
+                "solvers": [{"static": {"addresses": ["192.0.2.1"]}}],
+                "minAddresses": 2
+            }}))
+            .with_events(events.clone())
+            .client(),
+        )
+        .await
+        .unwrap();
+
+        let errors = manager.reconcile_svcs().await.unwrap();
+        assert_eq!(errors.len(), 2);
+        let events = events.lock().unwrap();
+        let count = |kind: &str| events.iter().filter(|k| *k == kind).count();
+        assert_eq!(count("Service"), 2);
+        assert_eq!(count("ClusterExternalIPSource"), 1);
+    }
+
+    #[tokio::test]
+    async fn skips_services_with_empty_source_annotation() {
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig::default(),
+            FakeApi::new(vec![
+                fake_svc(
+                    "blank",
+                    serde_json::json!({"externalip.spacebird.dev/cluster-external-ip-source": " "}),
+                    &[],
+                ),
+                fake_svc("valid", serde_json::json!({}), &[]),
+            ])
+            .with_patches(patches.clone())
+            .client(),
+        )
+        .await
+        .unwrap();
+
+        let errors = manager.reconcile_svcs().await.unwrap();
+        assert!(
+            matches!(&errors[..], [Error::Service(e)] if e.msg.contains("default/blank")),
+            "{errors:?}"
+        );
+        let patches = patches.lock().unwrap();
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].0, "valid");
+    }
+
+    #[tokio::test]
+    async fn detects_drift_from_other_controllers() {
+        let managed_key = AnnotationKeys::default().managed_external_ips;
+        let managed = |addrs: &str| serde_json::json!({&managed_key: addrs});
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig::default(),
+            FakeApi::new(vec![
+                // Another controller replaced the address written by the manager
+                fake_svc("drifted", managed("192.0.2.1"), &["198.51.100.7"]),
+                // The source changed since the last run, which is a regular update
+                fake_svc("outdated", managed("192.0.2.9"), &["192.0.2.9"]),
+                fake_svc("new", serde_json::json!({}), &[]),
+            ])
+            .with_patches(patches.clone())
+            .client(),
+        )
+        .await
+        .unwrap();
+
+        let errors = manager.reconcile_svcs().await.unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(patches.lock().unwrap().len(), 3);
+        assert_eq!(manager.metrics().drift_detected.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn reports_dropping_address_count() {
+        let managed_key = AnnotationKeys::default().managed_external_ips;
+        let addrs = ["192.0.2.1", "192.0.2.2", "192.0.2.3"];
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut manager = Manager::new(
+            ManagerConfig {
+                address_drop_threshold: NonZeroUsize::new(2),
+                ..Default::default()
+            },
+            FakeApi::new(vec![
+                // The source only resolves 192.0.2.1
+                fake_svc(
+                    "shrunk",
+                    serde_json::json!({&managed_key: addrs.join(",")}),
+                    &addrs,
+                ),
+                fake_svc("grown", serde_json::json!({&managed_key: ""}), &[]),
+            ])
+            .with_patches(patches.clone())
+            .client(),
+        )
+        .await
+        .unwrap();
+        let deleted = ("default".to_string(), "deleted".to_string());
+        manager.applied_address_counts.insert(deleted.clone(), 3);
+
+        let errors = manager.reconcile_svcs().await.unwrap();
+        assert!(errors.is_empty());
+        // The addresses are updated regardless
+        assert_eq!(patches.lock().unwrap().len(), 2);
+        assert_eq!(manager.metrics().address_count_dropped.get(), 1);
+        // Services that no longer exist are forgotten
+        assert!(!manager.applied_address_counts.contains_key(&deleted));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_runs_exceeding_timeout() {
+        let patches = Arc::new(std::sync::Mutex::new(vec![]));
+        // Every update takes 50ms, so that 5 services are handled in time and the 6th is cut off by the deadline
+        let api = FakeApi {
+            patch_latency: Duration::from_millis(50),
+            failing_patches: vec!["svc-01"],
+            ..FakeApi::new(
+                (0..20)
+                    .map(|i| fake_svc(&format!("svc-{i:02}"), serde_json::json!({}), &[]))
+                    .collect(),
+            )
+        };
+        let mut manager = Manager::new(
+            ManagerConfig {
+                reconcile_run_timeout: Some(Duration::from_millis(290)),
+                ..Default::default()
+            },
+            api.with_patches(patches.clone()).client(),
+        )
+        .await
+        .unwrap();
+
+        // Errors of the services handled before the timeout are still reported
+        let errors = manager.reconcile_svcs().await.unwrap();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(matches!(errors[0], Error::Kube(_)), "{errors:?}");
+        assert!(matches!(errors[1], Error::RunTimeout { .. }), "{errors:?}");
+        // Services updated before the timeout keep their addresses
+        let expected = ["svc-00", "svc-02", "svc-03", "svc-04"];
+        assert_eq!(patched_names(&patches), expected);
+        // The service that was cut off didn't time out itself and isn't retried early
+        assert!(!manager.has_pending_svcs());
+        assert_eq!(manager.metrics().reconcile_run_timeouts.get(), 1);
+        assert_eq!(manager.metrics().reconcile_in_flight.get(), 0);
+        assert_eq!(manager.metrics().reconcile_queue_depth.get(), 0);
+
+        // The next run continues with the service that was cut off
+        patches.lock().unwrap().clear();
+        let errors = manager.reconcile_svcs().await.unwrap();
+        assert!(matches!(errors.last(), Some(Error::RunTimeout { .. })));
+        assert_eq!(patched_names(&patches)[0], "svc-05");
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex, atomic::AtomicU64},
+    time::{Duration, Instant},
+};
+
+use http_body_util::Full;
+use hyper::{
+    Method, Request, Response, StatusCode, body::Bytes, server::conn::http1, service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use prometheus_client::{
+    encoding::{EncodeLabelSet, text::encode},
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
+    registry::Registry,
+};
+use tokio::net::TcpListener;
+use tracing::{debug, info};
+
+const METRICS_PREFIX: &str = "externalip";
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Labels of per-solver metrics. Only the solver kind is used, so that the number of series stays bounded
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct SolverLabels {
+    pub solver: &'static str,
+}
+
+/// Prometheus metrics of the manager, see [serve_metrics]
+#[derive(Debug)]
+pub struct Metrics {
+    registry: Registry,
+    /// Queries answered from a solver's cache without contacting the upstream service
+    pub solver_cache_hits: Family<SolverLabels, Counter>,
+    /// Queries that had to be sent to the upstream service
+    pub solver_cache_misses: Family<SolverLabels, Counter>,
+    /// Remaining time for which a solver is backing off after being rate limited, 0 if it isn't.
+    /// Solvers of the same kind share a series reporting the longest backoff, see [Metrics::set_solver_backoff]
+    pub solver_backoff_seconds: Family<SolverLabels, Gauge<f64, AtomicU64>>,
+    /// End of the backoff of each solver instance by solver kind, from which [Metrics::solver_backoff_seconds] is derived
+    solver_backoffs: Mutex<HashMap<&'static str, HashMap<String, Instant>>>,
+    /// Services whose addresses were changed by another actor and then restored
+    pub drift_detected: Counter,
+    /// Services whose resolved addresses dropped by at least `address_drop_threshold` compared to the last update
+    pub address_count_dropped: Counter,
+    /// Services still waiting to be reconciled in the current run
+    pub reconcile_queue_depth: Gauge,
+    /// Services currently being reconciled
+    pub reconcile_in_flight: Gauge,
+    /// Services reconciled in full runs, regardless of the outcome
+    pub reconcile_processed: Counter,
+    /// Full runs that were aborted after exceeding `reconcile_run_timeout`
+    pub reconcile_run_timeouts: Counter,
+    /// Services whose resolved addresses differ from the live ones, only counted with `compare_with_live`
+    pub canary_diff: Counter,
+    /// Unix timestamp of the last successful refresh of the ClusterExternalIPSources, 0 if there was none
+    pub registry_last_refresh_timestamp: Gauge<f64, AtomicU64>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let mut registry = Registry::with_prefix(METRICS_PREFIX);
+        let solver_cache_hits = Family::<SolverLabels, Counter>::default();
+        registry.register(
+            "solver_cache_hits",
+            "Solver queries answered from the cache",
+            solver_cache_hits.clone(),
+        );
+        let solver_cache_misses = Family::<SolverLabels, Counter>::default();
+        registry.register(
+            "solver_cache_misses",
+            "Solver queries sent to the upstream service",
+            solver_cache_misses.clone(),
+        );
+        let solver_backoff_seconds = Family::<SolverLabels, Gauge<f64, AtomicU64>>::default();
+        registry.register(
+            "solver_backoff_seconds",
+            "Seconds a solver backs off for after being rate limited",
+            solver_backoff_seconds.clone(),
+        );
+        let drift_detected = Counter::default();
+        registry.register(
+            "drift_detected",
+            "Services whose addresses were changed outside of the manager and restored",
+            drift_detected.clone(),
+        );
+        let address_count_dropped = Counter::default();
+        registry.register(
+            "address_count_dropped",
+            "Services whose number of resolved addresses dropped compared to the last update",
+            address_count_dropped.clone(),
+        );
+        let reconcile_queue_depth = Gauge::default();
+        registry.register(
+            "reconcile_queue_depth",
+            "Services waiting to be reconciled in the current run",
+            reconcile_queue_depth.clone(),
+        );
+        let reconcile_in_flight = Gauge::default();
+        registry.register(
+            "reconcile_in_flight",
+            "Services currently being reconciled",
+            reconcile_in_flight.clone(),
+        );
+        let reconcile_processed = Counter::default();
+        registry.register(
+            "reconcile_processed",
+            "Services reconciled in full runs",
+            reconcile_processed.clone(),
+        );
+        let reconcile_run_timeouts = Counter::default();
+        registry.register(
+            "reconcile_run_timeouts",
+            "Full runs aborted after exceeding the run timeout",
+            reconcile_run_timeouts.clone(),
+        );
+        let canary_diff = Counter::default();
+        registry.register(
+            "canary_diff",
+            "Services whose resolved addresses differ from the live ones when comparing with a primary instance",
+            canary_diff.clone(),
+        );
+        let registry_last_refresh_timestamp = Gauge::<f64, AtomicU64>::default();
+        registry.register(
+            "registry_last_refresh_timestamp",
+            "Unix timestamp of the last successful refresh of the ClusterExternalIPSources",
+            registry_last_refresh_timestamp.clone(),
+        );
+        Metrics {
+            registry,
+            solver_cache_hits,
+            solver_cache_misses,
+            solver_backoff_seconds,
+            solver_backoffs: Mutex::default(),
+            drift_detected,
+            address_count_dropped,
+            reconcile_queue_depth,
+            reconcile_in_flight,
+            reconcile_processed,
+            reconcile_run_timeouts,
+            canary_diff,
+            registry_last_refresh_timestamp,
+        }
+    }
+}
+
+impl Metrics {
+    /// Record that the solver `instance` of kind `labels` backs off for `remaining`, [Duration::ZERO] if it doesn't
+    pub fn set_solver_backoff(&self, labels: &SolverLabels, instance: &str, remaining: Duration) {
+        self.solver_backoffs
+            .lock()
+            .expect("solver backoff lock poisoned")
+            .entry(labels.solver)
+            .or_default()
+            .insert(instance.to_string(), Instant::now() + remaining);
+        self.refresh_solver_backoffs();
+    }
+
+    /// Set [Metrics::solver_backoff_seconds] to the longest remaining backoff of the solvers of each kind,
+    /// so that backoffs decay even if the solver isn't queried again
+    fn refresh_solver_backoffs(&self) {
+        let now = Instant::now();
+        let mut backoffs = self
+            .solver_backoffs
+            .lock()
+            .expect("solver backoff lock poisoned");
+        for (solver, instances) in backoffs.iter_mut() {
+            instances.retain(|_, until| *until > now);
+            let longest = instances
+                .values()
+                .map(|until| until.duration_since(now))
+                .max()
+                .unwrap_or_default();
+            self.solver_backoff_seconds
+                .get_or_create(&SolverLabels { solver })
+                .set(longest.as_secs_f64());
+        }
+    }
+
+    /// Render all metrics in the OpenMetrics text format
+    pub fn encode(&self) -> String {
+        self.refresh_solver_backoffs();
+        let mut out = String::new();
+        encode(&mut out, &self.registry).expect("writing to a String can't fail");
+        out
+    }
+}
+
+/// Serve `metrics` on `addr` under `/metrics` until the task is aborted
+pub async fn serve_metrics(addr: SocketAddr, metrics: Arc<Metrics>) -> std::io::Result<()> {
+    serve_get(addr, "/metrics", CONTENT_TYPE, move || metrics.encode()).await
+}
+
+/// Answer GET requests for `path` on `addr` with the output of `render` until the task is aborted
+pub(crate) async fn serve_get(
+    addr: SocketAddr,
+    path: &'static str,
+    content_type: &'static str,
+    render: impl Fn() -> String + Send + Sync + 'static,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(msg = "serving HTTP endpoint", address = %addr, path);
+    let render = Arc::new(render);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let render = render.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                let render = render.clone();
+                async move {
+                    let res = match (req.method(), req.uri().path()) {
+                        (&Method::GET, req_path) if req_path == path => Response::builder()
+                            .header(hyper::header::CONTENT_TYPE, content_type)
+                            .body(Full::new(Bytes::from(render()))),
+                        _ => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Full::new(Bytes::new())),
+                    };
+                    Ok::<_, Infallible>(res.expect("static response parts are valid"))
+                }
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!(msg = "failed to serve HTTP request", path, err = %e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_solver_metrics() {
+        let metrics = Metrics::default();
+        let labels = SolverLabels { solver: "ipAPI" };
+        metrics.solver_cache_hits.get_or_create(&labels).inc();
+        metrics.set_solver_backoff(&labels, "a", Duration::from_secs(30));
+        let encoded = metrics.encode();
+        assert!(encoded.contains(r#"externalip_solver_cache_hits_total{solver="ipAPI"} 1"#));
+        assert!(encoded.contains(r#"externalip_solver_backoff_seconds{solver="ipAPI"} "#));
+        let backoff = metrics.solver_backoff_seconds.get_or_create(&labels).get();
+        assert!(backoff > 29.0 && backoff <= 30.0, "{backoff}");
+    }
+
+    #[test]
+    fn reports_longest_solver_backoff() {
+        let metrics = Metrics::default();
+        let labels = SolverLabels { solver: "ipAPI" };
+        let backoff = || metrics.solver_backoff_seconds.get_or_create(&labels).get();
+        metrics.set_solver_backoff(&labels, "a", Duration::from_secs(30));
+        metrics.set_solver_backoff(&labels, "b", Duration::ZERO);
+        assert!(backoff() > 29.0);
+        metrics.set_solver_backoff(&labels, "a", Duration::from_millis(10));
+        assert!(backoff() <= 0.01);
+        // Expired backoffs decay to 0 without the solver being queried again
+        std::thread::sleep(Duration::from_millis(20));
+        metrics.encode();
+        assert_eq!(backoff(), 0.0);
+    }
+}
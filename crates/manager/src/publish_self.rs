@@ -0,0 +1,135 @@
+use itertools::Itertools;
+use k8s_openapi::api::{
+    coordination::v1::Lease,
+    core::v1::{Node, Service},
+};
+use kube::{
+    Api,
+    api::{Patch, PatchParams},
+};
+use tracing::{info, warn};
+
+use crate::{MANAGER_ID, Manager, error::Error, normalize_addresses, svc::AddressOrder};
+
+/// Object to publish the addresses resolved by the manager itself on, see [PublishSelfConfig]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelfTarget {
+    Node(String),
+    Lease { namespace: String, name: String },
+}
+
+impl std::fmt::Display for SelfTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SelfTarget::Node(name) => write!(f, "node/{name}"),
+            SelfTarget::Lease { namespace, name } => write!(f, "lease/{namespace}/{name}"),
+        }
+    }
+}
+
+/// Parse a target for [PublishSelfConfig], either `node/<name>` or `lease/<namespace>/<name>`
+pub fn parse_self_target(target: &str) -> Result<SelfTarget, String> {
+    let parts = target.split('/').collect_vec();
+    match parts.as_slice() {
+        ["node", name] if !name.is_empty() => Ok(SelfTarget::Node(name.to_string())),
+        ["lease", namespace, name] if !namespace.is_empty() && !name.is_empty() => {
+            Ok(SelfTarget::Lease {
+                namespace: namespace.to_string(),
+                name: name.to_string(),
+            })
+        }
+        _ => Err(format!(
+            "expected a target such as node/<name> or lease/<namespace>/<name>, got {target}"
+        )),
+    }
+}
+
+/// Publish the addresses of a ClusterExternalIPSource on an object other than a service, such as the node the manager runs on
+#[derive(Debug, Clone)]
+pub struct PublishSelfConfig {
+    pub target: SelfTarget,
+    /// Name of the ClusterExternalIPSource to resolve. Solvers depending on a service, such as loadBalancerIngress, yield no addresses
+    pub source: String,
+    /// Only publish the addresses on `target` and don't reconcile any services
+    pub only: bool,
+}
+
+impl Manager {
+    /// Resolve the source of `publish` and write its addresses to the target object, unless the manager is read-only
+    pub(crate) async fn publish_self(&self, publish: &PublishSelfConfig) -> Result<(), Error> {
+        let ip_source = self
+            .ip_sources
+            .get_cluster(&publish.source)
+            .ok_or_else(|| Error::UnknownIPSource(publish.source.clone()))?;
+        // No solver may refer to the service, as there is none
+        let addrs = ip_source
+            .query(&Service::default(), self.ip_sources.solvers())
+            .await
+            .map_err(|err| Error::IPSource {
+                name: publish.source.clone(),
+                err,
+            })?;
+        let addrs = AddressOrder::V4First
+            .sort(normalize_addresses(addrs))
+            .iter()
+            .join(",");
+        let target = publish.target.to_string();
+        if addrs.is_empty() && !self.config.allow_empty {
+            warn!(
+                msg = "resolved no addresses to publish, keeping the current ones",
+                target
+            );
+            return Ok(());
+        }
+        if self.config.is_read_only() {
+            info!(
+                msg = "not publishing addresses in dry-run mode",
+                target,
+                addresses = addrs
+            );
+            return Ok(());
+        }
+
+        let patch = Patch::Merge(serde_json::json!({
+            "metadata": {"annotations": {&self.annotations.published_external_ips: addrs}}
+        }));
+        let params = PatchParams::apply(MANAGER_ID);
+        match &publish.target {
+            SelfTarget::Node(name) => {
+                Api::<Node>::all(self.client.clone())
+                    .patch(name, &params, &patch)
+                    .await?;
+            }
+            SelfTarget::Lease { namespace, name } => {
+                Api::<Lease>::namespaced(self.client.clone(), namespace)
+                    .patch(name, &params, &patch)
+                    .await?;
+            }
+        }
+        info!(msg = "published addresses", target, addresses = addrs);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!(
+            parse_self_target("node/worker-1"),
+            Ok(SelfTarget::Node("worker-1".to_string()))
+        );
+        assert_eq!(
+            parse_self_target("lease/kube-system/edge"),
+            Ok(SelfTarget::Lease {
+                namespace: "kube-system".to_string(),
+                name: "edge".to_string()
+            })
+        );
+        for invalid in ["node", "node/", "lease/edge", "pod/default/manager"] {
+            assert!(parse_self_target(invalid).is_err());
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::{fmt::Debug, net::IpAddr, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{net::TcpStream, time::timeout};
+use tracing::debug;
+
+/// Checks whether resolved addresses are reachable before they are written to services, see [ManagerConfig::verify_reachable](crate::ManagerConfig::verify_reachable)
+#[async_trait]
+pub trait ReachabilityProbe: Debug + Send + Sync {
+    async fn is_reachable(&self, addr: IpAddr) -> bool;
+}
+
+/// Considers an address reachable if a TCP connection to `port` can be established within `timeout`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpProbe {
+    pub port: u16,
+    pub timeout: Duration,
+}
+
+#[async_trait]
+impl ReachabilityProbe for TcpProbe {
+    async fn is_reachable(&self, addr: IpAddr) -> bool {
+        match timeout(self.timeout, TcpStream::connect((addr, self.port))).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => {
+                debug!(msg = "reachability probe failed", address = %addr, port = self.port, err = %e);
+                false
+            }
+            Err(_) => {
+                debug!(msg = "reachability probe timed out", address = %addr, port = self.port);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn probes_tcp_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let probe = |port| TcpProbe {
+            port,
+            timeout: Duration::from_secs(1),
+        };
+        let localhost = "127.0.0.1".parse().unwrap();
+        assert!(probe(port).is_reachable(localhost).await);
+        drop(listener);
+        assert!(!probe(port).is_reachable(localhost).await);
+    }
+}
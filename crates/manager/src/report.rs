@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::svc::ExternalIpSvc;
+use crate::{AddressUpdate, MANAGER_ID, Manager, first_non_empty, plan_address_update};
+
+/// What the manager would set as the externalIPs of a service, see [Manager::report]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceReport {
+    /// Name of the primary ClusterExternalIPSource of the service
+    pub source: String,
+    pub resolved_v4: Vec<IpAddr>,
+    pub resolved_v6: Vec<IpAddr>,
+    /// The externalIPs currently set on the service
+    pub current: Vec<String>,
+    /// Whether a reconciliation would change the externalIPs of the service
+    pub would_change: bool,
+    /// Why the addresses of the service could not be resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ServiceReport {
+    fn new(
+        source: String,
+        current: Vec<String>,
+        resolved: Result<Vec<IpAddr>, String>,
+        foreign: &HashSet<IpAddr>,
+        allow_empty: bool,
+    ) -> ServiceReport {
+        let (resolved, error) = match resolved {
+            Ok(resolved) => (resolved, None),
+            Err(e) => (vec![], Some(e)),
+        };
+        let would_change = error.is_none()
+            && match current
+                .iter()
+                .map(|addr| addr.parse::<IpAddr>())
+                .collect::<Result<HashSet<_>, _>>()
+            {
+                Ok(current) => matches!(
+                    plan_address_update(
+                        &current,
+                        &resolved.iter().copied().collect(),
+                        foreign,
+                        allow_empty
+                    ),
+                    AddressUpdate::Update(_)
+                ),
+                // Invalid entries are never patched, see [Manager::reconcile_svcs]
+                Err(_) => false,
+            };
+        let (resolved_v4, resolved_v6) = resolved.into_iter().partition(IpAddr::is_ipv4);
+        ServiceReport {
+            source,
+            resolved_v4,
+            resolved_v6,
+            current,
+            would_change,
+            error,
+        }
+    }
+}
+
+impl Manager {
+    /// Resolve the addresses of every annotated service without modifying them, regardless of `dry_run`.
+    ///
+    /// The returned map is keyed by `namespace/name`.
+    pub async fn report(&mut self) -> Result<BTreeMap<String, ServiceReport>, Error> {
+        self.ip_sources
+            .refresh()
+            .await
+            .map_err(|e| Error::IPSource {
+                name: "registry".to_string(),
+                err: e,
+            })?;
+        let svcs = self.svc_finder.find_annotated_svcs().await?;
+        let mut reports = BTreeMap::new();
+        for svc in svcs.iter().filter_map(|svc| svc.as_ref().ok()) {
+            let (namespace, name) = crate::svc_ref(svc);
+            reports.insert(format!("{namespace}/{name}"), self.report_svc(svc).await);
+        }
+        Ok(reports)
+    }
+
+    async fn report_svc(&self, svc: &ExternalIpSvc) -> ServiceReport {
+        let source = svc.ip_source().name();
+        let current = svc.current_addresses(self.config.target_annotation.as_deref());
+        let resolved = first_non_empty(svc.ip_sources(), async |source| {
+            match self.ip_sources.get_cluster(&source.name()) {
+                Some(ip_source) => ip_source
+                    .query(svc.svc(), self.ip_sources.solvers())
+                    .await
+                    .map_err(|e| e.to_string()),
+                None => Err(Error::UnknownIPSource(source.name()).to_string()),
+            }
+        })
+        .await
+        .map(|resolved| resolved.map(|(_, addrs)| addrs).unwrap_or_default());
+        let foreign = if self.config.merge_with_existing {
+            let current_ips = current
+                .iter()
+                .filter_map(|addr| addr.parse().ok())
+                .collect();
+            svc.foreign_ips(&current_ips, MANAGER_ID)
+        } else {
+            HashSet::new()
+        };
+        ServiceReport::new(source, current, resolved, &foreign, self.config.allow_empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn splits_resolved_families() {
+        let report = ServiceReport::new(
+            "public".to_string(),
+            vec!["192.0.2.1".to_string()],
+            Ok(addrs(&["192.0.2.1", "2001:db8::1"])),
+            &HashSet::new(),
+            false,
+        );
+        assert_eq!(report.resolved_v4, addrs(&["192.0.2.1"]));
+        assert_eq!(report.resolved_v6, addrs(&["2001:db8::1"]));
+        assert!(report.would_change);
+    }
+
+    #[test]
+    fn unchanged_or_failed_services_would_not_change() {
+        let current = vec!["192.0.2.1".to_string()];
+        let unchanged = ServiceReport::new(
+            "public".to_string(),
+            current.clone(),
+            Ok(addrs(&["192.0.2.1"])),
+            &HashSet::new(),
+            false,
+        );
+        assert!(!unchanged.would_change);
+
+        let failed = ServiceReport::new(
+            "public".to_string(),
+            current,
+            Err("solver failed".to_string()),
+            &HashSet::new(),
+            false,
+        );
+        assert!(!failed.would_change);
+        assert_eq!(failed.error.as_deref(), Some("solver failed"));
+    }
+}
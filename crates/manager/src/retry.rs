@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use tracing::warn;
+
+const RETRY_ATTEMPTS: u32 = 4;
+const RETRY_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Whether a kube API error is likely to go away on its own, such as during an API server rollout
+fn is_transient(err: &kube::Error) -> bool {
+    match err {
+        kube::Error::Api(status) => status.code == 429 || status.code >= 500,
+        kube::Error::HyperError(_) | kube::Error::Service(_) | kube::Error::ReadEvents(_) => true,
+        _ => false,
+    }
+}
+
+/// Run the kube API operation `op`, retrying transient errors with exponential backoff.
+///
+/// If all attempts fail, the last error is returned unchanged.
+pub async fn retry_transient<T, F, Fut>(op_name: &str, op: F) -> Result<T, kube::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, kube::Error>>,
+{
+    retry_transient_with(op_name, RETRY_ATTEMPTS, RETRY_INITIAL_BACKOFF, op).await
+}
+
+async fn retry_transient_with<T, F, Fut>(
+    op_name: &str,
+    attempts: u32,
+    initial_backoff: Duration,
+    mut op: F,
+) -> Result<T, kube::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, kube::Error>>,
+{
+    let mut backoff = initial_backoff;
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(e) if attempt < attempts && is_transient(&e) => {
+                warn!(msg = "transient kube API error, retrying", op = op_name, attempt, backoff_ms = backoff.as_millis(), err = ?e);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::core::Status;
+
+    use super::*;
+
+    fn api_error(code: u16) -> kube::Error {
+        kube::Error::Api(Status::failure("test", "Test").with_code(code).boxed())
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let mut calls = 0;
+        let res = retry_transient_with("test", 3, Duration::from_millis(1), || {
+            calls += 1;
+            let res = if calls < 3 {
+                Err(api_error(503))
+            } else {
+                Ok(calls)
+            };
+            async move { res }
+        })
+        .await;
+        assert_eq!(res.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_permanent_errors() {
+        let mut calls = 0;
+        let res: Result<(), _> = retry_transient_with("test", 3, Duration::from_millis(1), || {
+            calls += 1;
+            async { Err(api_error(403)) }
+        })
+        .await;
+        assert!(matches!(res, Err(kube::Error::Api(status)) if status.code == 403));
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn returns_last_error_when_exhausted() {
+        let mut calls = 0;
+        let res: Result<(), _> = retry_transient_with("test", 3, Duration::from_millis(1), || {
+            calls += 1;
+            async { Err(api_error(500)) }
+        })
+        .await;
+        assert!(matches!(res, Err(kube::Error::Api(status)) if status.code == 500));
+        assert_eq!(calls, 3);
+    }
+}
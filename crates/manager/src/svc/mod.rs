@@ -1,67 +1,325 @@
+use std::{collections::HashSet, net::IpAddr};
+
 use itertools::Itertools;
-use k8s_openapi::api::core::v1::Service;
-use kube::{Api, Client, api::ListParams};
-use tracing::{info, instrument};
+use k8s_openapi::api::core::v1::{ObjectReference, Service};
+use kube::{Api, Client, Resource, api::ListParams, runtime::events::EventType};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    events::EventRecorder, external_ip_source::ExternalIpSourceKind, retry::retry_transient,
+};
+
+/// Prefix of all annotations read and written by the manager, unless configured otherwise
+pub const DEFAULT_ANNOTATION_PREFIX: &str = "externalip.spacebird.dev";
+const ANNOTATION_CLUSTER_EXTERNAL_IP_SOURCE: &str = "cluster-external-ip-source";
+/// Ordered, comma-separated list of sources to use if the source above yields no addresses
+const ANNOTATION_CLUSTER_EXTERNAL_IP_SOURCE_FALLBACK: &str = "cluster-external-ip-source-fallback";
+/// Order in which the addresses of both families are written, either `v4-first` (default) or `v6-first`
+const ANNOTATION_ADDRESS_ORDER: &str = "address-order";
+/// Records the externalIPs that were written by the manager, as opposed to ones added by users or other controllers
+const ANNOTATION_MANAGED_EXTERNAL_IPS: &str = "managed-external-ips";
+/// Set to `true` on a LoadBalancer service to manage it even if LoadBalancer services are skipped
+const ANNOTATION_MANAGE_LOAD_BALANCER: &str = "manage-load-balancer";
+/// Comma-separated list of the services (namespace/name) referencing a source, set on the ClusterExternalIPSource itself
+const ANNOTATION_REFERENCED_BY: &str = "referenced-by";
+/// Comma-separated list of the addresses published on a node or lease, see [crate::PublishSelfConfig]
+const ANNOTATION_PUBLISHED_EXTERNAL_IPS: &str = "external-ips";
+
+/// Full keys of the annotations read and written by the manager, all sharing a common prefix.
+///
+/// Using a custom prefix allows running multiple managers side by side without their annotations colliding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotationKeys {
+    pub cluster_external_ip_source: String,
+    pub cluster_external_ip_source_fallback: String,
+    pub address_order: String,
+    pub managed_external_ips: String,
+    pub manage_load_balancer: String,
+    pub referenced_by: String,
+    pub published_external_ips: String,
+}
 
-use crate::{events::EventRecorder, external_ip_source::ExternalIpSourceKind};
+impl AnnotationKeys {
+    /// Build the annotation keys under `prefix`, which must have been checked with [parse_annotation_prefix]
+    pub fn new(prefix: &str) -> AnnotationKeys {
+        let key = |name: &str| format!("{prefix}/{name}");
+        AnnotationKeys {
+            cluster_external_ip_source: key(ANNOTATION_CLUSTER_EXTERNAL_IP_SOURCE),
+            cluster_external_ip_source_fallback: key(
+                ANNOTATION_CLUSTER_EXTERNAL_IP_SOURCE_FALLBACK,
+            ),
+            address_order: key(ANNOTATION_ADDRESS_ORDER),
+            managed_external_ips: key(ANNOTATION_MANAGED_EXTERNAL_IPS),
+            manage_load_balancer: key(ANNOTATION_MANAGE_LOAD_BALANCER),
+            referenced_by: key(ANNOTATION_REFERENCED_BY),
+            published_external_ips: key(ANNOTATION_PUBLISHED_EXTERNAL_IPS),
+        }
+    }
+}
+
+impl Default for AnnotationKeys {
+    fn default() -> Self {
+        AnnotationKeys::new(DEFAULT_ANNOTATION_PREFIX)
+    }
+}
 
-const ANNOTATION_CLUSTER_EXTERNAL_IP_SOURCE: &str =
-    "externalip.spacebird.dev/cluster-external-ip-source";
+/// Check that `prefix` is a valid DNS subdomain, as required for the prefix of annotation keys
+pub fn parse_annotation_prefix(prefix: &str) -> Result<String, String> {
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && label
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+    };
+    if prefix.len() > 253 || !prefix.split('.').all(valid_label) {
+        return Err(format!(
+            "annotation prefix must be a valid DNS subdomain such as example.com, got {prefix}"
+        ));
+    }
+    Ok(prefix.to_string())
+}
 
 pub struct ServiceFinder {
+    client: Client,
     svc_api: Api<Service>,
-    #[allow(dead_code)]
     events: EventRecorder,
+    annotations: AnnotationKeys,
+    /// Namespaces to list services in one by one, all namespaces at once if empty
+    namespaces: Vec<String>,
+    /// Log every annotated service that was found at info level, instead of debug
+    verbose: bool,
 }
 
 impl ServiceFinder {
-    pub async fn new(events: EventRecorder) -> Result<ServiceFinder, kube::Error> {
-        Ok(ServiceFinder {
-            svc_api: Api::all(Client::try_default().await?),
+    pub fn new(
+        client: Client,
+        events: EventRecorder,
+        annotations: AnnotationKeys,
+        namespaces: Vec<String>,
+        verbose: bool,
+    ) -> ServiceFinder {
+        ServiceFinder {
+            svc_api: Api::all(client.clone()),
+            client,
             events,
-        })
+            annotations,
+            namespaces,
+            verbose,
+        }
+    }
+
+    fn log_found(&self, svc: &ExternalIpSvc) {
+        let name = svc.svc.metadata.name.as_deref().unwrap_or_default();
+        let namespace = svc.svc.metadata.namespace.as_deref().unwrap_or_default();
+        let msg = "found service with cluster-external-ip-source annotation";
+        if self.verbose {
+            info!(msg, svc = name, namespace);
+        } else {
+            debug!(msg, svc = name, namespace);
+        }
+    }
+
+    /// Warn about a service whose annotations can't be used, so that its owner notices
+    async fn report_invalid(&self, svc: &Service, err: &FinderError) {
+        warn!(
+            msg = "skipping service with invalid annotation",
+            svc = svc.metadata.name,
+            namespace = svc.metadata.namespace,
+            err = %err
+        );
+        self.events
+            .publish(
+                "InvalidAnnotation".to_string(),
+                "ValidateAnnotations".to_string(),
+                EventType::Warning,
+                Some(err.msg.clone()),
+                &svc.object_ref(&()),
+            )
+            .await;
+    }
+
+    /// Warn about a namespace whose services may not be listed, so that a missing RoleBinding doesn't go unnoticed
+    async fn report_forbidden(&self, namespace: &str, reason: &str) {
+        warn!(
+            msg = "skipping namespace, not allowed to list its services",
+            namespace, reason
+        );
+        let namespace_ref = ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Namespace".to_string()),
+            name: Some(namespace.to_string()),
+            ..Default::default()
+        };
+        self.events
+            .publish(
+                "ForbiddenNamespace".to_string(),
+                "ListServices".to_string(),
+                EventType::Warning,
+                Some(format!(
+                    "Services in this namespace are not managed, listing them is forbidden: {reason}"
+                )),
+                &namespace_ref,
+            )
+            .await;
     }
 
+    /// Retrieve all services with valid externalip-manager annotations.
+    ///
+    /// If namespaces are configured, namespaces in which listing services is forbidden are skipped with a warning
+    /// instead of failing, so that a missing RoleBinding in one namespace doesn't stop the others from being managed.
     #[instrument(skip(self))]
     pub async fn find_annotated_svcs(
         &self,
     ) -> Result<Vec<Result<ExternalIpSvc, FinderError>>, kube::Error> {
-        Ok(self
-            .svc_api
-            .list(&ListParams::default())
-            .await?
-            .items
-            .iter()
-            .filter_map(|svc| {
-                let Some(annotations) = &svc.metadata.annotations else {
-                    return None;
-                };
-                let extip_cluster_source = annotations.get(ANNOTATION_CLUSTER_EXTERNAL_IP_SOURCE);
-                // grab more annotations here in the future
-
-                if let Some(source) = extip_cluster_source {
-                    info!(
-                        msg = "found service with cluster-external-ip-source annotation",
-                        svc = svc.metadata.name,
-                        namespace = svc.metadata.namespace
-                    );
-                    return Some(Ok(ExternalIpSvc {
-                        svc: svc.clone(),
-                        source: ExternalIpSourceKind::Cluster(source.to_owned()),
-                    }));
+        let list_params = ListParams::default();
+        let svcs = if self.namespaces.is_empty() {
+            retry_transient("list services", || self.svc_api.list(&list_params))
+                .await?
+                .items
+        } else {
+            let mut svcs = vec![];
+            for namespace in &self.namespaces {
+                let api = Api::<Service>::namespaced(self.client.clone(), namespace);
+                match retry_transient("list services", || api.list(&list_params)).await {
+                    Ok(list) => svcs.extend(list.items),
+                    Err(kube::Error::Api(resp)) if resp.code == 403 => {
+                        self.report_forbidden(namespace, &resp.message).await;
+                    }
+                    Err(e) => return Err(e),
                 }
-                None
-            })
-            .collect_vec())
+            }
+            svcs
+        };
+        let mut found = vec![];
+        for svc in &svcs {
+            match manageable_svc(svc, &self.annotations) {
+                Some(Ok(extip_svc)) => {
+                    self.log_found(&extip_svc);
+                    found.push(Ok(extip_svc));
+                }
+                Some(Err(e)) => {
+                    self.report_invalid(svc, &e).await;
+                    found.push(Err(e));
+                }
+                None => (),
+            }
+        }
+        Ok(found)
+    }
+
+    /// Retrieve a single service by name, returning [None] if it does not have valid externalip-manager annotations or is being deleted
+    #[instrument(skip(self))]
+    pub async fn find_annotated_svc(
+        &self,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Option<ExternalIpSvc>, kube::Error> {
+        let svc = Api::<Service>::namespaced(self.client.clone(), namespace)
+            .get(name)
+            .await?;
+        match manageable_svc(&svc, &self.annotations) {
+            Some(Ok(extip_svc)) => {
+                self.log_found(&extip_svc);
+                Ok(Some(extip_svc))
+            }
+            Some(Err(e)) => {
+                self.report_invalid(&svc, &e).await;
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Returns `svc` if it has externalip-manager annotations and is not being deleted, or an error if the annotations are invalid.
+///
+/// Patching services that are being torn down is pointless and may conflict with their deletion.
+fn manageable_svc(
+    svc: &Service,
+    annotations: &AnnotationKeys,
+) -> Option<Result<ExternalIpSvc, FinderError>> {
+    if svc.metadata.deletion_timestamp.is_some() {
+        debug!(
+            msg = "skipping service that is being deleted",
+            svc = svc.metadata.name,
+            namespace = svc.metadata.namespace
+        );
+        return None;
     }
+    ExternalIpSvc::from_annotated(svc, annotations)
 }
 
 #[derive(Debug)]
 pub struct ExternalIpSvc {
     svc: Service,
     source: ExternalIpSourceKind,
+    fallback_sources: Vec<ExternalIpSourceKind>,
+    address_order: AddressOrder,
+    /// Key of the annotation recording the addresses written by the manager
+    managed_ips_key: String,
+    /// Whether the service opted in to being managed even if it is a LoadBalancer service
+    manage_load_balancer: bool,
 }
 impl ExternalIpSvc {
+    fn from_annotated(
+        svc: &Service,
+        keys: &AnnotationKeys,
+    ) -> Option<Result<ExternalIpSvc, FinderError>> {
+        let annotations = svc.metadata.annotations.as_ref()?;
+        let extip_cluster_source = annotations.get(&keys.cluster_external_ip_source);
+        // grab more annotations here in the future
+
+        if let Some(source) = extip_cluster_source {
+            if source.trim().is_empty() {
+                return Some(Err(FinderError {
+                    msg: format!(
+                        "service {}/{} has an empty {} annotation",
+                        svc.metadata.namespace.as_deref().unwrap_or_default(),
+                        svc.metadata.name.as_deref().unwrap_or_default(),
+                        keys.cluster_external_ip_source,
+                    ),
+                }));
+            }
+            let fallback_sources = annotations
+                .get(&keys.cluster_external_ip_source_fallback)
+                .map(|fallbacks| {
+                    fallbacks
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .map(|name| ExternalIpSourceKind::Cluster(name.to_owned()))
+                        .collect_vec()
+                })
+                .unwrap_or_default();
+            let address_order = match annotations.get(&keys.address_order).map(String::as_str) {
+                None | Some("v4-first") => AddressOrder::V4First,
+                Some("v6-first") => AddressOrder::V6First,
+                Some(order) => {
+                    warn!(
+                        msg = "ignoring invalid address order, expected v4-first or v6-first",
+                        svc = svc.metadata.name,
+                        order
+                    );
+                    AddressOrder::V4First
+                }
+            };
+            return Some(Ok(ExternalIpSvc {
+                svc: svc.clone(),
+                source: ExternalIpSourceKind::Cluster(source.to_owned()),
+                fallback_sources,
+                address_order,
+                managed_ips_key: keys.managed_external_ips.clone(),
+                manage_load_balancer: annotations
+                    .get(&keys.manage_load_balancer)
+                    .is_some_and(|value| value == "true"),
+            }));
+        }
+        None
+    }
+
     pub fn svc(&self) -> &Service {
         &self.svc
     }
@@ -69,6 +327,108 @@ impl ExternalIpSvc {
     pub fn ip_source(&self) -> &ExternalIpSourceKind {
         &self.source
     }
+
+    /// The ClusterIPs assigned to the service, ignoring `None` for headless services
+    pub fn cluster_ips(&self) -> HashSet<IpAddr> {
+        self.svc
+            .spec
+            .iter()
+            .flat_map(|spec| {
+                spec.cluster_ip
+                    .iter()
+                    .chain(spec.cluster_ips.iter().flatten())
+            })
+            .filter_map(|addr| addr.parse().ok())
+            .collect()
+    }
+
+    pub fn address_order(&self) -> AddressOrder {
+        self.address_order
+    }
+
+    /// Whether the service is of type LoadBalancer and did not opt in to being managed anyway
+    pub fn is_unmanaged_load_balancer(&self) -> bool {
+        let load_balancer = self
+            .svc
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.type_.as_deref())
+            == Some("LoadBalancer");
+        load_balancer && !self.manage_load_balancer
+    }
+
+    /// The addresses currently published for the service, either in `spec.externalIPs` or in `target_annotation` if set
+    pub fn current_addresses(&self, target_annotation: Option<&str>) -> Vec<String> {
+        match target_annotation {
+            Some(key) => self
+                .svc
+                .metadata
+                .annotations
+                .as_ref()
+                .and_then(|a| a.get(key))
+                .map(|addrs| {
+                    addrs
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|addr| !addr.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            None => self
+                .svc
+                .spec
+                .as_ref()
+                .and_then(|spec| spec.external_ips.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The primary source followed by any fallback sources, in the order they should be queried
+    pub fn ip_sources(&self) -> impl Iterator<Item = &ExternalIpSourceKind> {
+        std::iter::once(&self.source).chain(&self.fallback_sources)
+    }
+
+    /// The addresses last written by the manager, as recorded in [AnnotationKeys::managed_external_ips]
+    pub fn managed_ips(&self) -> Option<HashSet<IpAddr>> {
+        let managed = self
+            .svc
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|a| a.get(&self.managed_ips_key))?;
+        Some(
+            managed
+                .split(',')
+                .filter_map(|addr| addr.trim().parse().ok())
+                .collect(),
+        )
+    }
+
+    /// Return the addresses in `current` that were not written by the field manager `manager`.
+    ///
+    /// `spec.externalIPs` is an atomic list, so managedFields only tracks ownership of the list as a whole.
+    /// We therefore record our own entries in an annotation and only fall back to managedFields for services without it.
+    pub fn foreign_ips(&self, current: &HashSet<IpAddr>, manager: &str) -> HashSet<IpAddr> {
+        if let Some(managed) = self.managed_ips() {
+            return current.difference(&managed).copied().collect();
+        }
+
+        let owns_external_ips = self
+            .svc
+            .metadata
+            .managed_fields
+            .iter()
+            .flatten()
+            .filter(|entry| entry.manager.as_deref() == Some(manager))
+            .filter_map(|entry| entry.fields_v1.as_ref())
+            .any(|fields| fields.0.pointer("/f:spec/f:externalIPs").is_some());
+        if owns_external_ips {
+            HashSet::new()
+        } else {
+            current.clone()
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug, Clone)]
@@ -76,3 +436,148 @@ impl ExternalIpSvc {
 pub struct FinderError {
     pub msg: String,
 }
+
+/// Order of the externalIPs written to a service
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AddressOrder {
+    #[default]
+    V4First,
+    V6First,
+}
+
+impl AddressOrder {
+    /// Sort `addrs` by family according to this order, and by address within each family
+    pub fn sort(&self, addrs: impl IntoIterator<Item = IpAddr>) -> Vec<IpAddr> {
+        addrs
+            .into_iter()
+            .sorted_by_key(|addr| {
+                let v6_first = *self == AddressOrder::V6First;
+                (addr.is_ipv4() == v6_first, *addr)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(addrs: &[&str]) -> Vec<IpAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    fn annotated_svc() -> Service {
+        Service {
+            metadata: kube::api::ObjectMeta {
+                annotations: Some(
+                    [(
+                        AnnotationKeys::default().cluster_external_ip_source,
+                        "public".to_string(),
+                    )]
+                    .into(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn skips_terminating_services() {
+        let mut svc = annotated_svc();
+        assert!(manageable_svc(&svc, &AnnotationKeys::default()).is_some());
+        svc.metadata.deletion_timestamp = Some(
+            k8s_openapi::apimachinery::pkg::apis::meta::v1::Time(Default::default()),
+        );
+        assert!(manageable_svc(&svc, &AnnotationKeys::default()).is_none());
+    }
+
+    #[test]
+    fn rejects_empty_source_annotation() {
+        let mut svc = annotated_svc();
+        svc.metadata.name = Some("web".to_string());
+        svc.metadata.namespace = Some("default".to_string());
+        svc.metadata.annotations = Some(
+            [(
+                AnnotationKeys::default().cluster_external_ip_source,
+                "  ".to_string(),
+            )]
+            .into(),
+        );
+        let err = manageable_svc(&svc, &AnnotationKeys::default())
+            .unwrap()
+            .unwrap_err();
+        assert!(err.msg.contains("default/web"), "{}", err.msg);
+    }
+
+    #[test]
+    fn collects_cluster_ips() {
+        let svc = ExternalIpSvc::from_annotated(
+            &Service {
+                spec: Some(k8s_openapi::api::core::v1::ServiceSpec {
+                    cluster_ip: Some("10.0.0.1".to_string()),
+                    cluster_ips: Some(vec!["10.0.0.1".to_string(), "fd00::1".to_string()]),
+                    ..Default::default()
+                }),
+                ..annotated_svc()
+            },
+            &AnnotationKeys::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            svc.cluster_ips(),
+            addrs(&["10.0.0.1", "fd00::1"]).into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn reads_annotations_under_custom_prefix() {
+        let custom = AnnotationKeys::new("eip.example.com");
+        assert!(manageable_svc(&annotated_svc(), &custom).is_none());
+        let svc = Service {
+            metadata: kube::api::ObjectMeta {
+                annotations: Some(
+                    [(
+                        "eip.example.com/cluster-external-ip-source".to_string(),
+                        "public".to_string(),
+                    )]
+                    .into(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(manageable_svc(&svc, &custom).unwrap().is_ok());
+    }
+
+    #[test]
+    fn validates_annotation_prefix() {
+        assert!(parse_annotation_prefix("eip.example-1.com").is_ok());
+        for prefix in [
+            "",
+            "Example.com",
+            "-example.com",
+            "example..com",
+            "example.com/",
+        ] {
+            assert!(
+                parse_annotation_prefix(prefix).is_err(),
+                "{prefix} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn sorts_by_address_order() {
+        let mixed = addrs(&["2001:db8::2", "192.0.2.2", "2001:db8::1", "192.0.2.1"]);
+        assert_eq!(
+            AddressOrder::V4First.sort(mixed.clone()),
+            addrs(&["192.0.2.1", "192.0.2.2", "2001:db8::1", "2001:db8::2"])
+        );
+        assert_eq!(
+            AddressOrder::V6First.sort(mixed),
+            addrs(&["2001:db8::1", "2001:db8::2", "192.0.2.1", "192.0.2.2"])
+        );
+    }
+}
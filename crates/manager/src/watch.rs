@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use futures::StreamExt;
+use kube::{
+    Api, Client,
+    runtime::{WatchStreamExt, watcher},
+};
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use crate::crd::v1alpha1::ClusterExternalIPSource;
+
+/// Watch ClusterExternalIPSources and notify `changed` whenever one is created, updated or deleted.
+///
+/// Runs until the task is aborted. Multiple changes before the notification is consumed only result in a single wake-up.
+pub async fn watch_ip_sources(client: Client, changed: Arc<Notify>) {
+    let api: Api<ClusterExternalIPSource> = Api::all(client);
+    let mut events = watcher(api, watcher::Config::default())
+        .default_backoff()
+        .boxed();
+    // The initial listing only reflects the state the manager already knows about
+    let mut initial_list_done = false;
+    while let Some(event) = events.next().await {
+        match event {
+            Ok(watcher::Event::Apply(ceips) | watcher::Event::Delete(ceips)) => {
+                debug!(
+                    msg = "ClusterExternalIPSource changed",
+                    name = ceips.metadata.name
+                );
+                changed.notify_one();
+            }
+            Ok(watcher::Event::InitDone) => {
+                // Changes may have been missed while the watch was being restarted
+                if initial_list_done {
+                    changed.notify_one();
+                }
+                initial_list_done = true;
+            }
+            Ok(watcher::Event::Init | watcher::Event::InitApply(_)) => (),
+            Err(e) => {
+                warn!(msg = "failed to watch ClusterExternalIPSources", err = %e);
+            }
+        }
+    }
+}